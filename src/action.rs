@@ -1,7 +1,12 @@
-use crate::app::{AppState, Focus};
+use crate::{
+    app::{AppState, Focus},
+    git::RegexpEngine,
+    settings::{ArgQuoting, Settings, SortOrder},
+};
 
 #[derive(Debug, Clone)]
 pub enum Action {
+    Batch(Vec<Action>),
     Quit,
     ToggleLegend,
     InitLegend {
@@ -9,23 +14,98 @@ pub enum Action {
         label_show: String,
         label_hide: String,
     },
+    InitSettings(Box<Settings>),
     SetFocus(Focus),
+    FocusNext,
+    FocusPrev,
     CursorUp,
     CursorDown,
+    CursorUpBy(usize),
+    CursorDownBy(usize),
     CursorLeft,
     CursorRight,
+    ScrollLineUp,
+    ScrollLineDown,
+    ScrollPageUp,
+    ScrollPageDown,
+    Recenter,
+    NextMatch,
+    PrevMatch,
     ToggleExpansion,
     ToggleAllExpansion,
+    StartSelection,
+    YankSelection,
+    ToggleShowWhitespace,
+    ToggleFileBreaks,
+    ToggleArgQuoting,
+    ToggleFoldBlankContext,
+    ToggleMergeContext,
+    ToggleStickyHeading,
+    ToggleBasenameOnly,
+    DenseMode,
+    ToggleContextOnlyFiles,
+    ToggleHeadingFormat,
+    SwapPatternAnd,
+    ToggleSecondaryMode,
+    EscapePattern,
+    RefineWithWordUnderCursor,
+    SearchWholeLine,
+    FindSymbol,
+    SwapPrevious,
+    YankCodeReference,
+    OpenInBrowser,
+    YankAsMarkdown {
+        context: bool,
+    },
+    YankRawGitOutput {
+        command: bool,
+    },
+    YankSummary,
+    YankDisplayedCommand,
+    YankCliArgs,
+    JumpToFileNumber,
+    JumpToHottestFile,
+    ToggleDirectorySummary,
+    SetExtensionFilter,
+    SetPathspecFile,
+    RestoreBreadcrumb,
+    ToggleRevisionComparison,
+    ToggleGitStatus,
+    ToggleLazyContext,
+    ActivateFile,
+    ToggleMinimap,
+    ApplyTemplate {
+        name: String,
+    },
+    PreviewReplace,
+    ClearExtensionFilter,
+    ClearVisited,
+    ShowWorktreeDiffForLine,
+    ShowFileCommit,
+    ExpandFullFile,
+    SearchSelectionFromPreview,
     FlipWholeWord,
     FlipIgnoreCase,
     FlipUntracked,
     FlipNoIndex,
     FlipNoRecursive,
+    FlipTextconv,
     FlipExtendedRegexp,
     FlipFixedStrings,
     FlipPerlRegexp,
+    UseEngine(RegexpEngine),
+    ResetFlags,
+    Refresh,
+    ToggleTermHighlight,
+    ToggleFilenameSearch,
+    ToggleAddedLinesOnly,
+    ToggleExcludeTests,
+    ToggleFlagStatus,
+    ToggleSortOrder,
     DecreaseContext,
     IncreaseContext,
+    DecreaseMinMatchLen,
+    IncreaseMinMatchLen,
     DeleteChar,
     DeleteBackward,
     InsertChar,
@@ -36,6 +116,8 @@ pub enum Action {
     DeleteToEnd,
     AcceptInput,
     ExecuteCommand(mame::command::ExternalCommand),
+    ShowRawOutput,
+    OpenMatchesInPager,
 }
 
 impl Action {
@@ -46,30 +128,169 @@ impl Action {
             Action::FlipUntracked => state.grep.untracked,
             Action::FlipNoIndex => state.grep.no_index,
             Action::FlipNoRecursive => state.grep.no_recursive,
+            Action::FlipTextconv => state.grep.textconv,
             Action::FlipWholeWord => state.grep.word_regexp,
             Action::FlipFixedStrings => state.grep.fixed_strings,
             Action::FlipExtendedRegexp => state.grep.extended_regexp,
             Action::FlipPerlRegexp => state.grep.perl_regexp,
+            Action::UseEngine(engine) => state.grep.engine() == *engine,
+            Action::ToggleTermHighlight => state.grep.highlight_terms,
+            Action::ToggleFilenameSearch => state.grep.filename_search,
+            Action::ToggleAddedLinesOnly => state.grep.added_lines_only,
+            Action::ToggleSecondaryMode => state.grep.or_secondary,
+            Action::ToggleRevisionComparison => state.compare_revision,
+            Action::ToggleGitStatus => state.show_git_status,
+            Action::ToggleLazyContext => state.grep.lazy_context,
+            Action::ToggleFlagStatus => state.settings.show_flag_status,
+            Action::ToggleSortOrder => state.settings.sort_order != SortOrder::Path,
+            Action::ToggleExcludeTests => {
+                state.grep.is_excluding(&state.settings.test_exclude_globs)
+            }
+            Action::SetExtensionFilter => !state.grep.extensions().is_empty(),
+            Action::SetPathspecFile => state.grep.pathspec_file.is_some(),
+
+            // Visual selection mode
+            Action::StartSelection => state.selection_anchor.is_some(),
+
+            // View settings
+            Action::ToggleShowWhitespace => state.settings.show_whitespace,
+            Action::ToggleFileBreaks => state.settings.file_breaks,
+            Action::ToggleArgQuoting => state.settings.arg_quoting != ArgQuoting::Auto,
+            Action::ToggleFoldBlankContext => state.settings.fold_blank_context,
+            Action::ToggleMergeContext => state.settings.merge_context,
+            Action::ToggleStickyHeading => state.settings.sticky_file_heading,
+            Action::ToggleBasenameOnly => state.settings.basename_only,
+            Action::DenseMode => state.dense_mode,
+            Action::ToggleMinimap => state.show_minimap,
+            Action::ToggleContextOnlyFiles => state.hide_context_only_files,
+            Action::ToggleDirectorySummary => state.directory_summary.is_some(),
+            Action::ToggleHeadingFormat => state.inline_format,
+            Action::ExpandFullFile => state.is_cursor_file_expanded(),
+
+            // Results that may be out of date
+            Action::Refresh => state.stale,
 
             // All other actions don't represent toggleable flags
             _ => false,
         }
     }
 
+    // Whether the action switches something on and off (as reported by `is_flag_set()`),
+    // as opposed to the actions that only use `is_flag_set()` to highlight a related state.
+    pub fn is_toggle(&self) -> bool {
+        matches!(
+            self,
+            Action::FlipIgnoreCase
+                | Action::FlipUntracked
+                | Action::FlipNoIndex
+                | Action::FlipNoRecursive
+                | Action::FlipTextconv
+                | Action::FlipWholeWord
+                | Action::FlipFixedStrings
+                | Action::FlipExtendedRegexp
+                | Action::FlipPerlRegexp
+                | Action::UseEngine(_)
+                | Action::ToggleTermHighlight
+                | Action::ToggleFilenameSearch
+                | Action::ToggleAddedLinesOnly
+                | Action::ToggleSecondaryMode
+                | Action::ToggleRevisionComparison
+                | Action::ToggleGitStatus
+                | Action::ToggleLazyContext
+                | Action::ToggleFlagStatus
+                | Action::ToggleSortOrder
+                | Action::ToggleExcludeTests
+                | Action::ToggleShowWhitespace
+                | Action::ToggleFileBreaks
+                | Action::ToggleArgQuoting
+                | Action::ToggleFoldBlankContext
+                | Action::ToggleMergeContext
+                | Action::ToggleStickyHeading
+                | Action::ToggleBasenameOnly
+                | Action::DenseMode
+                | Action::ToggleMinimap
+                | Action::ToggleContextOnlyFiles
+                | Action::ToggleDirectorySummary
+                | Action::ToggleHeadingFormat
+        )
+    }
+
     pub fn is_applicable(&self, state: &AppState) -> bool {
         match self {
             // Always applicable actions
-            Action::Quit
+            Action::Batch(_)
+            | Action::Quit
             | Action::ToggleLegend
             | Action::InitLegend { .. }
+            | Action::InitSettings(_)
             | Action::SetFocus(_)
             | Action::FlipIgnoreCase
             | Action::FlipUntracked
             | Action::FlipNoIndex
             | Action::FlipNoRecursive
+            | Action::FlipTextconv
             | Action::FlipWholeWord
+            | Action::ResetFlags
+            | Action::Refresh
+            | Action::UseEngine(_)
+            | Action::ToggleTermHighlight
+            | Action::ToggleFilenameSearch
+            | Action::ToggleAddedLinesOnly
+            | Action::ToggleSecondaryMode
+            | Action::ToggleFlagStatus
+            | Action::ToggleSortOrder
+            | Action::ToggleShowWhitespace
+            | Action::ToggleFileBreaks
+            | Action::ToggleArgQuoting
+            | Action::ToggleFoldBlankContext
+            | Action::ToggleMergeContext
+            | Action::ToggleStickyHeading
+            | Action::ToggleBasenameOnly
+            | Action::DenseMode
+            | Action::ToggleMinimap
+            | Action::ToggleContextOnlyFiles
+            | Action::ToggleHeadingFormat
+            | Action::YankSummary
+            | Action::YankDisplayedCommand
+            | Action::YankCliArgs
             | Action::ExecuteCommand(_) => true,
 
+            // Query rewriting actions
+            Action::ToggleExcludeTests => !state.settings.test_exclude_globs.is_empty(),
+            Action::SwapPatternAnd => !state.grep.and_pattern.is_empty(),
+            Action::EscapePattern => {
+                state.grep.engine() != RegexpEngine::FixedStrings
+                    && matches!(
+                        state.focus,
+                        Focus::SearchResult
+                            | Focus::Pattern
+                            | Focus::AndPattern
+                            | Focus::NotPattern
+                    )
+            }
+            Action::SwapPrevious => state.previous_grep.is_some(),
+            Action::SetExtensionFilter | Action::SetPathspecFile => !state.grep.filename_search,
+            Action::ClearExtensionFilter => !state.grep.extensions().is_empty(),
+            Action::ClearVisited => state.settings.dim_visited && !state.visited.is_empty(),
+
+            // External program actions
+            Action::OpenMatchesInPager => {
+                !state.search_result.is_empty() && state.grep.revision.is_empty()
+            }
+
+            // Command output actions
+            Action::SearchSelectionFromPreview => state
+                .command_output
+                .as_ref()
+                .is_some_and(|output| output.selected_word().is_some()),
+
+            // Debug actions
+            Action::ShowRawOutput => !state.grep.pattern.is_empty(),
+            Action::ShowWorktreeDiffForLine => {
+                state.cursor.is_line_level() && !state.grep.revision.is_empty()
+            }
+            Action::ShowFileCommit => state.cursor.file.is_some() && !state.grep.no_index,
+
             // Actions that depend on current focus
             Action::AcceptInput
             | Action::InsertChar
@@ -82,18 +303,53 @@ impl Action {
             | Action::MoveBackward => state.focus.is_editing(),
 
             // Navigation actions that depend on search results
-            Action::CursorUp => state.can_cursor_up(),
-            Action::CursorDown => state.can_cursor_down(),
+            Action::CursorUp | Action::CursorUpBy(_) => state.can_cursor_up(),
+            Action::CursorDown | Action::CursorDownBy(_) => state.can_cursor_down(),
             Action::CursorLeft => state.cursor.is_line_level(),
             Action::CursorRight => state.cursor.is_file_level(),
+            Action::ScrollLineUp
+            | Action::ScrollLineDown
+            | Action::ScrollPageUp
+            | Action::ScrollPageDown => !state.search_result.is_empty(),
+            Action::Recenter => state.scroll_offset.is_some(),
+            Action::JumpToFileNumber => !state.search_result.is_empty(),
+            Action::FocusNext | Action::FocusPrev => state.focus != Focus::Prompt,
+            Action::ToggleRevisionComparison => !state.grep.revision.is_empty(),
+            Action::ToggleGitStatus => state.grep.revision.is_empty() && !state.grep.no_index,
+            Action::ToggleLazyContext => !state.grep.pattern.is_empty(),
+            Action::ActivateFile => state.cursor.file.is_some() && !state.cursor.is_line_level(),
+            Action::ApplyTemplate { name } => state.settings.pattern_templates.contains_key(name),
+            Action::PreviewReplace => !state.search_result.is_empty(),
+            Action::RestoreBreadcrumb => {
+                state.settings.show_breadcrumb && !state.breadcrumb.is_empty()
+            }
+            Action::JumpToHottestFile => state.shown_files().nth(1).is_some(),
+            Action::ToggleDirectorySummary => !state.search_result.is_empty(),
+            Action::NextMatch | Action::PrevMatch => state.cursor.is_line_level(),
+            Action::RefineWithWordUnderCursor => state.word_under_cursor().is_some(),
+            Action::SearchWholeLine => state.cursor.is_line_level(),
+            Action::FindSymbol => state.symbol_under_cursor().is_some(),
 
             // Toggle actions that depend on cursor position
-            Action::ToggleExpansion => state.cursor.is_file_level(),
+            Action::ToggleExpansion => state.cursor.file.is_some(),
             Action::ToggleAllExpansion => !state.search_result.is_empty(),
+            Action::ExpandFullFile => state.cursor.file.is_some(),
+
+            // Selection actions
+            Action::StartSelection => state.cursor.is_line_level(),
+            Action::YankSelection => state.selected_range().is_some(),
+            Action::YankCodeReference => state.cursor.is_line_level(),
+            Action::OpenInBrowser => state.cursor.is_line_level(),
+            Action::YankAsMarkdown { .. } => !state.search_result.is_empty(),
+            Action::YankRawGitOutput { .. } => !state.grep.pattern.is_empty(),
 
             // Context actions that depend on line level
-            Action::IncreaseContext => state.cursor.is_line_level(),
-            Action::DecreaseContext => state.cursor.is_line_level(),
+            Action::IncreaseContext => state.can_increase_context(),
+            Action::DecreaseContext => state.can_decrease_context(),
+
+            // Render-time filter actions
+            Action::IncreaseMinMatchLen => !state.search_result.is_empty(),
+            Action::DecreaseMinMatchLen => state.min_match_len > 0,
 
             // Regex flag actions with mutual exclusions
             Action::FlipFixedStrings => !(state.grep.perl_regexp || state.grep.extended_regexp),
@@ -112,6 +368,9 @@ impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for Action {
         let ty = value.to_member("type")?.required()?;
 
         match ty.to_unquoted_string_str()?.as_ref() {
+            "batch" => Ok(Self::Batch(
+                value.to_member("actions")?.required()?.try_into()?,
+            )),
             "quit" => Ok(Self::Quit),
             "toggle-legend" => Ok(Self::ToggleLegend),
             "init-legend" => {
@@ -128,6 +387,7 @@ impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for Action {
                     label_hide,
                 })
             }
+            "init-settings" => Ok(Self::InitSettings(Box::new(value.try_into()?))),
             "set-focus" => {
                 let focus_str = value.to_member("focus")?.required()?;
                 let focus = match focus_str.to_unquoted_string_str()?.as_ref() {
@@ -143,20 +403,106 @@ impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for Action {
             }
             "cursor-up" => Ok(Self::CursorUp),
             "cursor-down" => Ok(Self::CursorDown),
+            "cursor-up-by" => Ok(Self::CursorUpBy(parse_count(value)?)),
+            "cursor-down-by" => Ok(Self::CursorDownBy(parse_count(value)?)),
             "cursor-left" => Ok(Self::CursorLeft),
             "cursor-right" => Ok(Self::CursorRight),
+            "scroll-line-up" => Ok(Self::ScrollLineUp),
+            "scroll-line-down" => Ok(Self::ScrollLineDown),
+            "scroll-page-up" => Ok(Self::ScrollPageUp),
+            "scroll-page-down" => Ok(Self::ScrollPageDown),
+            "recenter" => Ok(Self::Recenter),
+            "next-match" => Ok(Self::NextMatch),
+            "prev-match" => Ok(Self::PrevMatch),
             "toggle-expansion" => Ok(Self::ToggleExpansion),
             "toggle-all-expansion" => Ok(Self::ToggleAllExpansion),
+            "start-selection" => Ok(Self::StartSelection),
+            "yank-selection" => Ok(Self::YankSelection),
+            "toggle-show-whitespace" => Ok(Self::ToggleShowWhitespace),
+            "toggle-file-breaks" => Ok(Self::ToggleFileBreaks),
+            "toggle-arg-quoting" => Ok(Self::ToggleArgQuoting),
+            "toggle-fold-blank-context" => Ok(Self::ToggleFoldBlankContext),
+            "toggle-merge-context" => Ok(Self::ToggleMergeContext),
+            "toggle-sticky-heading" => Ok(Self::ToggleStickyHeading),
+            "dense-mode" => Ok(Self::DenseMode),
+            "toggle-context-only-files" => Ok(Self::ToggleContextOnlyFiles),
+            "toggle-heading-format" => Ok(Self::ToggleHeadingFormat),
+            "expand-full-file" => Ok(Self::ExpandFullFile),
+            "toggle-basename-only" => Ok(Self::ToggleBasenameOnly),
+            "swap-pattern-and" => Ok(Self::SwapPatternAnd),
+            "toggle-secondary-mode" => Ok(Self::ToggleSecondaryMode),
+            "escape-pattern" => Ok(Self::EscapePattern),
+            "refine-with-word-under-cursor" => Ok(Self::RefineWithWordUnderCursor),
+            "search-whole-line" => Ok(Self::SearchWholeLine),
+            "find-symbol" => Ok(Self::FindSymbol),
+            "swap-previous" => Ok(Self::SwapPrevious),
+            "yank-code-reference" => Ok(Self::YankCodeReference),
+            "open-in-browser" => Ok(Self::OpenInBrowser),
+            "yank-as-markdown" => {
+                let context = value
+                    .to_member("context")?
+                    .map(bool::try_from)?
+                    .unwrap_or_default();
+                Ok(Self::YankAsMarkdown { context })
+            }
+            "yank-raw-git-output" => {
+                let command = value
+                    .to_member("command")?
+                    .map(bool::try_from)?
+                    .unwrap_or_default();
+                Ok(Self::YankRawGitOutput { command })
+            }
+            "yank-summary" => Ok(Self::YankSummary),
+            "yank-displayed-command" => Ok(Self::YankDisplayedCommand),
+            "yank-cli-args" => Ok(Self::YankCliArgs),
+            "jump-to-file-number" => Ok(Self::JumpToFileNumber),
+            "jump-to-hottest-file" => Ok(Self::JumpToHottestFile),
+            "toggle-directory-summary" => Ok(Self::ToggleDirectorySummary),
+            "show-worktree-diff-for-line" => Ok(Self::ShowWorktreeDiffForLine),
+            "show-file-commit" => Ok(Self::ShowFileCommit),
+            "search-selection-from-preview" => Ok(Self::SearchSelectionFromPreview),
             "flip-whole-word" => Ok(Self::FlipWholeWord),
             "flip-ignore-case" => Ok(Self::FlipIgnoreCase),
             "flip-untracked" => Ok(Self::FlipUntracked),
             "flip-no-index" => Ok(Self::FlipNoIndex),
             "flip-no-recursive" => Ok(Self::FlipNoRecursive),
+            "flip-textconv" => Ok(Self::FlipTextconv),
             "flip-extended-regexp" => Ok(Self::FlipExtendedRegexp),
             "flip-fixed-strings" => Ok(Self::FlipFixedStrings),
             "flip-perl-regexp" => Ok(Self::FlipPerlRegexp),
+            "use-basic-regexp" => Ok(Self::UseEngine(RegexpEngine::Basic)),
+            "use-extended-regexp" => Ok(Self::UseEngine(RegexpEngine::Extended)),
+            "use-pcre" => Ok(Self::UseEngine(RegexpEngine::Perl)),
+            "use-fixed-strings" => Ok(Self::UseEngine(RegexpEngine::FixedStrings)),
+            "reset-flags" => Ok(Self::ResetFlags),
+            "refresh" => Ok(Self::Refresh),
+            "toggle-term-highlight" => Ok(Self::ToggleTermHighlight),
+            "toggle-filename-search" => Ok(Self::ToggleFilenameSearch),
+            "toggle-added-lines-only" => Ok(Self::ToggleAddedLinesOnly),
+            "toggle-exclude-tests" => Ok(Self::ToggleExcludeTests),
+            "set-extension-filter" => Ok(Self::SetExtensionFilter),
+            "set-pathspec-file" => Ok(Self::SetPathspecFile),
+            "restore-breadcrumb" => Ok(Self::RestoreBreadcrumb),
+            "toggle-revision-comparison" => Ok(Self::ToggleRevisionComparison),
+            "toggle-git-status" => Ok(Self::ToggleGitStatus),
+            "toggle-lazy-context" => Ok(Self::ToggleLazyContext),
+            "activate-file" => Ok(Self::ActivateFile),
+            "toggle-minimap" => Ok(Self::ToggleMinimap),
+            "apply-template" => {
+                let name = value.to_member("name")?.required()?.try_into()?;
+                Ok(Self::ApplyTemplate { name })
+            }
+            "preview-replace" => Ok(Self::PreviewReplace),
+            "focus-next" => Ok(Self::FocusNext),
+            "focus-prev" => Ok(Self::FocusPrev),
+            "clear-extension-filter" => Ok(Self::ClearExtensionFilter),
+            "clear-visited" => Ok(Self::ClearVisited),
+            "toggle-flag-status" => Ok(Self::ToggleFlagStatus),
+            "toggle-sort-order" => Ok(Self::ToggleSortOrder),
             "decrease-context" => Ok(Self::DecreaseContext),
             "increase-context" => Ok(Self::IncreaseContext),
+            "decrease-min-match-len" => Ok(Self::DecreaseMinMatchLen),
+            "increase-min-match-len" => Ok(Self::IncreaseMinMatchLen),
             "delete-char" => Ok(Self::DeleteChar),
             "delete-backward" => Ok(Self::DeleteBackward),
             "insert-char" => Ok(Self::InsertChar),
@@ -167,7 +513,17 @@ impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for Action {
             "delete-to-end" => Ok(Self::DeleteToEnd),
             "accept-input" => Ok(Self::AcceptInput),
             "execute-command" => Ok(Self::ExecuteCommand(value.try_into()?)),
+            "show-raw-output" => Ok(Self::ShowRawOutput),
+            "open-matches-in-pager" => Ok(Self::OpenMatchesInPager),
             type_name => Err(ty.invalid(format!("unknown action type: {type_name:?}"))),
         }
     }
 }
+
+fn parse_count(value: nojson::RawJsonValue<'_, '_>) -> Result<usize, nojson::JsonParseError> {
+    let count = value.to_member("count")?.required()?;
+    match usize::try_from(count)? {
+        0 => Err(count.invalid("count must be at least 1")),
+        n => Ok(n),
+    }
+}
@@ -1,18 +1,28 @@
 use std::{
-    collections::BTreeSet,
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, VecDeque},
     num::NonZeroUsize,
-    ops::{RangeFrom, RangeTo},
-    path::PathBuf,
+    ops::{Range, RangeFrom, RangeInclusive, RangeTo},
+    os::fd::RawFd,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
 use mame::action::{Binding, BindingConfig, BindingContextName};
 use orfail::OrFail;
 use tuinix::{Terminal, TerminalEvent, TerminalPosition};
+use unicode_segmentation::UnicodeSegmentation;
 
 use crate::{
     action::Action,
     canvas::Canvas,
-    git::{GrepArg, GrepOptions, SearchResult},
+    clipboard::Copied,
+    git::{
+        BackgroundSearch, CommitInfo, ContextLines, FileStatus, GrepArg, GrepArgKind, GrepOptions,
+        Line, PathspecFile, SearchCache, SearchResult,
+    },
+    settings::{FileActivation, PatternTemplate, RefineMode, Settings, SortOrder},
+    watch::{Debounce, FileWatcher},
     widget_command_editor::CommandEditorWidget,
     widget_legend::LegendWidget,
     widget_search_result::{Cursor, SearchResultWidget},
@@ -20,7 +30,8 @@ use crate::{
 
 #[derive(Debug)]
 pub struct App {
-    terminal: Terminal,
+    // `None` only while the TUI is suspended for an external interactive program.
+    terminal: Option<Terminal>,
     config: BindingConfig<Action>,
     context: BindingContextName,
     exit: bool,
@@ -29,6 +40,12 @@ pub struct App {
     command_editor: CommandEditorWidget,
     search_result: SearchResultWidget,
     preview: Option<mame::preview::TextPreview>,
+    // Whether to run the first `execute-command` binding if the initial search has a single hit line.
+    auto_open_single: bool,
+    // Whether to print the `git grep` command on exit.
+    print_command: bool,
+    // Context entered by prompts that are not opened by their own bindings (e.g., the copy confirmation).
+    edit_context: Option<BindingContextName>,
 }
 
 impl App {
@@ -43,7 +60,7 @@ impl App {
             .cloned();
 
         let mut this = Self {
-            terminal: Terminal::new().or_fail()?,
+            terminal: Some(Terminal::new().or_fail()?),
             context: config.initial_context().clone(),
             config,
             exit: false,
@@ -52,8 +69,14 @@ impl App {
             command_editor: CommandEditorWidget::default(),
             search_result: SearchResultWidget::default(),
             preview: None,
+            auto_open_single: false,
+            print_command: true,
+            edit_context: binding_for_editing.as_ref().and_then(|b| b.context.clone()),
         };
 
+        this.state.worktree_dir = crate::git::show_toplevel()
+            .and_then(|dir| crate::git::show_prefix().map(|prefix| dir.join(prefix)))
+            .ok();
         this.state.grep = initial_options;
         if !this.state.grep.pattern.is_empty() {
             this.state.regrep().or_fail()?;
@@ -64,51 +87,97 @@ impl App {
         Ok(this)
     }
 
+    pub fn set_auto_open_single(&mut self, enabled: bool) {
+        self.auto_open_single = enabled;
+    }
+
+    pub fn set_print_command(&mut self, enabled: bool) {
+        self.print_command = enabled;
+    }
+
     pub fn run(mut self) -> orfail::Result<()> {
         if let Some(action) = self.config.setup_action().cloned() {
             self.handle_action(action).or_fail()?;
         }
         self.render().or_fail()?;
+        if self.auto_open_single {
+            self.open_single_hit().or_fail()?;
+        }
 
         while !self.exit {
-            let Some(event) = self.terminal.poll_event(&[], &[], None).or_fail()? else {
+            let fds = self.state.readable_fds();
+            let timeout = self.state.poll_timeout();
+            let terminal = self.terminal.as_mut().or_fail()?;
+            let event = terminal.poll_event(&fds, &[], timeout).or_fail()?;
+            self.refresh_if_due().or_fail()?;
+            let Some(event) = event else {
                 continue;
             };
             self.handle_event(event).or_fail()?;
         }
 
-        std::mem::drop(self.terminal);
+        std::mem::drop(self.terminal.take());
 
-        print!("git");
-        for arg in self.state.grep.args(Focus::default()) {
-            print!(" {}", arg.quoted_text());
+        if let Some(command) = exit_command_line(&self.state.grep, self.print_command) {
+            println!("{command}");
+        }
+
+        Ok(())
+    }
+
+    // Only the result of the initial search (i.e., a query given on the command line) is considered.
+    fn open_single_hit(&mut self) -> orfail::Result<()> {
+        if self.state.current_grep.is_none() {
+            return Ok(());
         }
-        println!();
+        let Some((file, line_number)) = self.state.single_hit_line() else {
+            return Ok(());
+        };
+        self.state.cursor.file = Some(file.clone());
+        self.state.cursor.line_number = Some(line_number);
+        self.state.cursor.match_index = None;
 
+        let command = self
+            .config
+            .all_bindings()
+            .flat_map(|(_, bindings)| bindings.iter())
+            .find_map(|b| match &b.action {
+                Some(Action::ExecuteCommand(command)) => Some(command.clone()),
+                _ => None,
+            });
+        if let Some(command) = command {
+            self.execute_command(&command).or_fail()?;
+        } else {
+            self.render().or_fail()?;
+        }
         Ok(())
     }
 
     fn render(&mut self) -> orfail::Result<()> {
-        if self.terminal.size().is_empty() {
+        self.state.update_file_window();
+        let terminal = self.terminal.as_mut().or_fail()?;
+        if terminal.size().is_empty() {
             return Ok(());
         }
 
-        self.command_editor
-            .set_available_cols(self.legend.remaining_cols(
-                self.terminal.size(),
-                self.config.get_bindings(&self.context).or_fail()?,
-                &self.state,
-            ));
+        let available_cols = self.legend.remaining_cols(
+            terminal.size(),
+            self.config.get_bindings(&self.context).or_fail()?,
+            &self.state,
+        );
+        self.command_editor.set_available_cols(available_cols);
 
-        let mut canvas = Canvas::new(self.terminal.size());
+        let mut canvas = Canvas::new(terminal.size());
         self.command_editor.render(&self.state, &mut canvas);
         canvas.newline();
         self.search_result.render(&self.state, &mut canvas);
 
         self.command_editor.update_cursor_position(&mut self.state);
-        self.terminal.set_cursor(self.state.show_terminal_cursor);
+        terminal.set_cursor(self.state.show_terminal_cursor);
 
-        let mut frame = canvas.into_frame().into_terminal_frame();
+        let frame = canvas.into_frame();
+        let links = frame.links();
+        let mut frame = frame.into_terminal_frame();
         if let Some(preview) = &mut self.preview {
             preview.render(&mut frame).or_fail()?;
         }
@@ -119,15 +188,70 @@ impl App {
                 &self.state,
             )
             .or_fail()?;
-        self.terminal.draw(frame).or_fail()?;
+        terminal.draw(frame).or_fail()?;
+
+        // Links under the legend or the preview are skipped so as not to overwrite them.
+        if self.preview.is_none() {
+            let links = links
+                .into_iter()
+                .filter(|(position, token)| position.col + token.cols() <= available_cols);
+            write_hyperlinks(links).or_fail()?;
+        }
 
         Ok(())
     }
 
     fn handle_action(&mut self, action: Action) -> orfail::Result<()> {
+        // Editing the text of the command does not change the result until it is run.
+        let is_text_editing = self.state.focus.is_editing()
+            && matches!(
+                action,
+                Action::InsertChar
+                    | Action::DeleteBackward
+                    | Action::DeleteChar
+                    | Action::DeleteToEnd
+                    | Action::MoveBackward
+                    | Action::MoveForward
+                    | Action::MoveToStart
+                    | Action::MoveToEnd
+                    | Action::CursorLeft
+                    | Action::CursorRight
+            );
+        // Moving the cursor or scrolling only changes the focused line and its context (see `SearchResultWidget`).
+        let is_view_only = matches!(
+            action,
+            Action::CursorUp
+                | Action::CursorDown
+                | Action::CursorUpBy(_)
+                | Action::CursorDownBy(_)
+                | Action::CursorLeft
+                | Action::CursorRight
+                | Action::ScrollLineUp
+                | Action::ScrollLineDown
+                | Action::ScrollPageUp
+                | Action::ScrollPageDown
+                | Action::Recenter
+                | Action::NextMatch
+                | Action::PrevMatch
+        );
+        if !is_text_editing && !is_view_only {
+            self.state.view_generation += 1;
+        }
+
         match action {
+            Action::Batch(actions) => {
+                for action in actions {
+                    self.handle_action(action).or_fail()?;
+                }
+            }
             Action::Quit => {
-                self.exit = true;
+                if self.state.settings.confirm_quit_while_editing
+                    && self.command_editor.has_unsaved_edit(&self.state)
+                {
+                    self.state.confirming_quit = true;
+                } else {
+                    self.exit = true;
+                }
             }
             Action::ToggleLegend => {
                 self.legend.hide = !self.legend.hide;
@@ -141,9 +265,194 @@ impl App {
                 self.legend.label_hide = label_hide;
                 self.legend.hide = hide;
             }
+            Action::InitSettings(settings) => {
+                let old_grep = self.state.grep.clone();
+                self.state.grep.grep_command = settings.grep_command.clone();
+                self.state.grep.ascii_only_ignore_case = settings.ascii_only_ignore_case;
+                self.state.settings = *settings;
+                if !old_grep.is_same_query(&self.state.grep) && self.state.current_grep.is_some() {
+                    self.state.regrep().or_fail()?;
+                }
+                // The initial search may have been done before the settings were loaded.
+                self.state.apply_auto_expansion();
+                self.state.apply_sort_order();
+                if let Some(delay) = self.state.settings.auto_refresh_delay
+                    && self.state.watcher.is_none()
+                {
+                    // A failure only disables the auto-refresh, but it is reported so that it is not silently ignored.
+                    let message = match FileWatcher::new(crate::git::tracked_dirs().or_fail()?) {
+                        Ok(Some(watcher)) => {
+                            self.state.watcher = Some((watcher, Debounce::new(delay)));
+                            None
+                        }
+                        Ok(None) => Some("not supported on this platform".to_owned()),
+                        Err(e) => Some(e.message),
+                    };
+                    if let Some(message) = message {
+                        let text = format!("(auto-refresh is disabled: {message})");
+                        let pane = mame::preview::TextPreviewPane::new("auto-refresh", &text);
+                        self.preview = Some(mame::preview::TextPreview::new(Some(pane), None));
+                    }
+                }
+                if self.state.settings.auto_run_when_ready && self.state.current_grep.is_none() {
+                    self.auto_run().or_fail()?;
+                }
+            }
             Action::ExecuteCommand(command) => {
                 self.execute_command(&command).or_fail()?;
             }
+            Action::ShowRawOutput => {
+                self.show_raw_output();
+            }
+            Action::AcceptInput if self.state.focus == Focus::Prompt => {
+                self.accept_prompt().or_fail()?;
+            }
+            Action::JumpToFileNumber => {
+                self.open_prompt(PromptKind::FileNumber);
+            }
+            Action::SetExtensionFilter => {
+                self.open_prompt(PromptKind::Extensions);
+            }
+            Action::SetPathspecFile => {
+                self.open_prompt(PromptKind::PathspecFile);
+            }
+            Action::FocusNext | Action::FocusPrev => {
+                let focus = if matches!(action, Action::FocusNext) {
+                    self.state.focus.next()
+                } else {
+                    self.state.focus.prev()
+                };
+                self.state.focus = focus;
+                self.command_editor.handle_focus_change(&mut self.state);
+                if focus == Focus::SearchResult {
+                    self.context = self.config.initial_context().clone();
+                }
+            }
+            Action::ToggleRevisionComparison => {
+                self.state.toggle_revision_comparison();
+            }
+            Action::ToggleGitStatus => {
+                self.state.toggle_git_status();
+            }
+            Action::ActivateFile => {
+                if let Some((path, line_number)) = self.state.activate_file() {
+                    self.open_in_editor(&path, line_number).or_fail()?;
+                }
+            }
+            Action::ToggleLazyContext => {
+                self.state.grep.lazy_context = !self.state.grep.lazy_context;
+                self.state.regrep().or_fail()?;
+            }
+            Action::ApplyTemplate { name } => {
+                if let Some(template) = self.state.settings.pattern_templates.get(&name) {
+                    self.continue_template(template.clone(), BTreeMap::new())
+                        .or_fail()?;
+                }
+            }
+            Action::RestoreBreadcrumb => {
+                self.open_prompt(PromptKind::Breadcrumb);
+            }
+            Action::PreviewReplace => {
+                self.open_prompt(PromptKind::Replacement);
+            }
+            Action::SwapPatternAnd => {
+                self.state.grep.swap_pattern_and();
+                if matches!(self.state.focus, Focus::Pattern | Focus::AndPattern) {
+                    self.command_editor.handle_focus_change(&mut self.state);
+                }
+                self.state.regrep().or_fail()?;
+            }
+            Action::ToggleSecondaryMode => {
+                self.state.grep.or_secondary = !self.state.grep.or_secondary;
+                self.state.regrep().or_fail()?;
+            }
+            Action::SearchWholeLine => {
+                if self.state.search_whole_line() {
+                    self.state.regrep().or_fail()?;
+                }
+            }
+            Action::FindSymbol => {
+                if self.state.find_symbol() {
+                    self.state.regrep().or_fail()?;
+                }
+            }
+            Action::RefineWithWordUnderCursor => {
+                if self.state.refine_with_word_under_cursor() {
+                    self.state.regrep().or_fail()?;
+                }
+            }
+            Action::EscapePattern => {
+                self.state.grep.escape_pattern(self.state.focus);
+                if self.state.focus.is_editing() {
+                    self.command_editor.handle_focus_change(&mut self.state);
+                }
+                self.state.regrep().or_fail()?;
+            }
+            Action::YankCodeReference => {
+                if let Some(reference) = self.state.cursor_code_reference() {
+                    self.yank(reference).or_fail()?;
+                }
+            }
+            Action::YankAsMarkdown { context } => {
+                let files = self.state.shown_files().map(|(file, lines)| {
+                    let lines = lines
+                        .iter()
+                        .filter(|line| context || self.state.is_hit_shown(file, line))
+                        .collect();
+                    (file.as_path(), lines)
+                });
+                let text = crate::export::markdown(files, crate::export::MAX_MARKDOWN_BYTES);
+                self.yank(text).or_fail()?;
+            }
+            Action::YankRawGitOutput { command } => {
+                // The output is taken as is, so the render-time filters are not applied.
+                let output = self.state.grep.call_raw().or_fail()?;
+                let text = raw_output_text(
+                    command.then(|| self.state.grep.parsing_command_line()),
+                    &output.stdout,
+                );
+                self.yank(text).or_fail()?;
+            }
+            Action::YankSummary => {
+                self.yank(self.state.summary_text()).or_fail()?;
+            }
+            Action::YankCliArgs => {
+                self.yank(self.state.grep.cli_command_line()).or_fail()?;
+            }
+            Action::YankDisplayedCommand => {
+                let command = self.command_editor.displayed_command(&self.state);
+                self.yank(command).or_fail()?;
+            }
+            Action::ShowFileCommit => {
+                self.show_file_commit();
+            }
+            Action::OpenInBrowser => {
+                self.open_in_browser();
+            }
+            Action::ShowWorktreeDiffForLine => {
+                self.show_worktree_diff_for_line();
+            }
+            Action::ExpandFullFile => {
+                self.expand_full_file();
+            }
+            Action::SearchSelectionFromPreview => {
+                self.preview = None;
+                if let Some(output) = self.state.command_output.take()
+                    && let Some(word) = output.selected_word()
+                {
+                    self.state.grep.pattern.text = word.to_owned();
+                    self.state.regrep().or_fail()?;
+                }
+            }
+            Action::OpenMatchesInPager => {
+                self.open_matches_in_pager().or_fail()?;
+            }
+            Action::YankSelection => {
+                if let Some(text) = self.state.selected_text() {
+                    self.yank(text).or_fail()?;
+                }
+                self.state.selection_anchor = None;
+            }
             _ => {
                 let old_focus = self.state.focus;
                 if self.state.focus.is_editing() {
@@ -157,10 +466,17 @@ impl App {
                 }
 
                 if old_focus != self.state.focus {
+                    if self.state.focus != Focus::Prompt {
+                        self.state.prompt = None;
+                    }
                     self.command_editor.handle_focus_change(&mut self.state);
                 }
             }
         }
+        if !is_text_editing {
+            self.state.load_cursor_context();
+            self.state.mark_visited();
+        }
         Ok(())
     }
 
@@ -168,7 +484,23 @@ impl App {
         match event {
             TerminalEvent::Resize(_) => self.render().or_fail(),
             TerminalEvent::Input(input) => {
+                if self.state.command_output.is_some() && self.handle_command_output_input(input)? {
+                    return self.render().or_fail();
+                }
                 self.preview = None;
+                self.state.command_output = None;
+
+                if self.state.confirming_quit {
+                    self.state.confirming_quit = false;
+                    if let tuinix::TerminalInput::Key(tuinix::KeyInput {
+                        code: tuinix::KeyCode::Char('y' | 'Y'),
+                        ..
+                    }) = input
+                    {
+                        self.exit = true;
+                    }
+                    return self.render().or_fail();
+                }
 
                 if let tuinix::TerminalInput::Key(tuinix::KeyInput {
                     code: tuinix::KeyCode::Char(c),
@@ -178,26 +510,452 @@ impl App {
                     self.state.last_input_char = c;
                 }
                 let bindings = self.config.get_bindings(&self.context).or_fail()?;
-                if let Some(binding) = bindings.iter().find(|b| b.matches(input)).cloned() {
+                if let Some(binding) = find_binding(bindings, input, &self.state).cloned() {
                     self.handle_binding(binding).or_fail()?;
                     self.render().or_fail()?;
                 }
                 Ok(())
             }
-            TerminalEvent::FdReady { .. } => Err(orfail::Failure::new("bug")),
+            TerminalEvent::FdReady { fd, .. } => {
+                if self.state.handle_fd_ready(fd).or_fail()? {
+                    self.render().or_fail()?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Moves the cursor in the command output preview, keeping the preview open.
+    fn handle_command_output_input(
+        &mut self,
+        input: tuinix::TerminalInput,
+    ) -> orfail::Result<bool> {
+        let bindings = self.config.get_bindings(&self.context).or_fail()?;
+        let Some(action) =
+            find_binding(bindings, input, &self.state).and_then(|b| b.action.clone())
+        else {
+            return Ok(false);
+        };
+        let output = self.state.command_output.as_mut().or_fail()?;
+        match action {
+            Action::CursorUp => output.cursor_up(),
+            Action::CursorDown => output.cursor_down(),
+            Action::CursorLeft => output.cursor_left(),
+            Action::CursorRight => output.cursor_right(),
+            _ => return Ok(false),
         }
+        self.preview = Some(output.preview());
+        Ok(true)
     }
 
     fn handle_binding(&mut self, binding: Binding<Action>) -> orfail::Result<()> {
+        let was_prompting = self.state.focus == Focus::Prompt;
         if let Some(action) = binding.action {
             self.handle_action(action).or_fail()?;
         }
-        if let Some(context) = binding.context {
+        // A prompt opened by accepting another one (i.e., the next placeholder of a template) stays in the editing context.
+        if let Some(context) = binding.context
+            && !(was_prompting && self.state.focus == Focus::Prompt)
+        {
             self.context = context;
         }
         Ok(())
     }
 
+    fn refresh_if_due(&mut self) -> orfail::Result<()> {
+        let Some((_, debounce)) = &mut self.state.watcher else {
+            return Ok(());
+        };
+        if !debounce.take_due(Instant::now()) || self.state.current_grep.is_none() {
+            return Ok(());
+        }
+        self.state.result_cache.clear();
+
+        // Searching with a half-edited query would be surprising.
+        if self.state.focus.is_editing() || self.preview.is_some() {
+            self.state.stale = true;
+            self.render().or_fail()?;
+        } else {
+            // The result is applied when the search finishes, so that the UI keeps responding meanwhile.
+            self.state.start_background_search().or_fail()?;
+        }
+        Ok(())
+    }
+
+    // Runs the initial search that was skipped in `new()` if the and-pattern can stand in for the pattern.
+    fn auto_run(&mut self) -> orfail::Result<()> {
+        self.state.grep.promote_and_pattern();
+        if self.state.grep.pattern.is_empty() {
+            return Ok(());
+        }
+        self.state.regrep().or_fail()?;
+        self.state.focus = Focus::SearchResult;
+        self.context = self.config.initial_context().clone();
+        Ok(())
+    }
+
+    fn open_prompt(&mut self, kind: PromptKind) {
+        self.state.prompt = Some(Prompt {
+            kind,
+            input: GrepArg::new(GrepArgKind::Other),
+        });
+        self.state.focus = Focus::Prompt;
+        self.command_editor.handle_focus_change(&mut self.state);
+    }
+
+    fn accept_prompt(&mut self) -> orfail::Result<()> {
+        let prompt = self.state.prompt.take().or_fail()?;
+        self.state.focus = Focus::SearchResult;
+        match prompt.kind {
+            PromptKind::FileNumber => {
+                // Invalid or out-of-range numbers are just ignored.
+                if let Ok(n) = prompt.input.text.trim().parse() {
+                    self.state.jump_to_file_number(n);
+                }
+            }
+            PromptKind::Extensions => {
+                self.state.grep.set_extensions(&prompt.input.text);
+                self.state.regrep().or_fail()?;
+            }
+            PromptKind::ConfirmCopy { text } => {
+                if prompt.input.text.trim().eq_ignore_ascii_case("y") {
+                    self.copy(&text).or_fail()?;
+                }
+            }
+            PromptKind::PathspecFile => {
+                self.set_pathspec_file(prompt.input.text.trim()).or_fail()?;
+            }
+            PromptKind::Replacement => {
+                self.preview_replace(&prompt.input.text);
+            }
+            PromptKind::TemplateValue {
+                template,
+                mut values,
+            } => {
+                let name = template.placeholders()[values.len()].to_owned();
+                values.insert(name, prompt.input.text);
+                self.continue_template(template, values).or_fail()?;
+            }
+            PromptKind::Breadcrumb => {
+                if let Ok(n) = prompt.input.text.trim().parse()
+                    && self.state.restore_breadcrumb(n)
+                {
+                    self.state.regrep().or_fail()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // An empty path clears the pathspecs.
+    fn set_pathspec_file(&mut self, path: &str) -> orfail::Result<()> {
+        let file = if path.is_empty() {
+            None
+        } else {
+            // There is no room for a flag in the prompt, so NUL-separated lists are detected by their content.
+            let nul = std::fs::read(path).is_ok_and(|bytes| bytes.contains(&0));
+            match PathspecFile::read(path, nul) {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    let text = format!("(failed to read the pathspec file: {})", e.message);
+                    let pane = mame::preview::TextPreviewPane::new("pathspec file", &text);
+                    self.preview = Some(mame::preview::TextPreview::new(Some(pane), None));
+                    return Ok(());
+                }
+            }
+        };
+        self.state.grep.pathspec_file = file;
+        self.state.regrep().or_fail()
+    }
+
+    fn continue_template(
+        &mut self,
+        template: PatternTemplate,
+        values: BTreeMap<String, String>,
+    ) -> orfail::Result<()> {
+        if values.len() < template.placeholders().len() {
+            self.open_prompt(PromptKind::TemplateValue { template, values });
+            return Ok(());
+        }
+        self.state.grep.pattern.text = template.fill(&values, self.state.grep.engine());
+        self.state.regrep().or_fail()
+    }
+
+    fn show_worktree_diff_for_line(&mut self) {
+        let (Some(file), Some(line_number)) =
+            (&self.state.cursor.file, self.state.cursor.line_number)
+        else {
+            return;
+        };
+        let Some(path) = self.state.worktree_path(file) else {
+            return;
+        };
+        let revision = &self.state.grep.revision.text;
+        let context = self.state.grep.context_lines.0.max(2);
+
+        let revision_text = match crate::git::show_file(revision, path) {
+            Ok(text) => line_snippet(&text, line_number, context),
+            Err(_) => format!("(`{path}` does not exist in `{revision}`)"),
+        };
+        let worktree_text = match std::fs::read_to_string(path) {
+            Ok(text) => line_snippet(&text, line_number, context),
+            Err(e) => format!("(`{path}` cannot be read from the working tree: {e})"),
+        };
+
+        let revision_pane =
+            mame::preview::TextPreviewPane::new(&format!("{revision}:{path}"), &revision_text);
+        let worktree_pane =
+            mame::preview::TextPreviewPane::new(&format!("worktree:{path}"), &worktree_text);
+        self.preview = Some(mame::preview::TextPreview::new(
+            Some(revision_pane),
+            Some(worktree_pane),
+        ));
+    }
+
+    // Shows how the matches would be replaced, without changing any files.
+    fn preview_replace(&mut self, replacement: &str) {
+        let files = self.state.shown_files().map(|(file, lines)| {
+            let lines = lines
+                .iter()
+                .filter(|line| line.hit && self.state.is_hit_shown(file, line))
+                .map(|line| {
+                    (
+                        line,
+                        self.state.search_result.hit_ranges_in_line(file, line),
+                    )
+                })
+                .collect();
+            (file.as_path(), lines)
+        });
+        let text = crate::replace::preview(files, replacement);
+        let pane =
+            mame::preview::TextPreviewPane::new(&format!("replace with: {replacement}"), &text);
+        self.preview = Some(mame::preview::TextPreview::new(Some(pane), None));
+    }
+
+    // Every yank goes through here so that huge payloads are confirmed before being emitted.
+    fn yank(&mut self, text: String) -> orfail::Result<()> {
+        if crate::clipboard::needs_confirmation(&text, self.state.settings.clipboard_confirm_bytes)
+        {
+            self.open_prompt(PromptKind::ConfirmCopy { text });
+            if let Some(context) = &self.edit_context {
+                self.context = context.clone();
+            }
+            return Ok(());
+        }
+        self.copy(&text).or_fail()
+    }
+
+    fn copy(&mut self, text: &str) -> orfail::Result<()> {
+        let terminal = self.terminal.as_mut().or_fail()?;
+        if let Copied::File(path) = crate::clipboard::copy(terminal, text).or_fail()? {
+            let text = format!(
+                "(the text is too large for the clipboard, so it was written to {})",
+                path.display()
+            );
+            let pane = mame::preview::TextPreviewPane::new("copy", &text);
+            self.preview = Some(mame::preview::TextPreview::new(Some(pane), None));
+        }
+        Ok(())
+    }
+
+    fn open_in_browser(&mut self) {
+        let result = self
+            .state
+            .cursor_browse_url()
+            .and_then(|url| url.map(|url| open_url(&url)).transpose());
+        if let Err(e) = result {
+            let text = format!("(failed to open the URL: {e})");
+            let pane = mame::preview::TextPreviewPane::new("open in browser", &text);
+            self.preview = Some(mame::preview::TextPreview::new(Some(pane), None));
+        }
+    }
+
+    fn show_file_commit(&mut self) {
+        let Some(file) = self.state.cursor.file.clone() else {
+            return;
+        };
+        let Some(path) = self.state.worktree_path(&file).map(str::to_owned) else {
+            return;
+        };
+        let text = if let Some(commit) = self.state.file_commits.get(&file) {
+            commit_text(commit.as_ref())
+        } else {
+            let revision = (!self.state.grep.revision.is_empty())
+                .then_some(self.state.grep.revision.text.as_str());
+            match crate::git::last_commit(revision, &path) {
+                Ok(commit) => {
+                    let text = commit_text(commit.as_ref());
+                    self.state.file_commits.insert(file, commit);
+                    text
+                }
+                // Failures are not cached as they may be transient.
+                Err(e) => format!("(failed to get the last commit: {e})"),
+            }
+        };
+        let pane = mame::preview::TextPreviewPane::new(&format!("last commit:{path}"), &text);
+        self.preview = Some(mame::preview::TextPreview::new(Some(pane), None));
+    }
+
+    fn expand_full_file(&mut self) {
+        if self.state.is_cursor_file_expanded() {
+            self.state.expanded_file = None;
+            return;
+        }
+        let Some(file) = self.state.cursor.file.clone() else {
+            return;
+        };
+        let Some(path) = self.state.worktree_path(&file) else {
+            return;
+        };
+        let revision = &self.state.grep.revision.text;
+        let text = if revision.is_empty() {
+            std::fs::read_to_string(path).or_fail()
+        } else {
+            crate::git::show_file(revision, path)
+        };
+        let title = file.display().to_string();
+        match text {
+            Ok(text) => {
+                // Too large files are shown in the preview rather than inlined into the result.
+                if !self.state.expand_file(file, &text) {
+                    let pane = mame::preview::TextPreviewPane::new(&title, &text);
+                    self.preview = Some(mame::preview::TextPreview::new(Some(pane), None));
+                }
+            }
+            Err(e) => {
+                let text = format!("(`{title}` cannot be read: {e})");
+                let pane = mame::preview::TextPreviewPane::new(&title, &text);
+                self.preview = Some(mame::preview::TextPreview::new(Some(pane), None));
+            }
+        }
+    }
+
+    fn show_raw_output(&mut self) {
+        if self.state.grep.pattern.is_empty() {
+            return;
+        }
+
+        let mut text = self.state.grep.parsing_command_line();
+        text.push('\n');
+
+        let mut stderr_pane = None;
+        match self.state.grep.call_raw() {
+            Ok(output) => {
+                text.push_str(&String::from_utf8_lossy(&output.stdout));
+                if !output.stderr.is_empty() {
+                    stderr_pane = Some(mame::preview::TextPreviewPane::new(
+                        "stderr",
+                        &String::from_utf8_lossy(&output.stderr),
+                    ));
+                }
+            }
+            Err(e) => {
+                text.push_str(&format!("error: {e}\n"));
+            }
+        }
+
+        let stdout_pane = mame::preview::TextPreviewPane::new("raw output", &text);
+        self.preview = Some(mame::preview::TextPreview::new(
+            Some(stdout_pane),
+            stderr_pane,
+        ));
+    }
+
+    // Only one `Terminal` can exist at a time, so it is dropped while `f` is running.
+    fn suspend_terminal<F, T>(&mut self, f: F) -> orfail::Result<T>
+    where
+        F: FnOnce() -> T,
+    {
+        use std::os::fd::{AsFd, AsRawFd};
+
+        // `Terminal` closes STDIN when dropped, so keep a duplicate to restore it afterwards.
+        let stdin = std::io::stdin().as_fd().try_clone_to_owned().or_fail()?;
+        self.terminal = None;
+        // SAFETY: `stdin` is a valid file descriptor owned by this function.
+        if unsafe { libc::dup2(stdin.as_raw_fd(), libc::STDIN_FILENO) } == -1 {
+            return Err(std::io::Error::last_os_error()).or_fail();
+        }
+
+        let result = f();
+        self.terminal = Some(Terminal::new().or_fail()?);
+        Ok(result)
+    }
+
+    fn open_in_editor(&mut self, path: &str, line_number: NonZeroUsize) -> orfail::Result<()> {
+        let editor = ["VISUAL", "EDITOR"]
+            .into_iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|editor| !editor.trim().is_empty())
+            .unwrap_or_else(|| "vi".to_owned());
+        let mut editor_args = editor.split_whitespace();
+        let program = editor_args.next().or_fail()?;
+
+        // `+N` is understood by most terminal editors (e.g., vi, emacs and nano).
+        let mut command = std::process::Command::new(program);
+        command
+            .args(editor_args)
+            .arg(format!("+{line_number}"))
+            .arg(path);
+        let status = self.suspend_terminal(|| command.status()).or_fail()?;
+
+        let text = match status {
+            Ok(status) if status.success() => {
+                // The file may have been changed.
+                return self.state.refresh().or_fail();
+            }
+            Ok(status) => format!("`{editor}` exited with {status}"),
+            Err(e) => format!("failed to execute `{editor}`: {e}"),
+        };
+        let pane = mame::preview::TextPreviewPane::new("editor", &text);
+        self.preview = Some(mame::preview::TextPreview::new(Some(pane), None));
+        Ok(())
+    }
+
+    fn open_matches_in_pager(&mut self) -> orfail::Result<()> {
+        let pager = std::env::var("PAGER")
+            .ok()
+            .filter(|pager| !pager.trim().is_empty())
+            .unwrap_or_else(|| "less".to_owned());
+        let mut pager_args = pager.split_whitespace();
+        let program = pager_args.next().or_fail()?;
+
+        let mut command = std::process::Command::new(program);
+        command.args(pager_args);
+        let is_less = std::path::Path::new(program)
+            .file_name()
+            .is_some_and(|name| name == "less");
+        if is_less && !self.state.grep.pattern.is_empty() {
+            if self.state.grep.ignore_case {
+                command.arg("-I");
+            }
+            // `++` applies the initial command to every file, not just the first one.
+            command.arg(format!("++/{}", less_search_pattern(&self.state.grep)));
+        }
+        command.args(self.state.search_result.files.keys());
+
+        let status = self.suspend_terminal(|| command.status()).or_fail()?;
+
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => {
+                let pane = mame::preview::TextPreviewPane::new(
+                    "pager",
+                    &format!("`{pager}` exited with {status}"),
+                );
+                self.preview = Some(mame::preview::TextPreview::new(Some(pane), None));
+            }
+            Err(e) => {
+                let pane = mame::preview::TextPreviewPane::new(
+                    "pager",
+                    &format!("failed to execute `{pager}`: {e}"),
+                );
+                self.preview = Some(mame::preview::TextPreview::new(Some(pane), None));
+            }
+        }
+        Ok(())
+    }
+
     fn execute_command(&mut self, command: &mame::command::ExternalCommand) -> orfail::Result<()> {
         let executing_pane = mame::preview::TextPreviewPane::new(
             "executing",
@@ -227,42 +985,474 @@ impl App {
                 .envs
                 .insert("MAMEGREP_LINE".to_owned(), line_number.to_string());
         }
+        if let Some(column) = self.state.cursor_column() {
+            command
+                .envs
+                .insert("MAMEGREP_COLUMN".to_owned(), column.to_string());
+        }
         let output = command.execute().or_fail()?;
 
         // If the command was successful, re-run the grep to refresh results
         if output.status.success() {
-            self.state.regrep().or_fail()?;
+            self.state.refresh().or_fail()?;
+        } else {
+            // The command may have changed some files before failing.
+            self.state.result_cache.clear();
+            self.state.stale = true;
         }
 
-        let stdout_pane =
-            mame::preview::TextPreviewPane::new("stdout", &String::from_utf8_lossy(&output.stdout));
-        let stderr_pane =
-            mame::preview::TextPreviewPane::new("stderr", &String::from_utf8_lossy(&output.stderr));
-        self.preview = Some(mame::preview::TextPreview::new(
-            Some(stdout_pane),
-            Some(stderr_pane),
-        ));
+        // `TextPreview` does not expose its text, so the output is kept for word selection.
+        let output = CommandOutput::new(
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        );
+        self.preview = Some(output.preview());
+        self.state.command_output = Some(output);
         Ok(())
     }
 }
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
-pub enum Focus {
-    #[default]
-    SearchResult,
-    Pattern,
-    AndPattern,
-    NotPattern,
-    Revision,
-    Path,
+// The browser is left running in the background.
+fn open_url(url: &str) -> orfail::Result<()> {
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    let mut command = std::process::Command::new(opener);
+    let status = command
+        .arg(url)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .or_fail_with(|e| format!("Failed to execute `$ {command:?}`: {e}"))?;
+    status
+        .success()
+        .or_fail_with(|()| format!("`$ {command:?}` exited with {status}"))?;
+    Ok(())
 }
 
-impl Focus {
+// The output of `git grep`, optionally preceded by the command line as a comment.
+fn raw_output_text(command_line: Option<String>, stdout: &[u8]) -> String {
+    let mut text = command_line
+        .map(|line| format!("# {line}\n"))
+        .unwrap_or_default();
+    text.push_str(&String::from_utf8_lossy(stdout));
+    text
+}
+
+// Sorts the files by their last commit time (newest first), putting files without history last.
+fn recency_order<'a, I, F>(files: I, time_of: F) -> Vec<PathBuf>
+where
+    I: Iterator<Item = &'a PathBuf>,
+    F: Fn(&Path) -> Option<i64>,
+{
+    let mut files = files
+        .map(|file| (time_of(file), file.clone()))
+        .collect::<Vec<_>>();
+    files.sort_by_key(|(time, _)| (time.is_none(), std::cmp::Reverse(*time)));
+    files.into_iter().map(|(_, file)| file).collect()
+}
+
+// Redraws the linked tokens wrapped in OSC 8 sequences, keeping the cursor position.
+fn write_hyperlinks<I>(links: I) -> std::io::Result<()>
+where
+    I: Iterator<Item = (TerminalPosition, crate::canvas::Token)>,
+{
+    use std::io::Write;
+
+    let mut stdout = std::io::stdout().lock();
+    let mut written = false;
+    for (position, token) in links {
+        let Some(link) = token.link() else {
+            continue;
+        };
+        if !written {
+            write!(stdout, "\x1b7")?;
+            written = true;
+        }
+        write!(
+            stdout,
+            "\x1b[{};{}H{}\x1b]8;;{link}\x1b\\{}\x1b]8;;\x1b\\{}",
+            position.row + 1,
+            position.col + 1,
+            token.style(),
+            token.text(),
+            tuinix::TerminalStyle::RESET
+        )?;
+    }
+    if written {
+        write!(stdout, "\x1b8")?;
+    }
+    stdout.flush()
+}
+
+// Percent-encodes the chars that cannot appear in a URL path as is.
+fn encode_url_path(path: &str) -> String {
+    let mut encoded = String::new();
+    for b in path.bytes() {
+        if b.is_ascii_alphanumeric() || b"/-._~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{b:02X}"));
+        }
+    }
+    encoded
+}
+
+// Returns the lines around `line_number` with the line itself marked by `>`.
+fn line_snippet(text: &str, line_number: NonZeroUsize, context: usize) -> String {
+    let start = line_number.get().saturating_sub(context).max(1);
+    let end = line_number.get() + context;
+    let width = end.to_string().len();
+    let mut snippet = String::new();
+    for (i, line) in text.lines().enumerate().map(|(i, l)| (i + 1, l)) {
+        if i < start || end < i {
+            continue;
+        }
+        let marker = if i == line_number.get() { '>' } else { ' ' };
+        snippet.push_str(&format!("{marker}{i:>width$}| {line}\n"));
+    }
+    if snippet.is_empty() {
+        snippet = format!("(line {line_number} does not exist)");
+    }
+    snippet
+}
+
+// First Unicode word (UAX #29) that overlaps with `range` of `text`.
+fn word_in(text: &str, range: Range<usize>) -> Option<&str> {
+    let end = range.end.max(range.start + 1);
+    text.split_word_bound_indices()
+        .skip_while(|(i, w)| i + w.len() <= range.start)
+        .take_while(|(i, _)| *i < end)
+        .map(|(_, w)| w)
+        .find(|w| w.chars().any(|c| c.is_alphanumeric() || c == '_'))
+}
+
+// Unlike `word_in()`, words joined by `.` or `'` (e.g., `foo.bar`) are split,
+// and runs starting with a digit (i.e., number literals) are not identifiers.
+fn identifier_in(text: &str, range: Range<usize>) -> Option<&str> {
+    let end = range.end.max(range.start + 1);
+    let mut start = None;
+    for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        if c.is_alphanumeric() || c == '_' {
+            start.get_or_insert(i);
+            continue;
+        }
+        let Some(s) = start.take() else {
+            continue;
+        };
+        if s >= end {
+            break;
+        }
+        if i > range.start && !text[s..].starts_with(|c: char| c.is_numeric()) {
+            return Some(&text[s..i]);
+        }
+    }
+    None
+}
+
+// Among the bindings sharing a trigger, the first applicable one is taken
+// (e.g., `<ESCAPE>` re-centers a scrolled view rather than quitting).
+fn find_binding<'a>(
+    bindings: &'a [Binding<Action>],
+    input: tuinix::TerminalInput,
+    state: &AppState,
+) -> Option<&'a Binding<Action>> {
+    let mut matched = bindings.iter().filter(|b| b.matches(input));
+    matched
+        .clone()
+        .find(|b| b.action.as_ref().is_none_or(|a| a.is_applicable(state)))
+        .or_else(|| matched.next())
+}
+
+// The `git grep` command printed on exit (if enabled), so that the final query can be reused.
+fn exit_command_line(grep: &GrepOptions, print_command: bool) -> Option<String> {
+    if !print_command {
+        return None;
+    }
+    let mut command = "git".to_owned();
+    if let Some(dir) = &grep.repo_dir {
+        command.push_str(" -C ");
+        command.push_str(&GrepArg::other(&dir.display().to_string()).quoted_text());
+    }
+    for arg in grep.args(Focus::default()) {
+        command.push(' ');
+        command.push_str(&arg.quoted_text());
+    }
+    Some(command)
+}
+
+fn commit_text(commit: Option<&CommitInfo>) -> String {
+    commit.map_or_else(
+        || "(no commits: the file may be untracked)".to_owned(),
+        |c| c.to_string(),
+    )
+}
+
+// Builds a pattern for `less`'s `/` command.
+fn less_search_pattern(grep: &GrepOptions) -> String {
+    let mut pattern = String::new();
+    for (i, c) in grep.pattern.text.chars().enumerate() {
+        // Characters at the beginning of a pattern are interpreted as search modifiers by `less`.
+        let needs_escape =
+            (grep.fixed_strings && r"\^$.*+?()[]{}|".contains(c)) || (i == 0 && "!*@".contains(c));
+        if needs_escape {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Focus {
+    #[default]
+    SearchResult,
+    Pattern,
+    AndPattern,
+    NotPattern,
+    Revision,
+    Path,
+    Prompt,
+}
+
+impl Focus {
+    // The order of `focus-next` and `focus-prev` (the prompt is not a part of the cycle).
+    const CYCLE: [Self; 6] = [
+        Self::SearchResult,
+        Self::Pattern,
+        Self::AndPattern,
+        Self::NotPattern,
+        Self::Revision,
+        Self::Path,
+    ];
+
     pub fn is_editing(self) -> bool {
         !matches!(self, Self::SearchResult)
     }
+
+    pub fn next(self) -> Self {
+        self.cycle(1)
+    }
+
+    pub fn prev(self) -> Self {
+        self.cycle(Self::CYCLE.len() - 1)
+    }
+
+    fn cycle(self, step: usize) -> Self {
+        match Self::CYCLE.iter().position(|&f| f == self) {
+            Some(i) => Self::CYCLE[(i + step) % Self::CYCLE.len()],
+            None => self,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PromptKind {
+    FileNumber,
+    Extensions,
+    PathspecFile,
+    Breadcrumb,
+    // Confirms the copy of a text larger than `clipboard-confirm-bytes`.
+    ConfirmCopy {
+        text: String,
+    },
+    Replacement,
+    // Prompts for the placeholders of the template one by one (`values` holds the ones already given).
+    TemplateValue {
+        template: PatternTemplate,
+        values: BTreeMap<String, String>,
+    },
+}
+
+impl PromptKind {
+    pub fn label(&self) -> Cow<'static, str> {
+        match self {
+            Self::FileNumber => Cow::Borrowed("JUMP TO FILE #"),
+            Self::Extensions => Cow::Borrowed("EXTENSIONS (e.g. rs,toml)"),
+            Self::PathspecFile => Cow::Borrowed("PATHSPEC FILE (empty clears)"),
+            Self::ConfirmCopy { text } => Cow::Owned(format!(
+                "COPY {}? [y/N]",
+                crate::clipboard::format_size(text.len())
+            )),
+            Self::Breadcrumb => Cow::Borrowed("RESTORE QUERY #"),
+            Self::Replacement => Cow::Borrowed("REPLACE WITH ($0 for the match)"),
+            Self::TemplateValue { template, values } => {
+                let name = template.placeholders().get(values.len()).copied();
+                Cow::Owned(format!("TEMPLATE {{{}}}", name.unwrap_or_default()))
+            }
+        }
+    }
+}
+
+// A single-line input that is edited with the same bindings as the grep args.
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub input: GrepArg,
+}
+
+// Recently run queries with short labels (oldest first), shown by the `show-breadcrumb` setting.
+#[derive(Debug, Default)]
+pub struct Breadcrumb {
+    entries: VecDeque<(String, GrepOptions)>,
+}
+
+impl Breadcrumb {
+    const CAPACITY: usize = 8;
+    const MAX_LABEL_COLS: usize = 20;
+
+    // A query that is the same as the last one is not pushed again.
+    pub fn push(&mut self, grep: &GrepOptions) {
+        if grep.pattern.is_empty()
+            || self
+                .entries
+                .back()
+                .is_some_and(|(_, last)| last.is_same_query(grep))
+        {
+            return;
+        }
+        self.entries.push_back((query_label(grep), grep.clone()));
+        if self.entries.len() > Self::CAPACITY {
+            self.entries.pop_front();
+        }
+    }
+
+    // Returns the `n`-th (1-based) query, dropping the later ones as the refinements are reverted.
+    pub fn restore(&mut self, n: usize) -> Option<GrepOptions> {
+        let (_, grep) = self.entries.get(n.checked_sub(1)?)?;
+        let grep = grep.clone();
+        self.entries.truncate(n);
+        Some(grep)
+    }
+
+    pub fn labels(&self) -> impl '_ + Iterator<Item = &str> {
+        self.entries.iter().map(|(label, _)| label.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+// E.g., `foo & bar !baz @HEAD :src`.
+fn query_label(grep: &GrepOptions) -> String {
+    let mut label = grep.pattern.text.clone();
+    for (prefix, arg) in [
+        (" & ", &grep.and_pattern),
+        (" !", &grep.not_pattern),
+        (" @", &grep.revision),
+        (" :", &grep.path),
+    ] {
+        if !arg.is_empty() {
+            label.push_str(prefix);
+            label.push_str(&arg.text);
+        }
+    }
+    if mame::terminal::str_cols(&label) > Breadcrumb::MAX_LABEL_COLS {
+        let mut cols = 0;
+        label = label
+            .chars()
+            .take_while(|&c| {
+                cols += mame::terminal::char_cols(c);
+                cols < Breadcrumb::MAX_LABEL_COLS
+            })
+            .chain(['…'])
+            .collect();
+    }
+    label
+}
+
+// Output of an external command shown in the preview, with a word cursor on the stdout side.
+#[derive(Debug, Clone)]
+pub struct CommandOutput {
+    stdout: String,
+    stderr: String,
+    line: usize,
+    word: usize,
+}
+
+impl CommandOutput {
+    pub fn new(stdout: String, stderr: String) -> Self {
+        let mut this = Self {
+            stdout,
+            stderr,
+            line: 0,
+            word: 0,
+        };
+        let first_line = this.word_lines().next();
+        this.line = first_line.unwrap_or_default();
+        this
+    }
+
+    fn words(line: &str) -> impl '_ + Iterator<Item = &str> {
+        line.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .filter(|w| !w.is_empty())
+    }
+
+    fn word_lines(&self) -> impl '_ + Iterator<Item = usize> {
+        self.stdout
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| Self::words(line).next().is_some())
+            .map(|(i, _)| i)
+    }
+
+    fn word_count(&self) -> usize {
+        self.stdout
+            .lines()
+            .nth(self.line)
+            .map_or(0, |line| Self::words(line).count())
+    }
+
+    pub fn selected_word(&self) -> Option<&str> {
+        Self::words(self.stdout.lines().nth(self.line)?).nth(self.word)
+    }
+
+    pub fn cursor_up(&mut self) {
+        if let Some(line) = self.word_lines().take_while(|i| *i < self.line).last() {
+            self.line = line;
+            self.word = self.word.min(self.word_count().saturating_sub(1));
+        }
+    }
+
+    pub fn cursor_down(&mut self) {
+        let next_line = self.word_lines().find(|i| *i > self.line);
+        if let Some(line) = next_line {
+            self.line = line;
+            self.word = self.word.min(self.word_count().saturating_sub(1));
+        }
+    }
+
+    pub fn cursor_left(&mut self) {
+        self.word = self.word.saturating_sub(1);
+    }
+
+    pub fn cursor_right(&mut self) {
+        self.word = (self.word + 1).min(self.word_count().saturating_sub(1));
+    }
+
+    pub fn preview(&self) -> mame::preview::TextPreview {
+        let stdout_pane = if let Some(word) = self.selected_word() {
+            let mut text = String::new();
+            for (i, line) in self.stdout.lines().enumerate() {
+                let marker = if i == self.line { '>' } else { ' ' };
+                text.push_str(&format!("{marker}{line}\n"));
+            }
+            mame::preview::TextPreviewPane::new(&format!("stdout [{word}]"), &text)
+        } else {
+            mame::preview::TextPreviewPane::new("stdout", &self.stdout)
+        };
+        let stderr_pane = mame::preview::TextPreviewPane::new("stderr", &self.stderr);
+        mame::preview::TextPreview::new(Some(stdout_pane), Some(stderr_pane))
+    }
 }
 
+// Files longer than this are shown in the preview by `ExpandFullFile` instead.
+const MAX_EXPANDED_FILE_LINES: usize = 5000;
+
 #[derive(Debug, Default)]
 pub struct AppState {
     pub grep: GrepOptions,
@@ -272,10 +1462,63 @@ pub struct AppState {
     pub show_terminal_cursor: Option<TerminalPosition>,
     pub focus: Focus,
     pub last_input_char: char,
+    pub min_match_len: usize,
+    // Position in the hit count order of the last `jump_to_hottest_file()` (reset by a new result).
+    pub hottest_file_rank: Option<usize>,
+    pub settings: Settings,
+    pub selection_anchor: Option<(PathBuf, NonZeroUsize)>,
+    pub file_window_start: usize,
+    pub prompt: Option<Prompt>,
+    pub confirming_quit: bool,
+    pub command_output: Option<CommandOutput>,
+    // The options of the current and the previous (different) searches, for `SwapPrevious`.
+    pub current_grep: Option<GrepOptions>,
+    pub previous_grep: Option<GrepOptions>,
+    // Absolute path of the current directory, which the result paths are relative to.
+    pub worktree_dir: Option<PathBuf>,
+    // Row offset of the result view while it is scrolled away from the cursor.
+    pub scroll_offset: Option<usize>,
+    // Whether only the hit lines are shown (regardless of the context lines of the search).
+    pub dense_mode: bool,
+    // Whether a column of hit density is shown beside the lines of each file.
+    pub show_minimap: bool,
+    // Whether the files may have been changed since the last search.
+    pub stale: bool,
+    // Whether each line is prefixed with its path instead of being grouped under a file heading.
+    pub inline_format: bool,
+    // Whether files that have only context lines (no hit lines) are hidden.
+    pub hide_context_only_files: bool,
+    pub breadcrumb: Breadcrumb,
+    // Whether to also search the working tree while a revision is given, to compare the hits.
+    pub compare_revision: bool,
+    // Hits of the query in the revision and in the working tree.
+    pub revision_comparison: Option<(usize, usize)>,
+    // Bumped whenever the rendered result may change other than by the cursor and scrolling.
+    pub view_generation: u64,
+    // Last commits per file fetched by `show-file-commit` (reset by a new result).
+    pub file_commits: BTreeMap<PathBuf, Option<CommitInfo>>,
+    pub show_git_status: bool,
+    // Lines the cursor has rested on (kept across searches until `clear-visited`).
+    pub visited: BTreeSet<(PathBuf, NonZeroUsize)>,
+    // Contents of the files read for `lazy_context` (`None` if unreadable; reset by a new result).
+    pub file_contents: BTreeMap<PathBuf, Option<Vec<String>>>,
+    // Statuses of the changed files in the working tree, fetched once per search while `show_git_status` is set.
+    pub git_statuses: Option<BTreeMap<String, FileStatus>>,
+    // Selected row of the per-directory summary shown in place of the files.
+    pub directory_summary: Option<usize>,
+    // The file whose whole content is shown in place of its hit lines, with the content lines.
+    pub expanded_file: Option<(PathBuf, Vec<String>)>,
+    // Sources of `TerminalEvent::FdReady` (see `handle_fd_ready()`).
+    pub watcher: Option<(FileWatcher, Debounce)>,
+    pub background_search: Option<BackgroundSearch>,
+    pub result_cache: SearchCache,
 }
 
 impl AppState {
     pub fn can_cursor_up(&self) -> bool {
+        if let Some(row) = self.directory_summary {
+            return row > 0;
+        }
         if self.cursor.is_file_level() {
             self.peek_cursor_up_file().is_some()
         } else if self.cursor.is_line_level() {
@@ -286,6 +1529,9 @@ impl AppState {
     }
 
     pub fn can_cursor_down(&self) -> bool {
+        if let Some(row) = self.directory_summary {
+            return row + 1 < self.search_result.directory_hits().len();
+        }
         if self.cursor.is_file_level() {
             self.peek_cursor_down_file().is_some()
         } else if self.cursor.is_line_level() {
@@ -295,247 +1541,2139 @@ impl AppState {
         }
     }
 
-    pub fn focused_arg_mut(&mut self) -> Option<&mut GrepArg> {
-        match self.focus {
-            Focus::SearchResult => None,
-            Focus::Pattern => Some(&mut self.grep.pattern),
-            Focus::AndPattern => Some(&mut self.grep.and_pattern),
-            Focus::NotPattern => Some(&mut self.grep.not_pattern),
-            Focus::Revision => Some(&mut self.grep.revision),
-            Focus::Path => Some(&mut self.grep.path),
-        }
+    pub fn can_increase_context(&self) -> bool {
+        self.cursor.is_line_level() && self.grep.context_lines < self.settings.max_context_lines
     }
 
-    pub fn set_focus(&mut self, focus: Focus) {
-        self.focus = focus;
+    pub fn can_decrease_context(&self) -> bool {
+        self.cursor.is_line_level() && self.grep.context_lines > ContextLines::MIN
     }
 
-    pub fn flip_grep_flag<F>(&mut self, f: F) -> orfail::Result<()>
-    where
-        F: FnOnce(&mut GrepOptions) -> &mut bool,
-    {
-        let flag = f(&mut self.grep);
-        *flag = !*flag;
-        self.regrep().or_fail()
+    pub fn is_hit_shown(&self, file: &PathBuf, line: &Line) -> bool {
+        line.hit
+            && (self.min_match_len == 0
+                || self
+                    .search_result
+                    .hit_texts_in_line(file, line.number)
+                    .iter()
+                    .any(|text| text.chars().count() >= self.min_match_len))
     }
 
-    pub fn regrep(&mut self) -> orfail::Result<()> {
-        let result = self.grep.call().or_fail();
-        match result {
-            Ok(result) => {
-                self.search_result = result;
-            }
-            Err(e) => {
-                if let Some(result) = self.grep.get_error_result() {
-                    self.search_result = result;
-                } else {
-                    return Err(e);
+    pub fn has_shown_hits(&self, file: &PathBuf) -> bool {
+        self.search_result
+            .files
+            .get(file)
+            // File-only entries (e.g., from the filename search) have no lines.
+            .is_some_and(|lines| {
+                lines.is_empty() || lines.iter().any(|line| self.is_hit_shown(file, line))
+            })
+            // Context-only files (e.g., from function contexts) are shown unless hidden.
+            || (!self.hide_context_only_files
+                && self.search_result.files.contains_key(file)
+                && self.search_result.hit_lines_in_file(file) == 0)
+    }
+
+    // All files in the display order.
+    pub fn ordered_files(&self) -> impl '_ + Iterator<Item = (&PathBuf, &Vec<Line>)> {
+        let order = &self.search_result.file_order;
+        let by_path = order.is_empty().then(|| self.search_result.files.iter());
+        let by_order = order
+            .iter()
+            .filter_map(|file| self.search_result.files.get_key_value(file));
+        by_path.into_iter().flatten().chain(by_order)
+    }
+
+    pub fn shown_files(&self) -> impl '_ + Iterator<Item = (&PathBuf, &Vec<Line>)> {
+        self.ordered_files()
+            .filter(|(file, _)| self.has_shown_hits(file))
+    }
+
+    pub fn apply_sort_order(&mut self) {
+        self.search_result.file_order = match self.settings.sort_order {
+            SortOrder::Path => Vec::new(),
+            SortOrder::Recency => {
+                if self.search_result.commit_times.is_none() {
+                    let paths = self
+                        .search_result
+                        .files
+                        .keys()
+                        .filter_map(|file| self.worktree_path(file))
+                        .collect::<Vec<_>>();
+                    let revision = (!self.grep.revision.is_empty())
+                        .then_some(self.grep.revision.text.as_str());
+                    // Sorting is best-effort: files are just treated as having no history if `git log` fails.
+                    let times = crate::git::last_commit_times(revision, &paths).unwrap_or_default();
+                    self.search_result.commit_times = Some(times);
                 }
+                let times = self
+                    .search_result
+                    .commit_times
+                    .as_ref()
+                    .expect("infallible");
+                recency_order(self.search_result.files.keys(), |file| {
+                    self.worktree_path(file)
+                        .and_then(|path| times.get(path))
+                        .copied()
+                })
             }
-        }
-        self.reset_cursor();
-        Ok(())
+        };
     }
 
-    pub fn toggle_expansion(&mut self) {
-        if self.cursor.is_line_level() {
-            return;
+    // The hit line if there is exactly one shown.
+    pub fn single_hit_line(&self) -> Option<(&PathBuf, NonZeroUsize)> {
+        let mut hits = self.shown_files().flat_map(|(file, lines)| {
+            lines
+                .iter()
+                .filter(move |line| self.is_hit_shown(file, line))
+                .map(move |line| (file, line.number))
+        });
+        let hit = hits.next()?;
+        hits.next().is_none().then_some(hit)
+    }
+
+    pub fn shown_hit_counts(&self) -> (usize, usize, usize) {
+        let mut counts = (0, 0, 0);
+        for (file, lines) in self.shown_files() {
+            counts.2 += 1;
+            for line in lines.iter().filter(|line| self.is_hit_shown(file, line)) {
+                counts.1 += 1;
+                counts.0 += self
+                    .search_result
+                    .hit_texts_in_line(file, line.number)
+                    .len();
+            }
         }
+        counts
+    }
+
+    // The pattern is the searched one, not the one being edited.
+    pub fn summary_text(&self) -> String {
+        let (hits, lines, files) = self.shown_hit_counts();
+        let pattern = &self
+            .current_grep
+            .as_ref()
+            .unwrap_or(&self.grep)
+            .pattern
+            .text;
+        self.settings
+            .summary_format
+            .format(hits, lines, files, pattern)
+    }
+
+    // Range of the shown files to be rendered (limited by `max_rendered_files`).
+    pub fn file_window(&self) -> Range<usize> {
+        let max = self.settings.max_rendered_files.max(1);
+        self.file_window_start..self.file_window_start + max
+    }
+
+    // Shifts the file window so that it contains the cursor.
+    pub fn update_file_window(&mut self) {
+        let max = self.settings.max_rendered_files.max(1);
+        let files = self.shown_files().count();
+        self.file_window_start = self.file_window_start.min(files.saturating_sub(max));
 
         let Some(file) = &self.cursor.file else {
             return;
         };
-        if !self.collapsed.remove(file) {
-            self.collapsed.insert(file.clone());
+        let Some(i) = self.shown_files().position(|(f, _)| f == file) else {
+            return;
+        };
+        if i < self.file_window_start {
+            self.file_window_start = i;
+        } else if i >= self.file_window_start + max {
+            self.file_window_start = i + 1 - max;
         }
     }
 
-    pub fn toggle_all_expansion(&mut self) {
-        fn can_collapse(cursor: &Cursor, file: &PathBuf) -> bool {
-            cursor.is_file_level() || cursor.file.as_ref() != Some(file)
-        }
+    // `n` is the 1-based file number as displayed in the result pane.
+    pub fn jump_to_file_number(&mut self, n: usize) {
+        let Some((file, _)) = n.checked_sub(1).and_then(|i| self.shown_files().nth(i)) else {
+            return;
+        };
+        self.jump_to_file(file.clone());
+    }
 
-        let target_files = self
-            .search_result
-            .files
-            .keys()
-            .filter(|file| can_collapse(&self.cursor, file));
-        if target_files
-            .clone()
-            .all(|file| self.collapsed.contains(file))
-        {
-            self.collapsed.clear();
-        } else {
-            self.collapsed.extend(target_files.cloned());
-        }
+    // Cycles through the files in descending order of the hit counts, skipping the focused file.
+    pub fn jump_to_hottest_file(&mut self) {
+        let mut files = self.shown_files().map(|(file, _)| file).collect::<Vec<_>>();
+        // Files with the same count are kept in the display order.
+        files.sort_by_key(|file| std::cmp::Reverse(self.search_result.hit_texts_in_file(file)));
+
+        let start = self.hottest_file_rank.map_or(0, |rank| rank + 1);
+        let Some(rank) = (start..start + files.len())
+            .map(|i| i % files.len())
+            .find(|&i| files.len() == 1 || self.cursor.file.as_ref() != Some(files[i]))
+        else {
+            return;
+        };
+        let file = files[rank].clone();
+        self.hottest_file_rank = Some(rank);
+        self.jump_to_file(file);
     }
 
-    pub fn cursor_up(&mut self) {
-        if self.cursor.is_file_level() {
-            self.cursor_up_file();
-        } else if self.cursor.is_line_level() {
-            self.cursor_up_line();
+    fn jump_to_file(&mut self, file: PathBuf) {
+        if self.cursor.file.as_ref() != Some(&file) {
+            self.cursor.file = Some(file);
+            self.selection_anchor = None;
         }
+        self.cursor.line_number = None;
+        self.cursor.match_index = None;
     }
 
-    fn peek_cursor_up_file(&self) -> Option<&PathBuf> {
-        let file = self.cursor.file.as_ref().expect("infallible");
-        self.search_result
-            .files
-            .range::<PathBuf, RangeTo<_>>(..file)
-            .next_back()
-            .map(|(k, _)| k)
+    pub fn set_min_match_len(&mut self, len: usize) {
+        self.min_match_len = len;
+        self.reset_cursor();
     }
 
-    fn cursor_up_file(&mut self) {
-        if let Some(new) = self.peek_cursor_up_file().cloned() {
-            self.cursor.file = Some(new);
+    // The summary opens with the directory of the focused file selected.
+    pub fn toggle_directory_summary(&mut self) {
+        if self.directory_summary.take().is_some() {
+            return;
         }
+        let dir = self.cursor.file.as_deref().map(crate::git::top_level_dir);
+        let row = self
+            .search_result
+            .directory_hits()
+            .iter()
+            .position(|(d, _)| Some(d) == dir.as_ref())
+            .unwrap_or_default();
+        self.directory_summary = Some(row);
+        self.cursor.line_number = None;
+        self.cursor.match_index = None;
     }
 
-    fn peek_cursor_up_line(&self) -> Option<(&PathBuf, NonZeroUsize)> {
-        let file = self.cursor.file.as_ref()?;
-        let line_number = self.cursor.line_number?;
-        let lines = self.search_result.files.get(file).expect("infallible");
-
-        if let Some(new_line) = lines
-            .iter()
-            .rfind(|line| line.hit && line.number < line_number)
-        {
-            Some((file, new_line.number))
-        } else if let Some(new_file) = self.peek_cursor_up_file() {
-            let lines = self.search_result.files.get(new_file).expect("infallible");
-            let new_line = lines.iter().rfind(|line| line.hit).expect("infallible");
-            Some((new_file, new_line.number))
-        } else {
-            None
+    // Closes the summary and moves the cursor to the first file in the selected directory.
+    pub fn drill_into_directory(&mut self) {
+        let Some(row) = self.directory_summary.take() else {
+            return;
+        };
+        let Some((dir, _)) = self.search_result.directory_hits().into_iter().nth(row) else {
+            return;
+        };
+        let file = self
+            .shown_files()
+            .map(|(file, _)| file)
+            .find(|file| crate::git::top_level_dir(file) == dir)
+            .cloned();
+        if let Some(file) = file {
+            self.jump_to_file(file);
         }
     }
 
-    fn cursor_up_line(&mut self) {
-        if let Some((file, line_number)) = self.peek_cursor_up_line() {
-            let file = file.clone();
-            self.collapsed.remove(&file);
-            self.cursor.file = Some(file);
-            self.cursor.line_number = Some(line_number);
-        }
+    pub fn toggle_context_only_files(&mut self) {
+        self.hide_context_only_files = !self.hide_context_only_files;
+        self.reset_cursor();
     }
 
-    pub fn cursor_down(&mut self) {
-        if self.cursor.is_file_level() {
-            self.cursor_down_file();
-        } else if self.cursor.is_line_level() {
-            self.cursor_down_line();
+    fn focused_hit_ranges(&self) -> Vec<Range<usize>> {
+        let (Some(file), Some(line_number)) = (&self.cursor.file, self.cursor.line_number) else {
+            return Vec::new();
+        };
+        let Some(lines) = self.search_result.files.get(file) else {
+            return Vec::new();
+        };
+        lines
+            .iter()
+            .find(|line| line.number == line_number)
+            .map(|line| self.search_result.hit_ranges_in_line(file, line))
+            .unwrap_or_default()
+    }
+
+    pub fn cursor_next_match(&mut self) {
+        let n = self.focused_hit_ranges().len();
+        if n == 0 {
+            return;
         }
+        self.cursor.match_index = Some(self.cursor.match_index.map_or(0, |i| (i + 1) % n));
     }
 
-    fn peek_cursor_down_line(&self) -> Option<(&PathBuf, NonZeroUsize)> {
+    pub fn cursor_prev_match(&mut self) {
+        let n = self.focused_hit_ranges().len();
+        if n == 0 {
+            return;
+        }
+        self.cursor.match_index = Some(self.cursor.match_index.map_or(n - 1, |i| (i + n - 1) % n));
+    }
+
+    fn focused_line(&self) -> Option<&Line> {
         let file = self.cursor.file.as_ref()?;
         let line_number = self.cursor.line_number?;
-        let lines = self.search_result.files.get(file).expect("infallible");
-
-        if let Some(new_line) = lines
+        self.search_result
+            .files
+            .get(file)?
             .iter()
-            .find(|line| line.hit && line.number > line_number)
-        {
-            Some((file, new_line.number))
-        } else if let Some(new_file) = self.peek_cursor_down_file() {
-            let lines = self.search_result.files.get(new_file).expect("infallible");
-            let new_line = lines.iter().find(|line| line.hit).expect("infallible");
-            Some((new_file, new_line.number))
+            .find(|line| line.number == line_number)
+    }
+
+    // Word at the active match (or the first match if none is active).
+    pub fn word_under_cursor(&self) -> Option<&str> {
+        let line = self.focused_line()?;
+        let ranges = self.focused_hit_ranges();
+        let range = ranges.get(self.cursor.match_index.unwrap_or_default())?;
+        word_in(&line.text, range.clone())
+    }
+
+    // Returns `false` if there is no word under the cursor.
+    pub fn refine_with_word_under_cursor(&mut self) -> bool {
+        let Some(word) = self.word_under_cursor().map(str::to_owned) else {
+            return false;
+        };
+        match self.settings.refine_mode {
+            RefineMode::Replace => self.grep.pattern.text = word,
+            RefineMode::And => self.grep.and_pattern.text = word,
+        }
+        true
+    }
+
+    pub fn symbol_under_cursor(&self) -> Option<&str> {
+        let line = self.focused_line()?;
+        let ranges = self.focused_hit_ranges();
+        let range = ranges.get(self.cursor.match_index.unwrap_or_default())?;
+        identifier_in(&line.text, range.clone())
+    }
+
+    // Searches for the identifier at the match as a whole word case-sensitively, like "find all uses"
+    // (the query is restorable by `swap-previous`).
+    // Returns `false` if there is no identifier under the cursor.
+    pub fn find_symbol(&mut self) -> bool {
+        let Some(symbol) = self.symbol_under_cursor().map(str::to_owned) else {
+            return false;
+        };
+        self.grep.pattern.text = symbol;
+        self.grep.word_regexp = true;
+        self.grep.ignore_case = false;
+        true
+    }
+
+    // Returns `false` if there is no such entry.
+    pub fn restore_breadcrumb(&mut self, n: usize) -> bool {
+        let Some(grep) = self.breadcrumb.restore(n) else {
+            return false;
+        };
+        self.grep = grep;
+        true
+    }
+
+    // Searches for the text of the focused line literally (the query is restorable by `swap-previous`).
+    // Returns `false` if there is no (non-blank) focused line.
+    pub fn search_whole_line(&mut self) -> bool {
+        let Some(line) = self.focused_line() else {
+            return false;
+        };
+        let text = if self.settings.trim_whole_line_search {
+            line.text.trim_start()
         } else {
-            None
+            line.text.as_str()
+        };
+        if text.trim().is_empty() {
+            return false;
         }
+        self.grep.pattern.text = text.to_owned();
+        self.grep.fixed_strings = true;
+        self.grep.extended_regexp = false;
+        self.grep.perl_regexp = false;
+        true
     }
 
-    fn cursor_down_line(&mut self) {
-        if let Some((file, line_number)) = self.peek_cursor_down_line() {
-            let file = file.clone();
-            self.collapsed.remove(&file);
-            self.cursor.file = Some(file);
-            self.cursor.line_number = Some(line_number);
+    // 1-based byte column of the active match (or the first match if none is active).
+    pub fn cursor_column(&self) -> Option<usize> {
+        let ranges = self.focused_hit_ranges();
+        let range = ranges.get(self.cursor.match_index.unwrap_or_default())?;
+        Some(range.start + 1)
+    }
+
+    pub fn is_cursor_file_expanded(&self) -> bool {
+        self.cursor.file.is_some()
+            && self.expanded_file.as_ref().map(|(file, _)| file) == self.cursor.file.as_ref()
+    }
+
+    // Returns `false` (without expanding) if the file has too many lines to be inlined.
+    pub fn expand_file(&mut self, file: PathBuf, text: &str) -> bool {
+        let lines = text.lines().map(str::to_owned).collect::<Vec<_>>();
+        if lines.len() > MAX_EXPANDED_FILE_LINES {
+            return false;
         }
+        self.expanded_file = Some((file, lines));
+        true
     }
 
-    fn peek_cursor_down_file(&self) -> Option<&PathBuf> {
-        let file = self.cursor.file.as_ref().expect("infallible");
-        self.search_result
-            .files
-            .range::<PathBuf, RangeFrom<_>>(file..)
-            .nth(1)
-            .map(|(k, _)| k)
+    // With a revision, `git grep` prefixes paths with `<revision>:`, which is stripped here.
+    pub fn worktree_path<'a>(&self, file: &'a Path) -> Option<&'a str> {
+        let path = file.to_str()?;
+        Some(
+            path.strip_prefix(&format!("{}:", self.grep.revision.text))
+                .filter(|_| !self.grep.revision.is_empty())
+                .unwrap_or(path),
+        )
     }
 
-    fn cursor_down_file(&mut self) {
-        if let Some(new) = self.peek_cursor_down_file().cloned() {
-            self.cursor.file = Some(new);
+    pub fn file_hyperlink(&self, file: &Path) -> Option<String> {
+        let format = self.settings.hyperlink_format.as_ref()?;
+        let path = self.worktree_dir.as_ref()?.join(self.worktree_path(file)?);
+        Some(format.format(&encode_url_path(path.to_str()?)))
+    }
+
+    pub fn cursor_code_reference(&self) -> Option<String> {
+        let file = self.cursor.file.as_ref()?;
+        let line_number = self.cursor.line_number?.get();
+        let path = self.worktree_path(file)?;
+
+        if let Some(template) = &self.settings.remote_url_template {
+            let revision = if self.grep.revision.is_empty() {
+                "HEAD"
+            } else {
+                &self.grep.revision.text
+            };
+            // Remote references are best-effort: fall back to a local one if `git` fails.
+            if let (Ok(sha), Ok(prefix)) =
+                (crate::git::rev_parse(revision), crate::git::show_prefix())
+            {
+                return Some(template.format(&sha, &format!("{prefix}{path}"), line_number));
+            }
         }
+        Some(
+            self.settings
+                .code_reference_format
+                .format(path, line_number),
+        )
     }
 
-    pub fn cursor_right(&mut self) {
-        if self.search_result.is_empty() | self.cursor.is_line_level() {
+    // Returns `Ok(None)` if the cursor is not on a line.
+    pub fn cursor_browse_url(&self) -> orfail::Result<Option<String>> {
+        let (Some(file), Some(line_number)) = (&self.cursor.file, self.cursor.line_number) else {
+            return Ok(None);
+        };
+        let path = self.worktree_path(file).or_fail()?;
+        let remote_url = crate::git::remote_url("origin").or_fail()?;
+        let base = crate::git::web_base_url(&remote_url)
+            .or_fail_with(|()| format!("unsupported remote URL: {remote_url}"))?;
+        let reference = if !self.grep.revision.is_empty() {
+            crate::git::rev_parse(&self.grep.revision.text).or_fail()?
+        } else if let Some(branch) = crate::git::current_branch().or_fail()? {
+            branch
+        } else {
+            crate::git::rev_parse("HEAD").or_fail()?
+        };
+        let prefix = crate::git::show_prefix().or_fail()?;
+        Ok(Some(self.settings.browse_url_template.format(
+            &base,
+            &reference,
+            &format!("{prefix}{path}"),
+            line_number.get(),
+        )))
+    }
+
+    pub fn start_selection(&mut self) {
+        if self.selection_anchor.is_some() {
+            self.selection_anchor = None;
+        } else if let (Some(file), Some(line_number)) = (&self.cursor.file, self.cursor.line_number)
+        {
+            self.selection_anchor = Some((file.clone(), line_number));
+        }
+    }
+
+    pub fn selected_range(&self) -> Option<(&PathBuf, RangeInclusive<NonZeroUsize>)> {
+        let (anchor_file, anchor_line_number) = self.selection_anchor.as_ref()?;
+        let file = self.cursor.file.as_ref().filter(|f| *f == anchor_file)?;
+        let line_number = self.cursor.line_number?;
+        let start = line_number.min(*anchor_line_number);
+        let end = line_number.max(*anchor_line_number);
+        Some((file, start..=end))
+    }
+
+    pub fn is_line_selected(&self, file: &PathBuf, line_number: NonZeroUsize) -> bool {
+        self.selected_range()
+            .is_some_and(|(f, range)| f == file && range.contains(&line_number))
+    }
+
+    pub fn selected_lines(&self) -> &[Line] {
+        let Some((file, range)) = self.selected_range() else {
+            return &[];
+        };
+        let lines = self.search_result.files.get(file).expect("infallible");
+        let start = lines.partition_point(|l| l.number < *range.start());
+        let end = lines.partition_point(|l| l.number <= *range.end());
+        &lines[start..end]
+    }
+
+    pub fn selected_text(&self) -> Option<String> {
+        let lines = self.selected_lines();
+        if lines.is_empty() {
+            return None;
+        }
+        Some(lines.iter().map(|l| format!("{}\n", l.text)).collect())
+    }
+
+    pub fn focused_arg(&self) -> Option<&GrepArg> {
+        match self.focus {
+            Focus::SearchResult => None,
+            Focus::Pattern => Some(&self.grep.pattern),
+            Focus::AndPattern => Some(&self.grep.and_pattern),
+            Focus::NotPattern => Some(&self.grep.not_pattern),
+            Focus::Revision => Some(&self.grep.revision),
+            Focus::Path => Some(&self.grep.path),
+            Focus::Prompt => self.prompt.as_ref().map(|p| &p.input),
+        }
+    }
+
+    pub fn focused_arg_mut(&mut self) -> Option<&mut GrepArg> {
+        match self.focus {
+            Focus::SearchResult => None,
+            Focus::Pattern => Some(&mut self.grep.pattern),
+            Focus::AndPattern => Some(&mut self.grep.and_pattern),
+            Focus::NotPattern => Some(&mut self.grep.not_pattern),
+            Focus::Revision => Some(&mut self.grep.revision),
+            Focus::Path => Some(&mut self.grep.path),
+            Focus::Prompt => self.prompt.as_mut().map(|p| &mut p.input),
+        }
+    }
+
+    pub fn set_focus(&mut self, focus: Focus) {
+        self.focus = focus;
+    }
+
+    pub fn flip_grep_flag<F>(&mut self, f: F) -> orfail::Result<()>
+    where
+        F: FnOnce(&mut GrepOptions) -> &mut bool,
+    {
+        let flag = f(&mut self.grep);
+        *flag = !*flag;
+        self.regrep().or_fail()
+    }
+
+    // Resets the flags and the context lines while preserving the patterns, the revision and the path.
+    pub fn reset_flags(&mut self) -> orfail::Result<()> {
+        let default = GrepOptions::default();
+        self.grep.ignore_case = default.ignore_case;
+        self.grep.untracked = default.untracked;
+        self.grep.no_index = default.no_index;
+        self.grep.no_recursive = default.no_recursive;
+        self.grep.textconv = default.textconv;
+        self.grep.word_regexp = default.word_regexp;
+        self.grep.extended_regexp = default.extended_regexp;
+        self.grep.fixed_strings = default.fixed_strings;
+        self.grep.perl_regexp = default.perl_regexp;
+        self.grep.context_lines = default.context_lines;
+        self.regrep().or_fail()
+    }
+
+    pub fn swap_previous(&mut self) -> orfail::Result<()> {
+        let Some(previous) = self.previous_grep.take() else {
+            return Ok(());
+        };
+        self.grep = previous;
+        self.regrep().or_fail()
+    }
+
+    pub fn mark_visited(&mut self) {
+        if let (Some(file), Some(line_number)) = (&self.cursor.file, self.cursor.line_number) {
+            self.visited.insert((file.clone(), line_number));
+        }
+    }
+
+    // Fetches the context of the focused line on demand, which is what `-C` would have given if `lazy_context` were unset.
+    pub fn load_cursor_context(&mut self) {
+        if !self.grep.lazy_context || self.grep.context_lines == ContextLines::MIN {
+            return;
+        }
+        let (Some(file), Some(line_number)) = (self.cursor.file.clone(), self.cursor.line_number)
+        else {
+            return;
+        };
+        if !self.file_contents.contains_key(&file) {
+            let content = self.worktree_path(&file).and_then(|path| {
+                let text = if self.grep.revision.is_empty() {
+                    std::fs::read_to_string(path).ok()
+                } else {
+                    crate::git::show_file(&self.grep.revision.text, path).ok()
+                };
+                text.map(|text| text.lines().map(str::to_owned).collect())
+            });
+            self.file_contents.insert(file.clone(), content);
+        }
+        let Some(Some(content)) = self.file_contents.get(&file) else {
             return;
+        };
+        if self.search_result.insert_context_lines(
+            &file,
+            line_number,
+            self.grep.context_lines.0,
+            content,
+        ) {
+            self.view_generation += 1;
         }
+    }
 
-        let file = self.cursor.file.as_ref().expect("infallible");
-        let line_number = self
-            .search_result
-            .files
-            .get(file)
-            .expect("infallible")
-            .iter()
-            .find(|l| l.hit)
-            .expect("infallible")
-            .number;
-        self.cursor.line_number = Some(line_number);
-        self.collapsed.remove(file);
+    pub fn toggle_git_status(&mut self) {
+        self.show_git_status = !self.show_git_status;
+        self.update_git_statuses();
     }
 
-    pub fn cursor_left(&mut self) {
-        if self.cursor.is_line_level() {
-            self.cursor.line_number = None;
+    // Statuses only make sense for the working tree (not for a revision or `--no-index`).
+    fn update_git_statuses(&mut self) {
+        if !self.show_git_status
+            || self.git_statuses.is_some()
+            || !self.grep.revision.is_empty()
+            || self.grep.no_index
+        {
+            return;
         }
+        // Best-effort: no markers are shown if `git status` fails.
+        self.git_statuses = Some(crate::git::file_statuses().unwrap_or_default());
     }
 
-    fn reset_cursor(&mut self) {
-        if self.search_result.is_empty() {
-            self.cursor = Cursor::default();
+    pub fn file_status(&self, file: &Path) -> Option<FileStatus> {
+        if !self.show_git_status || !self.grep.revision.is_empty() {
+            return None;
+        }
+        let statuses = self.git_statuses.as_ref()?;
+        statuses.get(file.to_str()?).copied()
+    }
+
+    pub fn toggle_revision_comparison(&mut self) {
+        self.compare_revision = !self.compare_revision;
+        self.update_revision_comparison();
+    }
+
+    // The working tree result goes through the result cache, so it is reused while the query is unchanged.
+    fn update_revision_comparison(&mut self) {
+        self.revision_comparison = None;
+        if !self.compare_revision
+            || self.grep.revision.is_empty()
+            || self.search_result.error.is_some()
+        {
             return;
         }
 
-        let Some(old_file) = &self.cursor.file else {
-            let new_file = self.search_result.files.keys().next().cloned();
-            self.cursor.file = new_file;
+        let mut worktree_grep = self.grep.clone();
+        worktree_grep.revision.text.clear();
+        let key = worktree_grep.cache_key();
+        let worktree_result = match self.result_cache.get(key) {
+            Some(result) => result,
+            None => {
+                let Ok(result) = worktree_grep.call() else {
+                    return;
+                };
+                self.result_cache.insert(key, &result);
+                result
+            }
+        };
+        if worktree_result.error.is_none() {
+            self.revision_comparison =
+                Some((self.search_result.hit_texts(), worktree_result.hit_texts()));
+        }
+    }
+
+    pub fn regrep(&mut self) -> orfail::Result<()> {
+        let key = self.grep.cache_key();
+        let result = match self.result_cache.get(key) {
+            Some(result) => Ok(result),
+            None => self
+                .grep
+                .call()
+                .or_fail()
+                .inspect(|result| self.result_cache.insert(key, result)),
+        };
+        self.apply_search_result(result)
+    }
+
+    // Re-runs the search ignoring the cached results (e.g., after the files have been changed).
+    pub fn refresh(&mut self) -> orfail::Result<()> {
+        self.result_cache.clear();
+        self.regrep()
+    }
+
+    // The fds to be polled along with the terminal input.
+    //
+    // Each fd is non-blocking or only becomes readable when reading it does not block.
+    // Once `poll_event()` reports one of them as `FdReady`, it must be passed to `handle_fd_ready()`,
+    // which consumes the pending data so that the same event is not reported again.
+    pub fn readable_fds(&self) -> Vec<RawFd> {
+        self.watcher
+            .iter()
+            .map(|(watcher, _)| watcher.fd())
+            .chain(self.background_search.iter().map(|search| search.fd()))
+            .collect()
+    }
+
+    // Time until a pending auto-refresh is due (`None` to wait for the events indefinitely).
+    pub fn poll_timeout(&self) -> Option<Duration> {
+        self.watcher
+            .as_ref()
+            .and_then(|(_, debounce)| debounce.remaining(Instant::now()))
+    }
+
+    // Returns whether the result has been changed (i.e., needs to be rendered).
+    pub fn handle_fd_ready(&mut self, fd: RawFd) -> orfail::Result<bool> {
+        if let Some((watcher, debounce)) = &mut self.watcher
+            && watcher.fd() == fd
+        {
+            watcher.drain();
+            debounce.notify(Instant::now());
+            return Ok(false);
+        }
+
+        let search = self
+            .background_search
+            .take_if(|search| search.fd() == fd)
+            .or_fail_with(|()| format!("unexpected fd ready: {fd}"))?;
+        let (options, result) = search.finish();
+        // The context lines are not part of the query but do change the result.
+        if options.cache_key() == self.grep.cache_key() {
+            if let Ok(result) = &result {
+                self.result_cache.insert(options.cache_key(), result);
+            }
+            self.apply_search_result(result)?;
+        } else {
+            // The query has been changed during the search.
+            self.stale = true;
+        }
+        Ok(true)
+    }
+
+    pub fn start_background_search(&mut self) -> orfail::Result<()> {
+        // A running search is just abandoned (its result will not be applied).
+        self.background_search = Some(BackgroundSearch::spawn(self.grep.clone()).or_fail()?);
+        Ok(())
+    }
+
+    fn apply_search_result(&mut self, result: orfail::Result<SearchResult>) -> orfail::Result<()> {
+        // A running search would overwrite this (newer) result with an older one.
+        self.background_search = None;
+        self.view_generation += 1;
+        if let Some(current) = self.current_grep.replace(self.grep.clone())
+            && !current.is_same_query(&self.grep)
+        {
+            self.previous_grep = Some(current);
+        }
+        self.breadcrumb.push(&self.grep);
+
+        match result {
+            Ok(result) => {
+                self.search_result = result;
+            }
+            Err(e) => {
+                if let Some(result) = self.grep.get_error_result() {
+                    self.search_result = result;
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+        self.selection_anchor = None;
+        self.scroll_offset = None;
+        self.stale = false;
+        self.expanded_file = None;
+        self.hottest_file_rank = None;
+        self.directory_summary = None;
+        self.file_commits.clear();
+        self.git_statuses = None;
+        self.update_git_statuses();
+        self.file_contents.clear();
+        self.apply_auto_expansion();
+        self.apply_sort_order();
+        self.reset_cursor();
+        self.load_cursor_context();
+        self.update_revision_comparison();
+        Ok(())
+    }
+
+    // Collapses files with more hit lines than the configured threshold and expands the others.
+    pub fn apply_auto_expansion(&mut self) {
+        let Some(max_hits) = self.settings.auto_expand_max_hits else {
             return;
         };
+        for file in self.search_result.files.keys() {
+            if self.search_result.hit_lines_in_file(file) > max_hits {
+                self.collapsed.insert(file.clone());
+            } else {
+                self.collapsed.remove(file);
+            }
+        }
+        self.leave_collapsed_lines();
+    }
 
-        if !self.search_result.files.contains_key(old_file) {
-            let new_file = self
-                .search_result
-                .files
-                .range::<PathBuf, RangeTo<_>>(..old_file)
-                .rev()
-                .chain(
-                    self.search_result
-                        .files
-                        .range::<PathBuf, RangeFrom<_>>(old_file..),
-                )
-                .next()
-                .map(|(k, _)| k.clone());
-            self.cursor.file = new_file;
+    // Keeps the invariant that a line-level cursor is in an expanded file,
+    // by moving the cursor up to the file if the file has been collapsed.
+    fn leave_collapsed_lines(&mut self) {
+        if self.cursor.is_line_level()
+            && self
+                .cursor
+                .file
+                .as_ref()
+                .is_some_and(|file| self.collapsed.contains(file))
+        {
             self.cursor.line_number = None;
-            return;
+            self.cursor.match_index = None;
+            self.selection_anchor = None;
         }
+    }
 
-        let Some(old_line_number) = self.cursor.line_number else {
+    // With a line-level cursor, the file is collapsed and the cursor moves up to the file.
+    pub fn toggle_expansion(&mut self) {
+        let Some(file) = &self.cursor.file else {
+            return;
+        };
+        if self.cursor.is_line_level() || !self.collapsed.remove(file) {
+            self.collapsed.insert(file.clone());
+        }
+        self.leave_collapsed_lines();
+    }
+
+    pub fn toggle_all_expansion(&mut self) {
+        fn can_collapse(cursor: &Cursor, file: &PathBuf) -> bool {
+            cursor.is_file_level() || cursor.file.as_ref() != Some(file)
+        }
+
+        let target_files = self
+            .search_result
+            .files
+            .keys()
+            .filter(|file| can_collapse(&self.cursor, file));
+        if target_files
+            .clone()
+            .all(|file| self.collapsed.contains(file))
+        {
+            self.collapsed.clear();
+        } else {
+            self.collapsed.extend(target_files.cloned());
+        }
+        self.leave_collapsed_lines();
+    }
+
+    // Stops at the first (or last for `cursor_down_by()`) entry.
+    pub fn cursor_up_by(&mut self, n: usize) {
+        for _ in 0..n {
+            if !self.can_cursor_up() {
+                break;
+            }
+            self.cursor_up();
+        }
+    }
+
+    pub fn cursor_down_by(&mut self, n: usize) {
+        for _ in 0..n {
+            if !self.can_cursor_down() {
+                break;
+            }
+            self.cursor_down();
+        }
+    }
+
+    pub fn cursor_up(&mut self) {
+        if let Some(row) = &mut self.directory_summary {
+            *row = row.saturating_sub(1);
+        } else if self.cursor.is_file_level() {
+            self.cursor_up_file();
+        } else if self.cursor.is_line_level() {
+            self.cursor_up_line();
+        }
+    }
+
+    fn peek_cursor_up_file(&self) -> Option<&PathBuf> {
+        let file = self.cursor.file.as_ref().expect("infallible");
+        self.ordered_files()
+            .take_while(|(k, _)| *k != file)
+            .filter(|(k, _)| self.has_shown_hits(k))
+            .last()
+            .map(|(k, _)| k)
+    }
+
+    fn cursor_up_file(&mut self) {
+        if let Some(new) = self.peek_cursor_up_file().cloned() {
+            self.cursor.file = Some(new);
+        }
+    }
+
+    fn peek_cursor_up_line(&self) -> Option<(&PathBuf, NonZeroUsize)> {
+        let file = self.cursor.file.as_ref()?;
+        let line_number = self.cursor.line_number?;
+        let lines = self.search_result.files.get(file).expect("infallible");
+
+        if let Some(new_line) = lines
+            .iter()
+            .rfind(|line| self.is_hit_shown(file, line) && line.number < line_number)
+        {
+            Some((file, new_line.number))
+        } else {
+            // Files without shown hit lines (e.g., context-only files) have no line to move into.
+            self.ordered_files()
+                .take_while(|(k, _)| *k != file)
+                .filter_map(|(k, lines)| {
+                    let line = lines.iter().rfind(|line| self.is_hit_shown(k, line))?;
+                    Some((k, line.number))
+                })
+                .last()
+        }
+    }
+
+    fn cursor_up_line(&mut self) {
+        if let Some((file, line_number)) = self.peek_cursor_up_line() {
+            let file = file.clone();
+            self.collapsed.remove(&file);
+            if self
+                .selection_anchor
+                .as_ref()
+                .is_some_and(|(anchor_file, _)| *anchor_file != file)
+            {
+                self.selection_anchor = None;
+            }
+            self.cursor.file = Some(file);
+            self.cursor.line_number = Some(line_number);
+            self.cursor.match_index = None;
+        }
+    }
+
+    pub fn cursor_down(&mut self) {
+        if self.directory_summary.is_some() {
+            let rows = self.search_result.directory_hits().len();
+            self.directory_summary = self
+                .directory_summary
+                .map(|row| (row + 1).min(rows.saturating_sub(1)));
+        } else if self.cursor.is_file_level() {
+            self.cursor_down_file();
+        } else if self.cursor.is_line_level() {
+            self.cursor_down_line();
+        }
+    }
+
+    fn peek_cursor_down_line(&self) -> Option<(&PathBuf, NonZeroUsize)> {
+        let file = self.cursor.file.as_ref()?;
+        let line_number = self.cursor.line_number?;
+        let lines = self.search_result.files.get(file).expect("infallible");
+
+        if let Some(new_line) = lines
+            .iter()
+            .find(|line| self.is_hit_shown(file, line) && line.number > line_number)
+        {
+            Some((file, new_line.number))
+        } else {
+            // Files without shown hit lines (e.g., context-only files) have no line to move into.
+            self.ordered_files()
+                .skip_while(|(k, _)| *k != file)
+                .skip(1)
+                .find_map(|(k, lines)| {
+                    let line = lines.iter().find(|line| self.is_hit_shown(k, line))?;
+                    Some((k, line.number))
+                })
+        }
+    }
+
+    fn cursor_down_line(&mut self) {
+        if let Some((file, line_number)) = self.peek_cursor_down_line() {
+            let file = file.clone();
+            self.collapsed.remove(&file);
+            if self
+                .selection_anchor
+                .as_ref()
+                .is_some_and(|(anchor_file, _)| *anchor_file != file)
+            {
+                self.selection_anchor = None;
+            }
+            self.cursor.file = Some(file);
+            self.cursor.line_number = Some(line_number);
+            self.cursor.match_index = None;
+        }
+    }
+
+    fn peek_cursor_down_file(&self) -> Option<&PathBuf> {
+        let file = self.cursor.file.as_ref().expect("infallible");
+        self.ordered_files()
+            .skip_while(|(k, _)| *k != file)
+            .skip(1)
+            .find(|(k, _)| self.has_shown_hits(k))
+            .map(|(k, _)| k)
+    }
+
+    fn cursor_down_file(&mut self) {
+        if let Some(new) = self.peek_cursor_down_file().cloned() {
+            self.cursor.file = Some(new);
+        }
+    }
+
+    pub fn cursor_right(&mut self) {
+        if self.directory_summary.is_some() {
+            self.drill_into_directory();
+            return;
+        }
+        if self.search_result.is_empty() | self.cursor.is_line_level() {
+            return;
+        }
+
+        let file = self.cursor.file.as_ref().expect("infallible");
+        // File-only entries have no lines to move into.
+        let Some(line) = self
+            .search_result
+            .files
+            .get(file)
+            .expect("infallible")
+            .iter()
+            .find(|l| self.is_hit_shown(file, l))
+        else {
+            return;
+        };
+        self.cursor.line_number = Some(line.number);
+        self.cursor.match_index = None;
+        self.collapsed.remove(file);
+    }
+
+    // Applies `file-activation` to the focused file.
+    // Opening an editor needs the terminal, so the path and line to open are returned to the caller instead.
+    pub fn activate_file(&mut self) -> Option<(String, NonZeroUsize)> {
+        let file = self
+            .cursor
+            .file
+            .clone()
+            .filter(|_| !self.cursor.is_line_level())?;
+        match self.settings.file_activation {
+            FileActivation::ToggleExpansion => self.toggle_expansion(),
+            FileActivation::EnterFirstLine => self.cursor_right(),
+            FileActivation::OpenInEditor => {
+                let line_number = self
+                    .search_result
+                    .files
+                    .get(&file)?
+                    .iter()
+                    .find(|l| self.is_hit_shown(&file, l))
+                    .map_or(NonZeroUsize::MIN, |l| l.number);
+                return Some((self.worktree_path(&file)?.to_owned(), line_number));
+            }
+        }
+        None
+    }
+
+    pub fn cursor_left(&mut self) {
+        if self.cursor.is_line_level() {
+            self.cursor.line_number = None;
+            self.cursor.match_index = None;
+        }
+    }
+
+    fn reset_cursor(&mut self) {
+        if self.shown_files().next().is_none() {
+            self.cursor = Cursor::default();
+            return;
+        }
+
+        let Some(old_file) = &self.cursor.file else {
+            let new_file = self.shown_files().next().map(|(k, _)| k.clone());
+            self.cursor.file = new_file;
+            return;
+        };
+
+        if !self.has_shown_hits(old_file) {
+            let order = &self.search_result.file_order;
+            let new_file = if order.is_empty() {
+                self.search_result
+                    .files
+                    .range::<PathBuf, RangeTo<_>>(..old_file)
+                    .rev()
+                    .chain(
+                        self.search_result
+                            .files
+                            .range::<PathBuf, RangeFrom<_>>(old_file..),
+                    )
+                    .map(|(k, _)| k)
+                    .find(|k| self.has_shown_hits(k))
+                    .cloned()
+            } else {
+                let i = order.iter().position(|f| f == old_file).unwrap_or(0);
+                order[..i]
+                    .iter()
+                    .rev()
+                    .chain(&order[i..])
+                    .find(|k| self.has_shown_hits(k))
+                    .cloned()
+            };
+            self.cursor.file = new_file;
+            self.cursor.line_number = None;
+            self.cursor.match_index = None;
+            return;
+        }
+
+        let Some(old_line_number) = self.cursor.line_number else {
             return;
         };
         let file = self.cursor.file.as_ref().expect("infallible");
         let lines = self.search_result.files.get(file).expect("infallible");
         self.cursor.line_number = lines
             .iter()
-            .rfind(|line| line.hit && old_line_number >= line.number)
-            .or_else(|| lines.iter().find(|line| line.hit))
+            .rfind(|line| self.is_hit_shown(file, line) && old_line_number >= line.number)
+            .or_else(|| lines.iter().find(|line| self.is_hit_shown(file, line)))
             .map(|line| line.number);
+        self.cursor.match_index = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use orfail::OrFail;
+
+    use super::*;
+    use crate::git::Highlight;
+
+    fn state(output: &str) -> orfail::Result<AppState> {
+        let mut state = AppState {
+            search_result: SearchResult::parse(output, Highlight::default()).or_fail()?,
+            ..Default::default()
+        };
+        state.reset_cursor();
+        Ok(state)
+    }
+
+    #[test]
+    fn line_snippet_marks_focused_line() -> orfail::Result<()> {
+        let text = "a\nb\nc\nd\ne\n";
+        let line = NonZeroUsize::new(2).or_fail()?;
+        assert_eq!(line_snippet(text, line, 1), " 1| a\n>2| b\n 3| c\n");
+
+        let line = NonZeroUsize::new(10).or_fail()?;
+        assert_eq!(line_snippet(text, line, 1), "(line 10 does not exist)");
+        Ok(())
+    }
+
+    #[test]
+    fn command_output_word_cursor() {
+        let mut output =
+            CommandOutput::new("\nfoo bar_baz\n--\nqux(1)\n".to_owned(), String::new());
+        assert_eq!(output.selected_word(), Some("foo"));
+
+        output.cursor_right();
+        output.cursor_right();
+        assert_eq!(output.selected_word(), Some("bar_baz"));
+
+        // Lines without words are skipped and the word index is clamped.
+        output.cursor_down();
+        assert_eq!(output.selected_word(), Some("1"));
+        output.cursor_left();
+        assert_eq!(output.selected_word(), Some("qux"));
+        output.cursor_down();
+        assert_eq!(output.selected_word(), Some("qux"));
+
+        output.cursor_up();
+        assert_eq!(output.selected_word(), Some("foo"));
+        output.cursor_up();
+        assert_eq!(output.selected_word(), Some("foo"));
+
+        let output = CommandOutput::new("--\n".to_owned(), String::new());
+        assert_eq!(output.selected_word(), None);
+    }
+
+    #[test]
+    fn file_hyperlinks() -> orfail::Result<()> {
+        let mut local_state = AppState::default();
+        let file = Path::new("src/a b.rs");
+        assert_eq!(local_state.file_hyperlink(file), None);
+
+        local_state.worktree_dir = Some(PathBuf::from("/repo/sub"));
+        local_state.settings.hyperlink_format =
+            Some("file://{path}".parse().map_err(orfail::Failure::new)?);
+        assert_eq!(
+            local_state.file_hyperlink(file).as_deref(),
+            Some("file:///repo/sub/src/a%20b.rs")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn raw_output_with_command_line() {
+        let mut options = GrepOptions::default();
+        options.pattern.text = "foo bar".to_owned();
+        let stdout = b"a.rs\n1:foo bar\n";
+        assert_eq!(raw_output_text(None, stdout), "a.rs\n1:foo bar\n");
+        assert_eq!(
+            raw_output_text(Some(options.parsing_command_line()), stdout),
+            "# $ git grep -nI --heading -C 4 'foo bar'\na.rs\n1:foo bar\n"
+        );
+    }
+
+    #[test]
+    fn recency_sorted_navigation() -> orfail::Result<()> {
+        let mut local_state = state("a.rs\n1:x\nb.rs\n1:x\nc.rs\n1:x\nd.rs\n1:x\n")?;
+        let times = [("a.rs", 100), ("c.rs", 300), ("d.rs", 100)];
+        local_state.search_result.file_order =
+            recency_order(local_state.search_result.files.keys(), |file| {
+                times
+                    .iter()
+                    .find(|(path, _)| Path::new(path) == file)
+                    .map(|(_, time)| *time)
+            });
+
+        // Ties keep the path order and files without history come last.
+        let files = |s: &AppState| {
+            s.shown_files()
+                .map(|(file, _)| file.display().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(files(&local_state), ["c.rs", "a.rs", "d.rs", "b.rs"]);
+
+        local_state.cursor.file = Some(PathBuf::from("c.rs"));
+        assert!(!local_state.can_cursor_up());
+        local_state.cursor_down();
+        local_state.cursor_down();
+        assert_eq!(local_state.cursor.file, Some(PathBuf::from("d.rs")));
+        local_state.cursor_up();
+        assert_eq!(local_state.cursor.file, Some(PathBuf::from("a.rs")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn less_pattern_escaping() {
+        let mut grep = GrepOptions::default();
+        grep.pattern.text = "!foo(bar)".to_owned();
+        assert_eq!(less_search_pattern(&grep), r"\!foo(bar)");
+
+        grep.fixed_strings = true;
+        assert_eq!(less_search_pattern(&grep), r"\!foo\(bar\)");
+    }
+
+    #[test]
+    fn activate_file() -> orfail::Result<()> {
+        let a = PathBuf::from("a.rs");
+        let activate = |activation| -> orfail::Result<_> {
+            let mut local_state = state("a.rs\n1-x\n2:x\n3:x\n")?;
+            local_state.settings.file_activation = activation;
+            local_state.cursor.file = Some(a.clone());
+            let opened = local_state.activate_file();
+            Ok((local_state, opened))
+        };
+
+        let (local_state, opened) = activate(FileActivation::ToggleExpansion)?;
+        assert!(local_state.collapsed.contains(&a));
+        assert_eq!(opened, None);
+
+        let (local_state, opened) = activate(FileActivation::EnterFirstLine)?;
+        assert_eq!(local_state.cursor.line_number, NonZeroUsize::new(2));
+        assert_eq!(opened, None);
+
+        let (local_state, opened) = activate(FileActivation::OpenInEditor)?;
+        assert!(!local_state.collapsed.contains(&a));
+        assert!(local_state.cursor.is_file_level());
+        assert_eq!(
+            opened,
+            Some(("a.rs".to_owned(), NonZeroUsize::new(2).or_fail()?))
+        );
+
+        // Nothing is done at line level.
+        let (mut local_state, _) = activate(FileActivation::EnterFirstLine)?;
+        local_state.settings.file_activation = FileActivation::ToggleExpansion;
+        assert_eq!(local_state.activate_file(), None);
+        assert!(!local_state.collapsed.contains(&a));
+        Ok(())
+    }
+
+    #[test]
+    fn collapse_focused_file() -> orfail::Result<()> {
+        let mut local_state = state("a.rs\n1:x\n2:x\nb.rs\n1:x\n")?;
+        let a = PathBuf::from("a.rs");
+
+        // File-level cursor.
+        local_state.cursor.file = Some(a.clone());
+        local_state.toggle_expansion();
+        assert!(local_state.collapsed.contains(&a));
+        assert!(local_state.cursor.is_file_level());
+        local_state.toggle_expansion();
+        assert!(!local_state.collapsed.contains(&a));
+
+        // Line-level cursor: collapsing moves the cursor up to the file.
+        local_state.cursor.line_number = NonZeroUsize::new(2);
+        local_state.cursor.match_index = Some(0);
+        local_state.toggle_expansion();
+        assert!(local_state.collapsed.contains(&a));
+        assert_eq!(local_state.cursor.file, Some(a.clone()));
+        assert_eq!(local_state.cursor.line_number, None);
+        assert_eq!(local_state.cursor.match_index, None);
+
+        // Collapsing all files keeps the file of a line-level cursor expanded.
+        local_state.toggle_expansion();
+        local_state.cursor.line_number = NonZeroUsize::new(1);
+        local_state.toggle_all_expansion();
+        assert!(!local_state.collapsed.contains(&a));
+        assert!(local_state.collapsed.contains(&PathBuf::from("b.rs")));
+        assert!(local_state.cursor.is_line_level());
+
+        // The auto expansion (e.g., by reloaded settings) also moves the cursor up.
+        local_state.settings.auto_expand_max_hits = Some(1);
+        local_state.apply_auto_expansion();
+        assert!(local_state.collapsed.contains(&a));
+        assert!(local_state.cursor.is_file_level());
+        Ok(())
+    }
+
+    #[test]
+    fn breadcrumb() {
+        let query = |pattern: &str, and_pattern: &str| {
+            let mut grep = GrepOptions::default();
+            grep.pattern.text = pattern.to_owned();
+            grep.and_pattern.text = and_pattern.to_owned();
+            grep
+        };
+
+        let mut breadcrumb = Breadcrumb::default();
+        breadcrumb.push(&query("", ""));
+        assert!(breadcrumb.is_empty());
+
+        breadcrumb.push(&query("foo", ""));
+        breadcrumb.push(&query("foo", ""));
+        breadcrumb.push(&query("foo", "bar"));
+        let mut ignore_case = query("foo", "bar");
+        ignore_case.ignore_case = true;
+        breadcrumb.push(&ignore_case);
+        breadcrumb.push(&query("a_very_long_pattern_to_be_truncated", ""));
+        assert_eq!(
+            breadcrumb.labels().collect::<Vec<_>>(),
+            ["foo", "foo & bar", "foo & bar", "a_very_long_pattern…"]
+        );
+
+        // Restoring drops the later entries.
+        assert!(breadcrumb.restore(0).is_none());
+        assert!(breadcrumb.restore(5).is_none());
+        let restored = breadcrumb.restore(3).expect("entry");
+        assert!(restored.is_same_query(&ignore_case));
+        assert_eq!(breadcrumb.labels().count(), 3);
+
+        // Re-running the restored query does not add an entry.
+        breadcrumb.push(&restored);
+        assert_eq!(breadcrumb.labels().count(), 3);
+
+        // Bounded.
+        for i in 0..20 {
+            breadcrumb.push(&query(&i.to_string(), ""));
+        }
+        assert_eq!(
+            breadcrumb.labels().collect::<Vec<_>>(),
+            ["12", "13", "14", "15", "16", "17", "18", "19"]
+        );
+    }
+
+    #[test]
+    fn focus_cycle() {
+        let mut focus = Focus::SearchResult;
+        let mut order = Vec::new();
+        for _ in 0..6 {
+            focus = focus.next();
+            order.push(focus);
+        }
+        assert_eq!(
+            order,
+            [
+                Focus::Pattern,
+                Focus::AndPattern,
+                Focus::NotPattern,
+                Focus::Revision,
+                Focus::Path,
+                Focus::SearchResult
+            ]
+        );
+        for &focus in &order {
+            assert_eq!(focus.next().prev(), focus);
+        }
+        assert_eq!(Focus::SearchResult.prev(), Focus::Path);
+        assert_eq!(Focus::Prompt.next(), Focus::Prompt);
+    }
+
+    #[test]
+    fn auto_expansion() -> orfail::Result<()> {
+        let output = "a.rs\n1:x\nb.rs\n1:x\n2-y\n3:x\n4:x\nc.rs\n1:x\n2:x\n";
+
+        // Disabled by default.
+        let mut state = state(output).or_fail()?;
+        state.collapsed.insert(PathBuf::from("a.rs"));
+        state.apply_auto_expansion();
+        assert_eq!(state.collapsed.len(), 1);
+
+        state.settings.auto_expand_max_hits = Some(2);
+        state.apply_auto_expansion();
+        assert_eq!(
+            state.collapsed.iter().collect::<Vec<_>>(),
+            [&PathBuf::from("b.rs")]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn code_reference() -> orfail::Result<()> {
+        let mut local_state = state("src/a.rs\n1-x\n2:y\nsrc/b.rs\n3:z\n").or_fail()?;
+        assert_eq!(local_state.cursor_code_reference(), None);
+
+        local_state.cursor_right();
+        assert_eq!(
+            local_state.cursor_code_reference().as_deref(),
+            Some("src/a.rs#L2")
+        );
+
+        let mut rev_state = state("HEAD~1:src/a.rs\n2:y\n").or_fail()?;
+        rev_state.grep.revision.text = "HEAD~1".to_owned();
+        rev_state.settings.code_reference_format =
+            "{path}:L{line}".parse().map_err(orfail::Failure::new)?;
+        rev_state.cursor_right();
+        assert_eq!(
+            rev_state.cursor_code_reference().as_deref(),
+            Some("src/a.rs:L2")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn file_window_follows_cursor() -> orfail::Result<()> {
+        let mut state = state("a\n1:x\nb\n1:x\nc\n1:x\nd\n1:x\ne\n1:x\n").or_fail()?;
+        state.settings.max_rendered_files = 2;
+        state.update_file_window();
+        assert_eq!(state.file_window(), 0..2);
+
+        state.cursor_down();
+        state.update_file_window();
+        assert_eq!(state.file_window(), 0..2);
+
+        state.cursor_down();
+        state.cursor_down();
+        state.update_file_window();
+        assert_eq!(state.file_window(), 2..4);
+
+        state.cursor_up();
+        state.cursor_up();
+        state.cursor_up();
+        state.update_file_window();
+        assert_eq!(state.file_window(), 0..2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn jump_to_file_number() -> orfail::Result<()> {
+        let mut state = AppState {
+            search_result: SearchResult::parse(
+                "a\n1:x\nb\n1:xyz\nc\n1:xyz\n",
+                Highlight::parse("a\n1:x\nb\n1:xyz\nc\n1:xyz\n").or_fail()?,
+            )
+            .or_fail()?,
+            ..Default::default()
+        };
+        state.reset_cursor();
+        state.cursor_right();
+
+        state.jump_to_file_number(3);
+        assert_eq!(state.cursor.file, Some(PathBuf::from("c")));
+        assert!(state.cursor.is_file_level());
+
+        // Out-of-range numbers are ignored.
+        state.jump_to_file_number(0);
+        state.jump_to_file_number(4);
+        assert_eq!(state.cursor.file, Some(PathBuf::from("c")));
+
+        // The numbering follows the displayed (filtered) order.
+        state.set_min_match_len(2);
+        state.jump_to_file_number(1);
+        assert_eq!(state.cursor.file, Some(PathBuf::from("b")));
+        state.jump_to_file_number(3);
+        assert_eq!(state.cursor.file, Some(PathBuf::from("b")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn context_bounds() -> orfail::Result<()> {
+        let mut local_state = state("a.rs\n1:foo\n")?;
+        local_state.cursor_right();
+        local_state.settings.max_context_lines = ContextLines(50);
+
+        local_state.grep.context_lines = ContextLines(49);
+        assert!(local_state.can_increase_context());
+        local_state.grep.context_lines = ContextLines(50);
+        assert!(!local_state.can_increase_context());
+        assert!(local_state.can_decrease_context());
+
+        local_state.grep.context_lines = ContextLines::MIN;
+        assert!(!local_state.can_decrease_context());
+
+        // The settings value is capped by `ContextLines::MAX`.
+        let json = nojson::RawJson::parse(r#"{"max-context-lines": 1000}"#).or_fail()?;
+        let settings = Settings::try_from(json.value()).or_fail()?;
+        assert_eq!(settings.max_context_lines, ContextLines::MAX);
+
+        Ok(())
+    }
+
+    #[test]
+    fn swap_previous_search() -> orfail::Result<()> {
+        let mut local_state = AppState::default();
+        local_state.grep.pattern.text = "foo".to_owned();
+        local_state.regrep().or_fail()?;
+        assert!(local_state.previous_grep.is_none());
+
+        // Re-running the same query does not replace the previous one.
+        local_state.grep.pattern.text = "bar".to_owned();
+        local_state.regrep().or_fail()?;
+        local_state.grep.context_lines = ContextLines(1);
+        local_state.regrep().or_fail()?;
+
+        local_state.swap_previous().or_fail()?;
+        assert_eq!(local_state.grep.pattern.text, "foo");
+        local_state.swap_previous().or_fail()?;
+        assert_eq!(local_state.grep.pattern.text, "bar");
+        assert_eq!(local_state.grep.context_lines, ContextLines(1));
+        local_state.swap_previous().or_fail()?;
+        assert_eq!(local_state.grep.pattern.text, "foo");
+
+        Ok(())
+    }
+
+    #[test]
+    fn reset_flags() -> orfail::Result<()> {
+        let mut state = AppState::default();
+        state.grep.and_pattern.text = "foo".to_owned();
+        state.grep.path.text = "src/".to_owned();
+        state.grep.ignore_case = true;
+        state.grep.untracked = true;
+        state.grep.word_regexp = true;
+        state.grep.fixed_strings = true;
+        state.grep.context_lines = ContextLines(10);
+
+        state.reset_flags().or_fail()?;
+        assert!(!state.grep.ignore_case);
+        assert!(!state.grep.untracked);
+        assert!(!state.grep.word_regexp);
+        assert!(!state.grep.fixed_strings);
+        assert_eq!(state.grep.context_lines, ContextLines::default());
+        assert_eq!(state.grep.and_pattern.text, "foo");
+        assert_eq!(state.grep.path.text, "src/");
+
+        Ok(())
+    }
+
+    #[test]
+    fn selection_range() -> orfail::Result<()> {
+        let mut state = state(
+            r#"a.rs
+1:a
+2-b
+3:c
+4-d
+5:e
+b.rs
+1:f
+"#,
+        )
+        .or_fail()?;
+
+        state.cursor_right();
+        assert!(state.selected_range().is_none());
+
+        // Select upward and downward from the anchor.
+        state.cursor_down();
+        state.start_selection();
+        state.cursor_up();
+        let texts = state.selected_lines().iter().map(|l| l.text.as_str());
+        assert_eq!(texts.collect::<Vec<_>>(), ["a", "b", "c"]);
+
+        state.cursor_down();
+        state.cursor_down();
+        let texts = state.selected_lines().iter().map(|l| l.text.as_str());
+        assert_eq!(texts.collect::<Vec<_>>(), ["c", "d", "e"]);
+        assert_eq!(state.selected_text().as_deref(), Some("c\nd\ne\n"));
+
+        // Leaving the file cancels the selection.
+        state.cursor_down();
+        assert!(state.selection_anchor.is_none());
+        assert!(state.selected_range().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn match_navigation_wraps_around() -> orfail::Result<()> {
+        let mut state = AppState {
+            search_result: SearchResult::parse(
+                "a.rs\n1:foo bar foo\n2:foo\n",
+                Highlight::parse("a.rs\n1:foo\n1:foo\n2:foo\n").or_fail()?,
+            )
+            .or_fail()?,
+            ..Default::default()
+        };
+        state.reset_cursor();
+        state.cursor_right();
+        assert_eq!(state.cursor.match_index, None);
+        assert_eq!(state.cursor_column(), Some(1));
+
+        state.cursor_next_match();
+        assert_eq!(state.cursor.match_index, Some(0));
+        state.cursor_next_match();
+        assert_eq!(state.cursor.match_index, Some(1));
+        assert_eq!(state.cursor_column(), Some(9));
+        state.cursor_next_match();
+        assert_eq!(state.cursor.match_index, Some(0));
+
+        state.cursor_prev_match();
+        assert_eq!(state.cursor.match_index, Some(1));
+        state.cursor_prev_match();
+        assert_eq!(state.cursor.match_index, Some(0));
+        state.cursor_prev_match();
+        assert_eq!(state.cursor.match_index, Some(1));
+
+        // Moving to another line resets the match index.
+        state.cursor_down();
+        assert_eq!(state.cursor.match_index, None);
+        state.cursor_prev_match();
+        assert_eq!(state.cursor.match_index, Some(0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn summary_text() -> orfail::Result<()> {
+        let mut local_state = AppState {
+            search_result: SearchResult::parse(
+                "a.rs\n1:foo foo\nb.rs\n2:foo\n",
+                Highlight::parse("a.rs\n1:foo\n1:foo\nb.rs\n2:foo\n").or_fail()?,
+            )
+            .or_fail()?,
+            ..Default::default()
+        };
+        local_state.grep.pattern.text = "foo".to_owned();
+        assert_eq!(
+            local_state.summary_text(),
+            "3 hits across 2 files for `foo`"
+        );
+
+        // The searched pattern is used while another one is being edited.
+        local_state.current_grep = Some(local_state.grep.clone());
+        local_state.grep.pattern.text = "bar".to_owned();
+        local_state.search_result = SearchResult::default();
+        assert_eq!(
+            local_state.summary_text(),
+            "0 hits across 0 files for `foo`"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn background_search_completion() -> orfail::Result<()> {
+        let mut local_state = state("a.rs\n1:foo\n")?;
+        local_state.grep.pattern.text = "foo".to_owned();
+        local_state.grep.grep_command = [
+            "-c",
+            "alias.canned=!f() { printf 'b.rs\\n1:foo\\n2:foo\\n'; }; f",
+            "canned",
+        ]
+        .map(str::to_owned)
+        .to_vec();
+        assert!(local_state.readable_fds().is_empty());
+
+        // `handle_fd_ready()` waits for the search if the fd is not actually ready yet.
+        local_state.start_background_search()?;
+        let fds = local_state.readable_fds();
+        assert_eq!(fds.len(), 1);
+        assert!(local_state.handle_fd_ready(fds[0])?);
+        assert!(local_state.background_search.is_none());
+        assert_eq!(local_state.search_result.hit_lines(), 2);
+        assert_eq!(local_state.cursor.file, Some(PathBuf::from("b.rs")));
+        assert!(local_state.handle_fd_ready(fds[0]).is_err());
+
+        // The result of an outdated query is not applied.
+        local_state.start_background_search()?;
+        local_state.grep.pattern.text = "bar".to_owned();
+        let fds = local_state.readable_fds();
+        assert!(local_state.handle_fd_ready(fds[0])?);
+        assert!(local_state.stale);
+        assert_eq!(local_state.search_result.hit_lines(), 2);
+
+        // Nor is that of a search with other context lines.
+        local_state.grep.pattern.text = "foo".to_owned();
+        local_state.start_background_search()?;
+        local_state.grep.context_lines.0 += 1;
+        let fds = local_state.readable_fds();
+        assert!(local_state.handle_fd_ready(fds[0])?);
+        assert!(local_state.stale);
+
+        // A synchronous search supersedes the running one.
+        local_state.start_background_search()?;
+        local_state.regrep()?;
+        assert!(local_state.background_search.is_none());
+        assert!(local_state.readable_fds().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn full_file_expansion() -> orfail::Result<()> {
+        let mut local_state = state("a.rs\n1:foo\nb.rs\n1:foo\n")?;
+        local_state.cursor_right();
+        assert!(!local_state.is_cursor_file_expanded());
+
+        assert!(local_state.expand_file(PathBuf::from("a.rs"), "foo\nbar\n"));
+        assert!(local_state.is_cursor_file_expanded());
+
+        // The expansion belongs to the file, not to the cursor.
+        local_state.cursor_down();
+        assert!(!local_state.is_cursor_file_expanded());
+        local_state.cursor_up();
+        assert!(local_state.is_cursor_file_expanded());
+
+        // Too large files are not expanded and the current expansion is kept.
+        let text = "x\n".repeat(MAX_EXPANDED_FILE_LINES + 1);
+        assert!(!local_state.expand_file(PathBuf::from("b.rs"), &text));
+        assert!(local_state.is_cursor_file_expanded());
+
+        local_state.expanded_file = None;
+        assert!(!local_state.is_cursor_file_expanded());
+        Ok(())
+    }
+
+    #[test]
+    fn word_extraction() {
+        assert_eq!(word_in("let foo_bar = 1;", 4..7), Some("foo_bar"));
+        assert_eq!(word_in("let foo_bar = 1;", 8..11), Some("foo_bar"));
+        assert_eq!(word_in("call(arg)", 4..8), Some("arg"));
+        assert_eq!(word_in("a + b", 1..3), None);
+        assert_eq!(word_in("über straße", 6..9), Some("straße"));
+
+        // An empty range is treated as the char at its position.
+        assert_eq!(word_in("foo bar", 4..4), Some("bar"));
+    }
+
+    #[test]
+    fn escape_recenters_while_scrolled() -> orfail::Result<()> {
+        let config = crate::config::load_default_config().or_fail()?;
+        let bindings = config
+            .get_bindings(&BindingContextName::new("@browse"))
+            .or_fail()?;
+        let key = |code| {
+            tuinix::TerminalInput::Key(tuinix::KeyInput {
+                ctrl: false,
+                alt: false,
+                code,
+            })
+        };
+        let action = |state: &AppState, code| {
+            find_binding(bindings, key(code), state).and_then(|b| b.action.clone())
+        };
+
+        let mut local_state = AppState::default();
+        let escape = tuinix::KeyCode::Escape;
+        let q = tuinix::KeyCode::Char('q');
+        assert!(matches!(action(&local_state, escape), Some(Action::Quit)));
+
+        // Only `<ESCAPE>` re-centers; `q` still quits.
+        local_state.scroll_offset = Some(3);
+        assert!(matches!(
+            action(&local_state, escape),
+            Some(Action::Recenter)
+        ));
+        assert!(matches!(action(&local_state, q), Some(Action::Quit)));
+        Ok(())
+    }
+
+    #[test]
+    fn exit_command() {
+        let mut grep = GrepOptions::default();
+        grep.pattern.text = "foo bar".to_owned();
+        assert_eq!(
+            exit_command_line(&grep, true).as_deref(),
+            Some("git grep -nI 'foo bar'")
+        );
+        assert_eq!(exit_command_line(&grep, false), None);
+
+        // The command is printed to be run where mamegrep was started.
+        grep.repo_dir = Some(PathBuf::from("../other repo"));
+        assert_eq!(
+            exit_command_line(&grep, true).as_deref(),
+            Some("git -C '../other repo' grep -nI 'foo bar'")
+        );
+    }
+
+    #[test]
+    fn identifier_extraction() {
+        assert_eq!(identifier_in("self.foo_bar(1)", 5..8), Some("foo_bar"));
+        assert_eq!(identifier_in("self.foo_bar(1)", 0..12), Some("self"));
+        assert_eq!(identifier_in("x = 1.5 + y2", 4..7), None);
+        assert_eq!(identifier_in("x = 1.5 + y2", 4..12), Some("y2"));
+        assert_eq!(identifier_in("a + b", 1..3), None);
+        assert_eq!(identifier_in("über", 2..2), Some("über"));
+    }
+
+    #[test]
+    fn find_symbol() -> orfail::Result<()> {
+        let mut local_state = AppState {
+            search_result: SearchResult::parse(
+                "a.rs\n1:let x = foo.bar_baz();\n",
+                Highlight::parse("a.rs\n1:bar\n").or_fail()?,
+            )
+            .or_fail()?,
+            ..Default::default()
+        };
+        local_state.grep.pattern.text = "bar".to_owned();
+        local_state.grep.ignore_case = true;
+        assert!(!local_state.find_symbol());
+
+        local_state.cursor.file = Some(PathBuf::from("a.rs"));
+        local_state.cursor.line_number = NonZeroUsize::new(1);
+        assert!(local_state.find_symbol());
+        assert_eq!(local_state.grep.pattern.text, "bar_baz");
+        assert!(local_state.grep.word_regexp);
+        assert!(!local_state.grep.ignore_case);
+        Ok(())
+    }
+
+    #[test]
+    fn refine_with_word_under_cursor() -> orfail::Result<()> {
+        let mut local_state = AppState {
+            search_result: SearchResult::parse(
+                "a.rs\n1:let foo_bar = baz;\n",
+                Highlight::parse("a.rs\n1:foo\n1:baz\n").or_fail()?,
+            )
+            .or_fail()?,
+            ..Default::default()
+        };
+        local_state.grep.pattern.text = "foo|baz".to_owned();
+        assert!(!local_state.refine_with_word_under_cursor());
+
+        local_state.cursor.file = Some(PathBuf::from("a.rs"));
+        local_state.cursor.line_number = NonZeroUsize::new(1);
+        assert_eq!(local_state.word_under_cursor(), Some("foo_bar"));
+        local_state.cursor.match_index = Some(1);
+        assert_eq!(local_state.word_under_cursor(), Some("baz"));
+
+        assert!(local_state.refine_with_word_under_cursor());
+        assert_eq!(local_state.grep.pattern.text, "baz");
+
+        local_state.settings.refine_mode = RefineMode::And;
+        local_state.cursor.match_index = Some(0);
+        assert!(local_state.refine_with_word_under_cursor());
+        assert_eq!(local_state.grep.pattern.text, "baz");
+        assert_eq!(local_state.grep.and_pattern.text, "foo_bar");
+        Ok(())
+    }
+
+    #[test]
+    fn search_whole_line() -> orfail::Result<()> {
+        let mut local_state = AppState {
+            search_result: SearchResult::parse(
+                "a.rs\n1:    let x = (a + b);\n2:\n",
+                Highlight::default(),
+            )
+            .or_fail()?,
+            ..Default::default()
+        };
+        local_state.grep.pattern.text = "let".to_owned();
+        local_state.grep.extended_regexp = true;
+        assert!(!local_state.search_whole_line());
+
+        local_state.cursor.file = Some(PathBuf::from("a.rs"));
+        local_state.cursor.line_number = NonZeroUsize::new(1);
+        assert!(local_state.search_whole_line());
+        assert_eq!(local_state.grep.pattern.text, "let x = (a + b);");
+        assert!(local_state.grep.fixed_strings);
+        assert!(!local_state.grep.extended_regexp);
+
+        local_state.settings.trim_whole_line_search = false;
+        assert!(local_state.search_whole_line());
+        assert_eq!(local_state.grep.pattern.text, "    let x = (a + b);");
+
+        // Blank lines are not searched.
+        local_state.cursor.line_number = NonZeroUsize::new(2);
+        assert!(!local_state.search_whole_line());
+        assert_eq!(local_state.grep.pattern.text, "    let x = (a + b);");
+        Ok(())
+    }
+
+    #[test]
+    fn result_cache() -> orfail::Result<()> {
+        // The stubbed grep prints the content of a file that is changed behind the cache.
+        let output = std::env::temp_dir().join(format!("mamegrep-cache-{}", std::process::id()));
+        std::fs::write(&output, "a.rs\n1:foo\n").or_fail()?;
+        let mut local_state = AppState::default();
+        local_state.grep.pattern.text = "foo".to_owned();
+        local_state.grep.grep_command = vec![
+            "-c".to_owned(),
+            format!("alias.canned=!f() {{ cat '{}'; }}; f", output.display()),
+            "canned".to_owned(),
+        ];
+        local_state.regrep().or_fail()?;
+        assert_eq!(local_state.search_result.hit_lines(), 1);
+        assert_eq!(local_state.result_cache.len(), 1);
+
+        // Hit.
+        std::fs::write(&output, "a.rs\n1:foo\n2:foo\n").or_fail()?;
+        local_state.regrep().or_fail()?;
+        assert_eq!(local_state.search_result.hit_lines(), 1);
+
+        // Miss and then hit after toggling a flag back and forth.
+        local_state.grep.ignore_case = true;
+        local_state.regrep().or_fail()?;
+        assert_eq!(local_state.search_result.hit_lines(), 2);
+        local_state.grep.ignore_case = false;
+        local_state.regrep().or_fail()?;
+        assert_eq!(local_state.search_result.hit_lines(), 1);
+        assert_eq!(local_state.result_cache.len(), 2);
+
+        // Invalidation.
+        local_state.refresh().or_fail()?;
+        assert_eq!(local_state.search_result.hit_lines(), 2);
+        assert_eq!(local_state.result_cache.len(), 1);
+
+        // The number of the cached results is bounded.
+        for n in 0..10 {
+            local_state.grep.context_lines = ContextLines(n);
+            local_state.regrep().or_fail()?;
+        }
+        assert_eq!(local_state.result_cache.len(), 8);
+
+        std::fs::remove_file(&output).or_fail()?;
+        Ok(())
+    }
+
+    #[test]
+    fn revision_comparison() -> orfail::Result<()> {
+        // The stubbed grep prints one hit for the revision and two for the working tree.
+        let script = std::env::temp_dir().join(format!("mamegrep-compare-{}", std::process::id()));
+        std::fs::write(
+            &script,
+            concat!(
+                "case \"$*\" in\n",
+                "*--column*HEAD*) printf 'HEAD:a.rs\\n1:1:foo\\n' ;;\n",
+                "*--column*) printf 'a.rs\\n1:1:foo\\n2:1:foo\\n' ;;\n",
+                "*HEAD*) printf 'HEAD:a.rs\\n1:foo\\n' ;;\n",
+                "*) printf 'a.rs\\n1:foo\\n2:foo\\n' ;;\n",
+                "esac\n"
+            ),
+        )
+        .or_fail()?;
+        let mut local_state = AppState::default();
+        local_state.grep.pattern.text = "foo".to_owned();
+        local_state.grep.grep_command = vec![
+            "-c".to_owned(),
+            format!("alias.canned=!sh '{}'", script.display()),
+            "canned".to_owned(),
+        ];
+
+        // Not compared without a revision.
+        local_state.toggle_revision_comparison();
+        assert_eq!(local_state.revision_comparison, None);
+
+        local_state.grep.revision.text = "HEAD".to_owned();
+        local_state.regrep().or_fail()?;
+        assert_eq!(local_state.revision_comparison, Some((1, 2)));
+        // Both results are cached.
+        assert_eq!(local_state.result_cache.len(), 2);
+
+        local_state.toggle_revision_comparison();
+        assert_eq!(local_state.revision_comparison, None);
+        local_state.toggle_revision_comparison();
+        assert_eq!(local_state.revision_comparison, Some((1, 2)));
+
+        std::fs::remove_file(&script).or_fail()?;
+        Ok(())
+    }
+
+    #[test]
+    fn context_only_files() -> orfail::Result<()> {
+        let mut local_state = state("a.rs\n1-ctx\n2-ctx\nb.rs\n3:foo\n")?;
+        let files = |s: &AppState| {
+            s.shown_files()
+                .map(|(file, _)| file.display().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(files(&local_state), ["a.rs", "b.rs"]);
+
+        local_state.toggle_context_only_files();
+        assert_eq!(files(&local_state), ["b.rs"]);
+        assert_eq!(local_state.cursor.file, Some(PathBuf::from("b.rs")));
+
+        // Files whose hits are all filtered out are hidden regardless of the toggle.
+        local_state.toggle_context_only_files();
+        local_state.set_min_match_len(10);
+        assert_eq!(files(&local_state), ["a.rs"]);
+        Ok(())
+    }
+
+    #[test]
+    fn cursor_moves_across_context_only_files() -> orfail::Result<()> {
+        let mut local_state = state("a.rs\n1:foo\nb.rs\n2-ctx\n3-ctx\nc.rs\n4:foo\n")?;
+        local_state.cursor_right();
+        assert_eq!(local_state.cursor.line_number, NonZeroUsize::new(1));
+
+        // The context-only file is skipped by the line-level moves.
+        local_state.cursor_down();
+        assert_eq!(local_state.cursor.file, Some(PathBuf::from("c.rs")));
+        assert_eq!(local_state.cursor.line_number, NonZeroUsize::new(4));
+        assert!(!local_state.can_cursor_down());
+
+        local_state.cursor_up();
+        assert_eq!(local_state.cursor.file, Some(PathBuf::from("a.rs")));
+        assert_eq!(local_state.cursor.line_number, NonZeroUsize::new(1));
+        assert!(!local_state.can_cursor_up());
+
+        // It is still reachable at the file level.
+        local_state.cursor_left();
+        local_state.cursor_down();
+        assert_eq!(local_state.cursor.file, Some(PathBuf::from("b.rs")));
+        Ok(())
+    }
+
+    #[test]
+    fn cursor_move_by_steps() -> orfail::Result<()> {
+        let mut local_state = state("a.rs\n1:x\n2:x\n3:x\n4:x\nb.rs\n5:x\n")?;
+        local_state.cursor_right();
+        assert_eq!(local_state.cursor.line_number, NonZeroUsize::new(1));
+
+        local_state.cursor_down_by(2);
+        assert_eq!(local_state.cursor.line_number, NonZeroUsize::new(3));
+
+        // Clamped at the last line of the result.
+        local_state.cursor_down_by(5);
+        assert_eq!(local_state.cursor.file, Some(PathBuf::from("b.rs")));
+        assert_eq!(local_state.cursor.line_number, NonZeroUsize::new(5));
+        assert!(!local_state.can_cursor_down());
+
+        local_state.cursor_up_by(3);
+        assert_eq!(local_state.cursor.line_number, NonZeroUsize::new(2));
+        local_state.cursor_up_by(10);
+        assert_eq!(local_state.cursor.line_number, NonZeroUsize::new(1));
+        assert!(!local_state.can_cursor_up());
+
+        // File-level steps.
+        local_state.cursor_left();
+        local_state.cursor_down_by(3);
+        assert_eq!(local_state.cursor.file, Some(PathBuf::from("b.rs")));
+        assert!(local_state.cursor.is_file_level());
+        Ok(())
+    }
+
+    #[test]
+    fn hottest_file_cycling() -> orfail::Result<()> {
+        let mut local_state = AppState {
+            search_result: SearchResult::parse(
+                "a.rs\n1:x\nb.rs\n1:x\n2:x\n3:x\nc.rs\n1:x\n2:x\nd.rs\n1:x\n",
+                Highlight::parse("a.rs\n1:x\nb.rs\n1:x\n2:x\n3:x\nc.rs\n1:x\n2:x\nd.rs\n1:x\n")
+                    .or_fail()?,
+            )
+            .or_fail()?,
+            ..Default::default()
+        };
+        local_state.reset_cursor();
+        let mut jump = || {
+            local_state.jump_to_hottest_file();
+            local_state.cursor.file.clone().unwrap_or_default()
+        };
+        assert_eq!(jump(), PathBuf::from("b.rs"));
+        assert_eq!(jump(), PathBuf::from("c.rs"));
+        // `a.rs` and `d.rs` have the same count, so they are visited in the display order.
+        assert_eq!(jump(), PathBuf::from("a.rs"));
+        assert_eq!(jump(), PathBuf::from("d.rs"));
+        assert_eq!(jump(), PathBuf::from("b.rs"));
+
+        // The traversal restarts for a new result, skipping the focused file.
+        local_state.apply_search_result(Ok(local_state.search_result.clone()))?;
+        local_state.cursor.file = Some(PathBuf::from("b.rs"));
+        local_state.jump_to_hottest_file();
+        assert_eq!(local_state.cursor.file, Some(PathBuf::from("c.rs")));
+        Ok(())
+    }
+
+    #[test]
+    fn single_hit_detection() -> orfail::Result<()> {
+        let local_state = state("a.rs\n1-x\n2:foo\n3-x\n")?;
+        assert_eq!(
+            local_state.single_hit_line(),
+            Some((&PathBuf::from("a.rs"), NonZeroUsize::MIN.saturating_add(1)))
+        );
+
+        let local_state = state("a.rs\n2:foo\nb.rs\n3:foo\n")?;
+        assert_eq!(local_state.single_hit_line(), None);
+
+        let local_state = state("")?;
+        assert_eq!(local_state.single_hit_line(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn lazy_context() -> orfail::Result<()> {
+        let dir = std::env::temp_dir();
+        let file = dir.join(format!("mamegrep-lazy-{}.txt", std::process::id()));
+        std::fs::write(&file, "a\nb\nfoo\nd\ne\n").or_fail()?;
+        // The stubbed grep prints the context only if `-C 0` is not given.
+        let script = dir.join(format!("mamegrep-lazy-{}.sh", std::process::id()));
+        std::fs::write(
+            &script,
+            format!(
+                concat!(
+                    "case \"$*\" in\n",
+                    "*--column*) printf '{0}\\n3:1:foo\\n' ;;\n",
+                    "*'-C 0'*) printf '{0}\\n3:foo\\n' ;;\n",
+                    "*) printf '{0}\\n2-b\\n3:foo\\n4-d\\n' ;;\n",
+                    "esac\n"
+                ),
+                file.display()
+            ),
+        )
+        .or_fail()?;
+        let mut local_state = AppState::default();
+        local_state.grep.pattern.text = "foo".to_owned();
+        local_state.grep.context_lines = ContextLines(1);
+        local_state.grep.grep_command = vec![
+            "-c".to_owned(),
+            format!("alias.canned=!sh '{}'", script.display()),
+            "canned".to_owned(),
+        ];
+        let lines = |local_state: &AppState| {
+            local_state.search_result.files[&file]
+                .iter()
+                .map(|l| (l.number.get(), l.hit))
+                .collect::<Vec<_>>()
+        };
+
+        local_state.regrep().or_fail()?;
+        assert_eq!(lines(&local_state), [(2, false), (3, true), (4, false)]);
+
+        local_state.grep.lazy_context = true;
+        local_state.regrep().or_fail()?;
+        assert!(local_state.grep.parsing_command_line().contains("-C 0"));
+        assert_eq!(lines(&local_state), [(3, true)]);
+
+        // The context is read from the file once the cursor is on the line.
+        local_state.cursor.file = Some(file.clone());
+        local_state.cursor.line_number = NonZeroUsize::new(3);
+        local_state.load_cursor_context();
+        assert_eq!(lines(&local_state), [(2, false), (3, true), (4, false)]);
+        assert!(local_state.file_contents.contains_key(&file));
+
+        std::fs::remove_file(&file).or_fail()?;
+        std::fs::remove_file(&script).or_fail()?;
+        Ok(())
+    }
+
+    #[test]
+    fn min_match_len_filter() -> orfail::Result<()> {
+        let output = "a.rs\n1:x\n2:xyz\n3:y\nb.rs\n1:z\nc.rs\n4:abc\n";
+        let mut local_state = AppState {
+            search_result: SearchResult::parse(output, Highlight::parse(output).or_fail()?)
+                .or_fail()?,
+            ..Default::default()
+        };
+        local_state.reset_cursor();
+        local_state.cursor_right();
+        local_state.cursor_down();
+        local_state.cursor_down();
+        assert_eq!(local_state.cursor.line_number, NonZeroUsize::new(3));
+        assert_eq!(local_state.shown_hit_counts(), (5, 5, 3));
+
+        // Raising the threshold hides short hits and moves the cursor to a shown line.
+        local_state.set_min_match_len(2);
+        assert_eq!(local_state.cursor.file, Some(PathBuf::from("a.rs")));
+        assert_eq!(local_state.cursor.line_number, NonZeroUsize::new(2));
+        assert_eq!(local_state.shown_hit_counts(), (2, 2, 2));
+
+        // Moving down skips lines and files without a long enough match.
+        local_state.cursor_down();
+        assert_eq!(local_state.cursor.file, Some(PathBuf::from("c.rs")));
+        assert_eq!(local_state.cursor.line_number, NonZeroUsize::new(4));
+        local_state.cursor_down();
+        assert_eq!(local_state.cursor.line_number, NonZeroUsize::new(4));
+
+        local_state.set_min_match_len(0);
+        assert_eq!(local_state.shown_hit_counts(), (5, 5, 3));
+        Ok(())
     }
 }
@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use orfail::OrFail;
+
+use crate::git::{CallTimings, GrepOptions};
+
+const PERCENTILES: [usize; 4] = [50, 90, 99, 100];
+
+type Phase = (&'static str, fn(&CallTimings) -> Duration);
+
+// Runs the search `iterations` times and returns the timing percentiles (in seconds) as a JSON object.
+pub fn run(options: &GrepOptions, iterations: usize) -> orfail::Result<String> {
+    let mut timings = Vec::with_capacity(iterations);
+    for _ in 0..iterations {
+        let result = options.call().or_fail()?;
+        if let Some(error) = result.error {
+            return Err(orfail::Failure::new(error));
+        }
+        timings.push(result.timings);
+    }
+    Ok(report(&timings))
+}
+
+fn report(timings: &[CallTimings]) -> String {
+    let phases: [Phase; 3] = [
+        ("grep", |t| t.grep),
+        ("parse", |t| t.parse),
+        ("highlight_parse", |t| t.highlight_parse),
+    ];
+    nojson::object(|f| {
+        f.member("iterations", timings.len())?;
+        for (name, phase) in phases {
+            let mut durations = timings.iter().map(phase).collect::<Vec<_>>();
+            durations.sort();
+            f.member(
+                name,
+                nojson::object(|f| {
+                    for p in PERCENTILES {
+                        f.member(format!("p{p}"), percentile(&durations, p).as_secs_f64())?;
+                    }
+                    Ok(())
+                }),
+            )?;
+        }
+        Ok(())
+    })
+    .to_string()
+}
+
+// Nearest-rank percentile of sorted durations.
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    let rank = (sorted.len() * p).div_ceil(100).max(1);
+    sorted.get(rank - 1).copied().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let durations = (1..=10).map(Duration::from_secs).collect::<Vec<_>>();
+        assert_eq!(percentile(&durations, 50), Duration::from_secs(5));
+        assert_eq!(percentile(&durations, 90), Duration::from_secs(9));
+        assert_eq!(percentile(&durations, 99), Duration::from_secs(10));
+        assert_eq!(percentile(&durations, 100), Duration::from_secs(10));
+        assert_eq!(percentile(&[], 50), Duration::ZERO);
+
+        let timings = [
+            CallTimings {
+                grep: Duration::from_millis(500),
+                ..Default::default()
+            },
+            CallTimings {
+                grep: Duration::from_millis(250),
+                ..Default::default()
+            },
+        ];
+        assert_eq!(
+            report(&timings),
+            concat!(
+                r#"{"iterations":2,"#,
+                r#""grep":{"p50":0.25,"p90":0.5,"p99":0.5,"p100":0.5},"#,
+                r#""parse":{"p50":0,"p90":0,"p99":0,"p100":0},"#,
+                r#""highlight_parse":{"p50":0,"p90":0,"p99":0,"p100":0}}"#
+            )
+        );
+    }
+}
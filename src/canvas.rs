@@ -1,6 +1,9 @@
-use std::{collections::VecDeque, fmt::Write, num::NonZeroUsize};
+use std::{borrow::Cow, collections::VecDeque, fmt::Write, num::NonZeroUsize};
 
 use tuinix::{TerminalPosition, TerminalSize, TerminalStyle};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::settings::ControlChars;
 
 #[derive(Debug)]
 pub struct Canvas {
@@ -32,6 +35,10 @@ impl Canvas {
         }
     }
 
+    pub fn row_offset(&self) -> usize {
+        self.row_offset
+    }
+
     pub fn cursor(&self) -> TerminalPosition {
         self.cursor
     }
@@ -79,6 +86,30 @@ impl Canvas {
         line.split_off(self.frame.size.cols);
     }
 
+    // The line at the (absolute) row, if it is in the frame.
+    pub fn frame_line(&self, row: usize) -> Option<&FrameLine> {
+        row.checked_sub(self.row_offset)
+            .and_then(|i| self.frame.lines.get(i))
+    }
+
+    // Like `drawln()` for a whole line rendered beforehand (at the start of a row), scrolling as usual.
+    pub fn drawln_frame_line(&mut self, line: FrameLine) {
+        if self.cursor.row >= self.row_offset {
+            let overflow = (self.cursor.row - self.row_offset).checked_sub(self.frame.size.rows);
+            match overflow {
+                Some(n) if self.auto_scroll => self.scroll(n + 1),
+                Some(_) => {
+                    self.newline();
+                    return;
+                }
+                None => {}
+            }
+            let i = self.cursor.row - self.row_offset;
+            self.frame.lines[i] = line;
+        }
+        self.newline();
+    }
+
     pub fn draw_frame_line(&mut self, line: FrameLine) {
         if self.cursor.row < self.frame.lines.len() {
             self.frame.lines[self.cursor.row] = line;
@@ -121,6 +152,21 @@ impl Frame {
         self.lines.into_iter()
     }
 
+    // Positions of the tokens that have hyperlinks.
+    pub fn links(&self) -> Vec<(TerminalPosition, Token)> {
+        let mut links = Vec::new();
+        for (row, line) in self.lines.iter().enumerate() {
+            let mut col = 0;
+            for token in &line.tokens {
+                if token.link.is_some() {
+                    links.push((TerminalPosition::row_col(row, col), token.clone()));
+                }
+                col += token.cols();
+            }
+        }
+        links
+    }
+
     pub fn into_terminal_frame(self) -> mame::terminal::UnicodeTerminalFrame {
         let mut frame = mame::terminal::UnicodeTerminalFrame::new(self.size);
         for line in self.into_lines() {
@@ -197,6 +243,8 @@ impl FrameLine {
 pub struct Token {
     text: String,
     style: TerminalStyle,
+    // OSC 8 hyperlink target, which is written separately as `TerminalFrame` only accepts SGR sequences.
+    link: Option<String>,
 }
 
 impl Token {
@@ -212,36 +260,43 @@ impl Token {
         self.style
     }
 
+    pub fn link(&self) -> Option<&str> {
+        self.link.as_deref()
+    }
+
+    pub fn with_link(mut self, link: Option<String>) -> Self {
+        self.link = link;
+        self
+    }
+
     pub fn with_style(text: impl Into<String>, style: TerminalStyle) -> Self {
         let mut text = text.into();
-        if text.chars().any(|c| c.is_control()) {
-            let mut escaped_text = String::new();
-            for c in text.chars() {
-                if c.is_control() {
-                    escaped_text.extend(c.escape_default());
-                } else {
-                    escaped_text.push(c);
-                }
-            }
+        // Callers that want another policy apply it beforehand.
+        if let Cow::Owned(escaped_text) = ControlChars::Escape.apply(&text) {
             text = escaped_text;
         }
-        Self { text, style }
+        Self {
+            text,
+            style,
+            link: None,
+        }
     }
 
+    // Splits at grapheme cluster boundaries so that combining marks and ZWJ sequences are kept intact.
     pub fn split_prefix_off(&mut self, col: usize) -> Self {
         let mut acc_cols = 0;
-        for (i, c) in self.text.char_indices() {
+        for (i, g) in self.text.grapheme_indices(true) {
             if acc_cols == col {
                 let suffix = self.text.split_off(i);
                 return std::mem::replace(self, Self::with_style(suffix, self.style));
             }
 
-            let next_acc_cols = acc_cols + mame::terminal::char_cols(c);
+            let next_acc_cols = acc_cols + mame::terminal::str_cols(g);
             if next_acc_cols > col {
-                // Not a char boundary.
-                let suffix = self.text.split_off(i + c.len_utf8());
+                // The column is inside a grapheme, whose columns are filled with `…` on both sides.
+                let suffix = "…".repeat(next_acc_cols - col) + &self.text[i + g.len()..];
                 let suffix = Self::with_style(suffix, self.style);
-                let _ = self.text.pop();
+                self.text.truncate(i);
                 for _ in acc_cols..col {
                     self.text.push('…');
                 }
@@ -284,4 +339,50 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn split_at_grapheme_boundaries() {
+        // A combining accent stays with its base char.
+        let mut token = Token::new("ae\u{301}b");
+        let prefix = token.split_prefix_off(2);
+        assert_eq!(prefix.text(), "ae\u{301}");
+        assert_eq!(token.text(), "b");
+
+        let mut token = Token::new("ae\u{301}b");
+        let prefix = token.split_prefix_off(1);
+        assert_eq!(prefix.text(), "a");
+        assert_eq!(token.text(), "e\u{301}b");
+
+        // A ZWJ emoji sequence is never split, and is replaced by `…` when cut in the middle.
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let mut token = Token::new(format!("a{family}b"));
+        assert_eq!(token.cols(), 4);
+        let prefix = token.split_prefix_off(2);
+        assert_eq!(prefix.text(), "a…");
+        assert_eq!(token.text(), "…b");
+
+        let mut token = Token::new(format!("a{family}b"));
+        let prefix = token.split_prefix_off(3);
+        assert_eq!(prefix.text(), format!("a{family}"));
+        assert_eq!(token.text(), "b");
+
+        // Same through `FrameLine`.
+        let mut line = FrameLine::new();
+        line.draw_token(0, Token::new(format!("xe\u{301}{family}y")));
+        line.draw_token(3, Token::new("z"));
+        assert_eq!(line.text(), "xe\u{301}…zy");
+        assert_eq!(line.cols(), 5);
+    }
+
+    #[test]
+    fn linked_token_width() {
+        let token =
+            Token::new("src/日本.rs").with_link(Some("file:///src/%E6%97%A5.rs".to_owned()));
+        assert_eq!(token.cols(), "src/".len() + 4 + ".rs".len());
+
+        let mut frame = Frame::new(TerminalSize::rows_cols(2, 40));
+        frame.lines[1].draw_token(0, Token::new("1# "));
+        frame.lines[1].draw_token(3, token.clone());
+        assert_eq!(frame.links(), [(TerminalPosition::row_col(1, 3), token)]);
+    }
 }
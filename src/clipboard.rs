@@ -0,0 +1,189 @@
+use std::{
+    io::Write,
+    os::fd::BorrowedFd,
+    path::{Path, PathBuf},
+};
+
+use tuinix::Terminal;
+
+// Terminals drop (or choke on) OSC 52 sequences beyond some length, so larger texts are written to a file instead.
+pub const MAX_ENCODED_BYTES: usize = 1_000_000;
+
+// Large sequences are written in pieces so that a slow terminal does not stall a single huge write.
+const CHUNK_BYTES: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Copied {
+    Clipboard,
+    // The text was too large for OSC 52 and was written to this file.
+    File(PathBuf),
+}
+
+// All yanks go through here.
+pub fn copy(terminal: &mut Terminal, text: &str) -> std::io::Result<Copied> {
+    let encoded = base64_encode(text.as_bytes());
+    if encoded.len() > MAX_ENCODED_BYTES {
+        // `$XDG_RUNTIME_DIR` is private to the user, unlike the shared temporary directory.
+        let dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_dir())
+            .unwrap_or_else(std::env::temp_dir);
+        let path = write_private_file(&dir, text)?;
+        return Ok(Copied::File(path));
+    }
+    // `Terminal` flushes each frame as a whole, so the sequence written to its output
+    // (while it is borrowed here) does not interleave with a frame.
+    // SAFETY: the fd stays open while `terminal` is alive, and it is only duplicated here.
+    let fd = unsafe { BorrowedFd::borrow_raw(terminal.output_fd()) };
+    let mut output = std::fs::File::from(fd.try_clone_to_owned()?);
+    write_osc52(&mut output, &encoded)?;
+    Ok(Copied::Clipboard)
+}
+
+// The file is created anew (never following an existing file or symlink) and readable only by the user.
+fn write_private_file(dir: &Path, text: &str) -> std::io::Result<PathBuf> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    for n in 0usize.. {
+        let path = dir.join(format!("mamegrep-clipboard-{}-{n}.txt", std::process::id()));
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path);
+        match file {
+            Ok(mut file) => {
+                file.write_all(text.as_bytes())?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!("the suffixes are exhausted")
+}
+
+fn write_osc52<W: Write>(writer: &mut W, encoded: &str) -> std::io::Result<()> {
+    writer.write_all(b"\x1b]52;c;")?;
+    for chunk in chunks(encoded, CHUNK_BYTES) {
+        writer.write_all(chunk.as_bytes())?;
+    }
+    writer.write_all(b"\x07")?;
+    writer.flush()
+}
+
+// The base64 text is ASCII, so it can be split at any byte.
+fn chunks(encoded: &str, size: usize) -> impl Iterator<Item = &str> {
+    encoded
+        .as_bytes()
+        .chunks(size)
+        .map(|chunk| std::str::from_utf8(chunk).expect("base64 is ASCII"))
+}
+
+// Whether the copy of `text` needs a confirmation before it is emitted.
+pub fn needs_confirmation(text: &str, confirm_bytes: usize) -> bool {
+    text.len() > confirm_bytes
+}
+
+// Human-readable size for the confirmation (e.g., `3.2 MB`).
+pub fn format_size(bytes: usize) -> String {
+    const UNITS: [&str; 3] = ["KB", "MB", "GB"];
+    if bytes < 1000 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while size >= 999.95 && unit + 1 < UNITS.len() {
+        size /= 1000.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, &b)| acc | (u32::from(b) << (16 - i * 8)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(TABLE[(n >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64_encode(&[0xff, 0xfe, 0x00]), "//4A");
+        assert_eq!(base64_encode("日本".as_bytes()), "5pel5pys");
+    }
+
+    #[test]
+    fn osc52_chunks() -> std::io::Result<()> {
+        assert_eq!(
+            chunks("abcdefg", 3).collect::<Vec<_>>(),
+            ["abc", "def", "g"]
+        );
+        assert_eq!(chunks("abc", 3).collect::<Vec<_>>(), ["abc"]);
+        assert_eq!(chunks("", 3).count(), 0);
+
+        // Chunking does not change the emitted sequence.
+        let encoded = base64_encode(&vec![b'x'; CHUNK_BYTES * 2]);
+        let mut output = Vec::new();
+        write_osc52(&mut output, &encoded)?;
+        assert_eq!(output, format!("\x1b]52;c;{encoded}\x07").into_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn confirmation() {
+        assert!(!needs_confirmation("abc", 3));
+        assert!(needs_confirmation("abcd", 3));
+        assert!(needs_confirmation("x", 0));
+
+        assert_eq!(format_size(999), "999 B");
+        assert_eq!(format_size(1000), "1.0 KB");
+        assert_eq!(format_size(3_200_000), "3.2 MB");
+        assert_eq!(format_size(999_999), "1.0 MB");
+        assert_eq!(format_size(5_000_000_000_000), "5000.0 GB");
+    }
+
+    #[test]
+    fn private_file() -> std::io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir =
+            std::env::temp_dir().join(format!("mamegrep-clipboard-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+
+        let first = write_private_file(&dir, "foo")?;
+        let second = write_private_file(&dir, "bar")?;
+        assert_ne!(first, second);
+        assert_eq!(std::fs::read_to_string(&first)?, "foo");
+        assert_eq!(std::fs::read_to_string(&second)?, "bar");
+        assert_eq!(
+            std::fs::metadata(&first)?.permissions().mode() & 0o777,
+            0o600
+        );
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
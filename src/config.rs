@@ -0,0 +1,103 @@
+use std::{collections::BTreeMap, path::Path};
+
+use mame::{action::BindingConfig, json::LoadJsonError};
+
+use crate::action::Action;
+
+pub const DEFAULT_CONFIG: &str = include_str!("../configs/default.jsonc");
+
+pub const REPO_CONFIG_FILE_NAME: &str = ".mamegrep.jsonc";
+
+pub fn load_default_config() -> Result<BindingConfig<Action>, LoadJsonError> {
+    BindingConfig::load_from_str("<DEFAULT>", DEFAULT_CONFIG)
+}
+
+// Loads a per-repository config merged over the default one.
+pub fn load_repo_config(path: &Path) -> Result<BindingConfig<Action>, LoadJsonError> {
+    let text = std::fs::read_to_string(path).map_err(|error| LoadJsonError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    let merged = merge_configs(DEFAULT_CONFIG, &text).map_err(|error| LoadJsonError::Json {
+        path: path.to_path_buf(),
+        text: text.clone(),
+        error,
+    })?;
+    BindingConfig::load_from_str(&path.display().to_string(), &merged)
+}
+
+// The `setup` and each context of the `bindings` in `repo_text` replace the ones in `default_text`.
+fn merge_configs(default_text: &str, repo_text: &str) -> Result<String, nojson::JsonParseError> {
+    let (default, _) = nojson::RawJson::parse_jsonc(default_text)?;
+    let (repo, _) = nojson::RawJson::parse_jsonc(repo_text)?;
+    let (default, repo) = (default.value(), repo.value());
+
+    let setup = match repo.to_member("setup")?.get() {
+        Some(setup) => setup,
+        None => default.to_member("setup")?.required()?,
+    };
+
+    let mut bindings = BTreeMap::new();
+    for config in [default, repo] {
+        let Some(contexts) = config.to_member("bindings")?.get() else {
+            continue;
+        };
+        for (name, value) in contexts.to_object()? {
+            bindings.insert(name.to_unquoted_string_str()?, (name, value));
+        }
+    }
+
+    let mut merged = format!("{{\"setup\": {}, \"bindings\": {{", setup.as_raw_str());
+    for (i, (name, value)) in bindings.values().enumerate() {
+        if i > 0 {
+            merged.push(',');
+        }
+        merged.push_str(&format!("{}: {}", name.as_raw_str(), value.as_raw_str()));
+    }
+    merged.push_str("}}");
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use mame::action::BindingContextName;
+    use orfail::OrFail;
+
+    use super::*;
+
+    #[test]
+    fn repo_config_overrides_default() -> orfail::Result<()> {
+        let repo = r#"{
+          // Replaces only the `@edit` context.
+          "bindings": {
+            "@edit": [{"label": "custom", "triggers": ["q"], "action": {"type": "quit"}}],
+            "@extra": [],
+          },
+        }"#;
+        let merged = merge_configs(DEFAULT_CONFIG, repo).or_fail()?;
+        let config: BindingConfig<Action> =
+            BindingConfig::load_from_str("<MERGED>", &merged).or_fail()?;
+
+        assert_eq!(config.initial_context().get(), "@browse");
+        assert!(config.setup_action().is_some());
+        let edit = config
+            .get_bindings(&BindingContextName::new("@edit"))
+            .or_fail()?;
+        assert_eq!(edit.len(), 1);
+        assert!(
+            config
+                .get_bindings(&BindingContextName::new("@browse"))
+                .or_fail()?
+                .len()
+                > 1
+        );
+        assert!(
+            config
+                .get_bindings(&BindingContextName::new("@extra"))
+                .is_some()
+        );
+
+        assert!(merge_configs(DEFAULT_CONFIG, "{").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use std::path::Path;
+
+use crate::git::Line;
+
+// OSC 52 payloads are limited by many terminals, so large results are truncated.
+pub const MAX_MARKDOWN_BYTES: usize = 64 * 1024;
+
+// Formats the lines as a `### path` heading followed by a fenced code block per file.
+// Hit lines are written as `N: text` and context lines as `N- text` (like `git grep`).
+pub fn markdown<'a, I>(files: I, max_bytes: usize) -> String
+where
+    I: IntoIterator<Item = (&'a Path, Vec<&'a Line>)>,
+{
+    let mut text = String::new();
+    let mut omitted_lines = 0;
+    for (path, lines) in files {
+        if omitted_lines > 0 {
+            omitted_lines += lines.len();
+            continue;
+        }
+
+        let fence = fence(&lines);
+        let lang = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        let mut block = format!("### {}\n\n{fence}{lang}\n", path.display());
+        let mut written = 0;
+        for line in &lines {
+            let delimiter = if line.hit { ':' } else { '-' };
+            let entry = format!("{}{delimiter} {}\n", line.number, line.text);
+            if text.len() + block.len() + entry.len() + fence.len() + 1 > max_bytes {
+                break;
+            }
+            block.push_str(&entry);
+            written += 1;
+        }
+        if written == 0 && !lines.is_empty() {
+            omitted_lines += lines.len();
+            continue;
+        }
+        omitted_lines += lines.len() - written;
+        block.push_str(&format!("{fence}\n\n"));
+        text.push_str(&block);
+    }
+
+    if omitted_lines > 0 {
+        text.push_str(&format!("_(truncated: {omitted_lines} more lines)_\n"));
+    }
+    text
+}
+
+// A backtick fence longer than any backtick run in the lines.
+fn fence(lines: &[&Line]) -> String {
+    let longest_run = lines
+        .iter()
+        .flat_map(|line| line.text.split(|c| c != '`'))
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    "`".repeat(longest_run.max(2) + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use orfail::OrFail;
+
+    use super::*;
+    use crate::git::{Highlight, SearchResult};
+
+    fn files(result: &SearchResult) -> Vec<(&Path, Vec<&Line>)> {
+        result
+            .files
+            .iter()
+            .map(|(path, lines)| (path.as_path(), lines.iter().collect()))
+            .collect()
+    }
+
+    #[test]
+    fn markdown_code_blocks() -> orfail::Result<()> {
+        let result = SearchResult::parse(
+            "src/a.rs\n1-use foo;\n2:fn foo() {}\nREADME\n3:run `foo`\n",
+            Highlight::default(),
+        )
+        .or_fail()?;
+        assert_eq!(
+            markdown(files(&result), MAX_MARKDOWN_BYTES),
+            concat!(
+                "### README\n\n```\n3: run `foo`\n```\n\n",
+                "### src/a.rs\n\n```rs\n1- use foo;\n2: fn foo() {}\n```\n\n",
+            )
+        );
+
+        let result = SearchResult::parse("a.md\n1:```\n", Highlight::default()).or_fail()?;
+        assert_eq!(
+            markdown(files(&result), MAX_MARKDOWN_BYTES),
+            "### a.md\n\n````md\n1: ```\n````\n\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn markdown_truncation() -> orfail::Result<()> {
+        let result =
+            SearchResult::parse("a.rs\n1:aaaa\n2:bbbb\nb.rs\n1:cccc\n", Highlight::default())
+                .or_fail()?;
+        assert_eq!(
+            markdown(files(&result), 30),
+            "### a.rs\n\n```rs\n1: aaaa\n```\n\n_(truncated: 2 more lines)_\n"
+        );
+        Ok(())
+    }
+}
@@ -1,39 +1,107 @@
-use std::{borrow::Cow, collections::BTreeMap, num::NonZeroUsize, path::PathBuf, process::Command};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    hash::{DefaultHasher, Hash, Hasher},
+    io::Read,
+    num::NonZeroUsize,
+    ops::Range,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    time::{Duration, Instant},
+};
 
 use orfail::OrFail;
+use unicode_segmentation::UnicodeSegmentation;
 
 use crate::app::Focus;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum QueryKind {
+    Ready,
+    // Only the and-pattern is given, which is searched as the pattern.
+    AndOnly,
+    // Only the not-pattern is given, which cannot be searched.
+    NotOnly,
+    Empty,
+}
+
 #[derive(Debug)]
-enum Mode {
+enum Mode<'a> {
     External,
     Parsing,
     Highlight,
+    // Highlighting the matches of a single pattern term.
+    TermHighlight(&'a str),
 }
 
+// Hit texts per line per file.
+pub type HitLines = BTreeMap<PathBuf, BTreeMap<NonZeroUsize, Vec<String>>>;
+
 #[derive(Debug, Default, Clone)]
 pub struct Highlight {
-    pub lines: BTreeMap<PathBuf, BTreeMap<NonZeroUsize, Vec<String>>>,
+    pub lines: HitLines,
+    // Per-term hit texts (in the order of the pattern terms), which are only set if `GrepOptions::highlight_terms` is enabled.
+    pub terms: Vec<(String, HitLines)>,
 }
 
 impl Highlight {
-    fn parse(s: &str) -> orfail::Result<Self> {
+    #[cfg(test)]
+    pub(crate) fn parse(s: &str) -> orfail::Result<Self> {
+        Self::parse_inner(s, false)
+    }
+
+    // Parses the output of `git grep -o --column`.
+    //
+    // The hit texts of each line are sorted by their columns,
+    // as `find_hit_ranges()` only searches forward from the previous hit.
+    pub(crate) fn parse_with_columns(s: &str) -> orfail::Result<Self> {
+        Self::parse_inner(s, true)
+    }
+
+    fn parse_inner(s: &str, columns: bool) -> orfail::Result<Self> {
         let mut lines = BTreeMap::<_, BTreeMap<_, Vec<_>>>::new();
         let mut current = PathBuf::new();
         for line in s.lines() {
             if let Some(m) = Line::parse(line) {
+                // A custom `grep-command` may ignore `--column`, so the column is optional.
+                let column = columns
+                    .then(|| m.text.split_once(':'))
+                    .flatten()
+                    .and_then(|(column, text)| Some((column.parse::<usize>().ok()?, text)));
+                let (column, text) = match column {
+                    Some((column, text)) => (column, text.to_owned()),
+                    None => (0, m.text),
+                };
                 lines
                     .get_mut(&current)
                     .or_fail()?
                     .entry(m.number)
                     .or_default()
-                    .push(m.text);
+                    .push((column, text));
             } else {
                 current = PathBuf::from(line);
                 lines.insert(current.clone(), BTreeMap::new());
             }
         }
-        Ok(Self { lines })
+
+        let lines = lines
+            .into_iter()
+            .map(|(file, file_lines)| {
+                let file_lines = file_lines
+                    .into_iter()
+                    .map(|(number, mut texts)| {
+                        // Stable, so that the output order is kept without columns.
+                        texts.sort_by_key(|(column, _)| *column);
+                        (number, texts.into_iter().map(|(_, text)| text).collect())
+                    })
+                    .collect();
+                (file, file_lines)
+            })
+            .collect();
+        Ok(Self {
+            lines,
+            terms: Vec::new(),
+        })
     }
 }
 
@@ -43,6 +111,62 @@ pub struct SearchResult {
     pub max_line_width: usize,
     pub highlight: Highlight,
     pub error: Option<String>,
+    pub duration: Duration,
+    // Display order of the files (empty means the path order).
+    pub file_order: Vec<PathBuf>,
+    // Last commit timestamps per worktree path, which are fetched once per search.
+    pub commit_times: Option<BTreeMap<String, i64>>,
+    // Breakdown of `duration` (only measured for content searches).
+    pub timings: CallTimings,
+}
+
+// Recently used search results (most recent first), so that toggling an option back and forth
+// does not re-run `git`. It must be cleared when the files may have been changed.
+#[derive(Debug, Default)]
+pub struct SearchCache {
+    entries: VecDeque<(u64, SearchResult)>,
+}
+
+impl SearchCache {
+    const CAPACITY: usize = 8;
+
+    // Larger results are not cached to bound the memory usage.
+    const MAX_LINES: usize = 10_000;
+
+    pub fn get(&mut self, key: u64) -> Option<SearchResult> {
+        let i = self.entries.iter().position(|(k, _)| *k == key)?;
+        let entry = self.entries.remove(i)?;
+        let result = entry.1.clone();
+        self.entries.push_front(entry);
+        Some(result)
+    }
+
+    pub fn insert(&mut self, key: u64, result: &SearchResult) {
+        self.entries.retain(|(k, _)| *k != key);
+        let lines = result.files.values().map(Vec::len).sum::<usize>();
+        if result.error.is_some() || lines > Self::MAX_LINES {
+            return;
+        }
+        self.entries.push_front((key, result.clone()));
+        self.entries.truncate(Self::CAPACITY);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CallTimings {
+    // The `git grep` call for the result lines (the highlight call runs concurrently).
+    pub grep: Duration,
+    pub parse: Duration,
+    pub highlight_parse: Duration,
 }
 
 impl SearchResult {
@@ -54,6 +178,12 @@ impl SearchResult {
         self.files.len()
     }
 
+    // Columns of the line numbers in the gutter, which are widened (but never narrowed) to `fixed_width`
+    // so that the code column stays put across searches.
+    pub fn line_number_width(&self, fixed_width: Option<usize>) -> usize {
+        self.max_line_width.max(fixed_width.unwrap_or(0)).max(1)
+    }
+
     pub fn hit_lines(&self) -> usize {
         self.highlight
             .lines
@@ -78,6 +208,50 @@ impl SearchResult {
             .unwrap_or_default()
     }
 
+    // Hit counts per top-level directory (see `top_level_dir()`), most hits first.
+    pub fn directory_hits(&self) -> Vec<(String, usize)> {
+        let mut counts = BTreeMap::<_, usize>::new();
+        for file in self.files.keys() {
+            *counts.entry(top_level_dir(file)).or_default() += self.hit_texts_in_file(file);
+        }
+        let mut counts = counts.into_iter().collect::<Vec<_>>();
+        // Directories with the same count are kept in the name order.
+        counts.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+        counts
+    }
+
+    // Inserts the `n` lines before and after `line_number` taken from `content` (the whole file) as context lines.
+    // Lines already in the result (e.g., hits) are kept as they are. Returns whether any line is inserted.
+    pub fn insert_context_lines(
+        &mut self,
+        file: &PathBuf,
+        line_number: NonZeroUsize,
+        n: usize,
+        content: &[String],
+    ) -> bool {
+        let Some(lines) = self.files.get_mut(file) else {
+            return false;
+        };
+        let start = line_number.get().saturating_sub(n).max(1);
+        let end = line_number.get().saturating_add(n).min(content.len());
+        let mut inserted = false;
+        for number in start..=end {
+            let Err(i) = lines.binary_search_by_key(&number, |line| line.number.get()) else {
+                continue;
+            };
+            lines.insert(
+                i,
+                Line {
+                    number: NonZeroUsize::new(number).expect("infallible"),
+                    text: content[number - 1].clone(),
+                    hit: false,
+                },
+            );
+            inserted = true;
+        }
+        inserted
+    }
+
     pub fn hit_lines_in_file(&self, file: &PathBuf) -> usize {
         self.files
             .get(file)
@@ -94,7 +268,28 @@ impl SearchResult {
             .unwrap_or(&[])
     }
 
-    fn parse(s: &str, highlight: Highlight) -> orfail::Result<Self> {
+    pub fn hit_ranges_in_line(&self, file: &PathBuf, line: &Line) -> Vec<Range<usize>> {
+        find_hit_ranges(&line.text, self.hit_texts_in_line(file, line.number))
+    }
+
+    pub fn term_hit_ranges_in_line(
+        &self,
+        term_index: usize,
+        file: &PathBuf,
+        line: &Line,
+    ) -> Vec<Range<usize>> {
+        let hit_texts = self
+            .highlight
+            .terms
+            .get(term_index)
+            .and_then(|(_, lines)| lines.get(file))
+            .and_then(|v| v.get(&line.number))
+            .map(|v| v.as_slice())
+            .unwrap_or(&[]);
+        find_hit_ranges(&line.text, hit_texts)
+    }
+
+    pub(crate) fn parse(s: &str, highlight: Highlight) -> orfail::Result<Self> {
         let mut files = BTreeMap::<_, Vec<_>>::new();
         let mut current = PathBuf::new();
         let mut max_line_width = 1;
@@ -116,8 +311,55 @@ impl SearchResult {
             max_line_width,
             highlight,
             error: None,
+            duration: Duration::ZERO,
+            file_order: Vec::new(),
+            commit_times: None,
+            timings: CallTimings::default(),
         })
     }
+
+    // Parses `git ls-files` output into file-only entries (i.e., without lines).
+    pub(crate) fn parse_file_list(s: &str) -> Self {
+        Self {
+            files: s
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(|line| (PathBuf::from(line), Vec::new()))
+                .collect(),
+            max_line_width: 1,
+            highlight: Highlight::default(),
+            error: None,
+            duration: Duration::ZERO,
+            file_order: Vec::new(),
+            commit_times: None,
+            timings: CallTimings::default(),
+        }
+    }
+}
+
+// First component of a path in a subdirectory, or `.` for a file at the top level.
+pub fn top_level_dir(file: &Path) -> String {
+    let mut components = file.components();
+    match (components.next(), components.next()) {
+        (Some(first), Some(_)) => first.as_os_str().to_string_lossy().into_owned(),
+        _ => ".".to_owned(),
+    }
+}
+
+// Byte ranges of the hit texts in the line, in the order they are found from left to right.
+// Hit texts that cannot be found in the line (e.g., the file was edited during the `git grep` call) are skipped.
+fn find_hit_ranges(text: &str, hit_texts: &[String]) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+    for hit_text in hit_texts {
+        let Some(i) = text[offset..].find(hit_text.as_str()) else {
+            continue;
+        };
+        let start = offset + i;
+        offset = start + hit_text.len();
+        ranges.push(start..offset);
+    }
+    ranges
 }
 
 #[derive(Debug, Clone)]
@@ -160,7 +402,8 @@ pub struct ContextLines(pub usize);
 
 impl ContextLines {
     pub const MIN: Self = Self(0);
-    pub const MAX: Self = Self(20);
+    // Upper bound of the `max-context-lines` setting.
+    pub const MAX: Self = Self(100);
 }
 
 impl Default for ContextLines {
@@ -174,14 +417,17 @@ pub struct GrepArg {
     pub kind: GrepArgKind,
     pub text: String,
     pub multiline_head: bool,
+    // Indentation level of the line started by this arg in multiline rendering.
+    pub multiline_indent: usize,
 }
 
 impl GrepArg {
-    fn new(kind: GrepArgKind) -> Self {
+    pub fn new(kind: GrepArgKind) -> Self {
         Self {
             kind,
             text: String::new(),
             multiline_head: false,
+            multiline_indent: 0,
         }
     }
 
@@ -190,6 +436,11 @@ impl GrepArg {
         self
     }
 
+    fn indented(mut self, level: usize) -> Self {
+        self.multiline_indent = level;
+        self
+    }
+
     pub fn insert(&mut self, i: usize, c: char) {
         self.text.insert(i, c);
     }
@@ -206,6 +457,19 @@ impl GrepArg {
         self.text[..i].chars().next_back()
     }
 
+    pub fn remove_grapheme(&mut self, i: usize) -> Option<String> {
+        let len = self.next_grapheme(i)?.len();
+        Some(self.text.drain(i..i + len).collect())
+    }
+
+    pub fn next_grapheme(&self, i: usize) -> Option<&str> {
+        self.text[i..].graphemes(true).next()
+    }
+
+    pub fn prev_grapheme(&self, i: usize) -> Option<&str> {
+        self.text[..i].graphemes(true).next_back()
+    }
+
     pub fn is_enabled(&self, focus: Focus) -> bool {
         !self.is_empty() || self.kind.is_focused(focus)
     }
@@ -219,15 +483,24 @@ impl GrepArg {
     }
 
     pub fn maybe_quoted_text(&self, focus: Focus) -> Cow<'_, str> {
-        if self.kind.is_focused(focus) || self.kind == GrepArgKind::Other {
+        if self.kind.is_focused(focus) || (self.kind == GrepArgKind::Other && !self.is_grouping()) {
             Cow::Borrowed(&self.text)
         } else {
             self.quoted_text()
         }
     }
 
-    pub fn width(&self, focus: Focus) -> usize {
-        mame::terminal::str_cols(&self.maybe_quoted_text(focus))
+    // Parentheses grouping patterns, which need quoting unlike the other fixed args.
+    pub fn is_grouping(&self) -> bool {
+        self.kind == GrepArgKind::Other && matches!(self.text.as_str(), "(" | ")")
+    }
+
+    // Returns the quoted form of `self.text[..index]` without the closing quote.
+    pub fn quoted_prefix(&self, index: usize) -> Cow<'_, str> {
+        if self.quoted_text() == self.text {
+            return Cow::Borrowed(&self.text[..index]);
+        }
+        Cow::Owned(format!("'{}", self.text[..index].replace('\'', r"'\''")))
     }
 
     pub fn quoted_text(&self) -> Cow<'_, str> {
@@ -243,7 +516,7 @@ impl GrepArg {
         quoted.push('\'');
         for c in self.text.chars() {
             if c == '\'' {
-                quoted.push_str(r#"'\'"#);
+                quoted.push_str(r#"'\''"#);
             } else {
                 quoted.push(c);
             }
@@ -252,11 +525,12 @@ impl GrepArg {
         Cow::Owned(quoted)
     }
 
-    fn other(s: &str) -> Self {
+    pub(crate) fn other(s: &str) -> Self {
         Self {
             kind: GrepArgKind::Other,
             text: s.to_string(),
             multiline_head: false,
+            multiline_indent: 0,
         }
     }
 }
@@ -284,22 +558,53 @@ impl GrepArgKind {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegexpEngine {
+    Basic,
+    Extended,
+    Perl,
+    FixedStrings,
+}
+
 #[derive(Debug, Clone)]
 pub struct GrepOptions {
     pub pattern: GrepArg,
     pub and_pattern: GrepArg,
     pub not_pattern: GrepArg,
+    // Whether the and-pattern is combined with the pattern by `--or` rather than `--and`.
+    pub or_secondary: bool,
     pub revision: GrepArg,
     pub path: GrepArg,
     pub ignore_case: bool,
     pub untracked: bool,
     pub no_index: bool,
     pub no_recursive: bool,
+    // `--textconv` (`--no-textconv` is the default of `git grep`, so it is never passed).
+    pub textconv: bool,
     pub word_regexp: bool,
     pub extended_regexp: bool,
     pub fixed_strings: bool,
     pub perl_regexp: bool,
     pub context_lines: ContextLines,
+    // Whether the context lines are fetched from the file around the focused line only (`-C 0` is passed instead).
+    pub lazy_context: bool,
+    pub timeout: Option<Duration>,
+    pub highlight_terms: bool,
+    pub filename_search: bool,
+    pub added_lines_only: bool,
+    // `:(exclude)` pathspecs passed after the path.
+    pub excluded_paths: Vec<String>,
+    // Pathspecs listed in a file, passed after the path.
+    pub pathspec_file: Option<PathspecFile>,
+    // `git` args run instead of `grep` (e.g., an alias), whose output must be in the `git grep` format.
+    pub grep_command: Vec<String>,
+    // Whether `ignore_case` folds only ASCII letters (by rewriting patterns rather than passing `-i`).
+    pub ascii_only_ignore_case: bool,
+    // Whether the repository has no work tree, in which case a revision is required.
+    pub bare: bool,
+    // The `--repo` directory as given on the command line.
+    // The search itself runs there as the current directory, so this only appears in the printed commands.
+    pub repo_dir: Option<PathBuf>,
 }
 
 impl Default for GrepOptions {
@@ -308,17 +613,30 @@ impl Default for GrepOptions {
             pattern: GrepArg::new(GrepArgKind::Pattern),
             and_pattern: GrepArg::new(GrepArgKind::AndPattern),
             not_pattern: GrepArg::new(GrepArgKind::NotPattern),
+            or_secondary: false,
             revision: GrepArg::new(GrepArgKind::Revision),
             path: GrepArg::new(GrepArgKind::Path),
             ignore_case: false,
             untracked: false,
             no_index: false,
             no_recursive: false,
+            textconv: false,
             word_regexp: false,
             extended_regexp: false,
             fixed_strings: false,
             perl_regexp: false,
             context_lines: ContextLines::default(),
+            lazy_context: false,
+            timeout: None,
+            highlight_terms: false,
+            filename_search: false,
+            added_lines_only: false,
+            excluded_paths: Vec::new(),
+            pathspec_file: None,
+            grep_command: vec!["grep".to_owned()],
+            ascii_only_ignore_case: false,
+            bare: false,
+            repo_dir: None,
         }
     }
 }
@@ -328,10 +646,211 @@ impl GrepOptions {
         self.build_grep_args(Mode::External, focus)
     }
 
+    // Whether both would run the same search (ignoring view-only options such as the context lines).
+    pub fn is_same_query(&self, other: &Self) -> bool {
+        let texts = |options: &Self| {
+            options
+                .args(Focus::SearchResult)
+                .into_iter()
+                .map(|arg| arg.text)
+                .collect::<Vec<_>>()
+        };
+        self.added_lines_only == other.added_lines_only && texts(self) == texts(other)
+    }
+
+    // Hash of everything that affects the result of `call()` (see `SearchCache`).
+    pub fn cache_key(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for mode in [Mode::Parsing, Mode::Highlight] {
+            for arg in self.build_grep_args(mode, Focus::SearchResult) {
+                arg.text.hash(&mut hasher);
+            }
+        }
+        (
+            self.highlight_terms,
+            self.filename_search,
+            self.added_lines_only,
+            self.timeout,
+        )
+            .hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // Names (as in the `flip-*` actions) of the enabled boolean flags, in the order of the legend.
+    pub fn enabled_flags(&self) -> impl Iterator<Item = &'static str> {
+        [
+            ("ignore-case", self.ignore_case),
+            ("untracked", self.untracked),
+            ("no-index", self.no_index),
+            ("no-recursive", self.no_recursive),
+            ("textconv", self.textconv),
+            ("whole-word", self.word_regexp),
+            ("fixed-strings", self.fixed_strings),
+            ("extended-regexp", self.extended_regexp),
+            ("perl-regexp", self.perl_regexp),
+        ]
+        .into_iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| name)
+    }
+
+    pub fn engine(&self) -> RegexpEngine {
+        if self.extended_regexp {
+            RegexpEngine::Extended
+        } else if self.perl_regexp {
+            RegexpEngine::Perl
+        } else if self.fixed_strings {
+            RegexpEngine::FixedStrings
+        } else {
+            RegexpEngine::Basic
+        }
+    }
+
+    pub fn set_engine(&mut self, engine: RegexpEngine) {
+        self.extended_regexp = engine == RegexpEngine::Extended;
+        self.perl_regexp = engine == RegexpEngine::Perl;
+        self.fixed_strings = engine == RegexpEngine::FixedStrings;
+    }
+
+    pub fn is_excluding(&self, globs: &[String]) -> bool {
+        !globs.is_empty()
+            && globs
+                .iter()
+                .all(|glob| self.excluded_paths.contains(&exclude_pathspec(glob)))
+    }
+
+    // Removes the exclusions if all of them are set, and adds the missing ones otherwise.
+    pub fn toggle_exclusions(&mut self, globs: &[String]) {
+        let pathspecs = globs
+            .iter()
+            .map(|g| exclude_pathspec(g))
+            .collect::<Vec<_>>();
+        if self.is_excluding(globs) {
+            self.excluded_paths.retain(|p| !pathspecs.contains(p));
+        } else {
+            for pathspec in pathspecs {
+                if !self.excluded_paths.contains(&pathspec) {
+                    self.excluded_paths.push(pathspec);
+                }
+            }
+        }
+    }
+
+    // The path field holds space-separated pathspecs.
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.path.text.split_whitespace()
+    }
+
+    // Extensions of the `*.ext` pathspecs in the path field.
+    pub fn extensions(&self) -> Vec<&str> {
+        self.paths().filter_map(pathspec_extension).collect()
+    }
+
+    // Replaces the `*.ext` pathspecs with the ones of a comma-separated extension list (empty clears them).
+    pub fn set_extensions(&mut self, list: &str) {
+        let paths = self
+            .paths()
+            .filter(|p| pathspec_extension(p).is_none())
+            .map(str::to_owned)
+            .chain(extension_pathspecs(list))
+            .collect::<Vec<_>>();
+        self.path.text = paths.join(" ");
+    }
+
+    // Escapes the focused pattern (or the main pattern if no pattern is focused) to match literally.
+    pub fn escape_pattern(&mut self, focus: Focus) {
+        let engine = self.engine();
+        let arg = match focus {
+            Focus::AndPattern => &mut self.and_pattern,
+            Focus::NotPattern => &mut self.not_pattern,
+            _ => &mut self.pattern,
+        };
+        arg.text = escape_regex(&arg.text, engine);
+    }
+
+    // Only the texts are exchanged so that each arg keeps its kind (and thus its position in the command).
+    pub fn swap_pattern_and(&mut self) {
+        std::mem::swap(&mut self.pattern.text, &mut self.and_pattern.text);
+    }
+
+    // `git grep` needs a primary pattern, so an and-pattern given alone takes that role.
+    pub fn promote_and_pattern(&mut self) {
+        if self.pattern.is_empty() {
+            self.swap_pattern_and();
+        }
+    }
+
+    pub fn parsing_args(&self) -> Vec<GrepArg> {
+        self.build_grep_args(Mode::Parsing, Focus::SearchResult)
+    }
+
+    // The command line of `call_raw()`.
+    pub fn parsing_command_line(&self) -> String {
+        let mut text = "$ git".to_owned();
+        for arg in self.parsing_args() {
+            text.push(' ');
+            text.push_str(&arg.quoted_text());
+        }
+        text
+    }
+
+    // The `mamegrep` args that start with the same query (mirroring the options of `main.rs`).
+    // The flags (e.g., `-i`) have no command-line options, so they are not reproduced.
+    pub fn cli_args(&self) -> Vec<String> {
+        let mut args = vec!["mamegrep".to_owned()];
+        if let Some(dir) = &self.repo_dir {
+            args.push("-C".to_owned());
+            args.push(dir.display().to_string());
+        }
+        for (option, arg) in [
+            ("-a", &self.and_pattern),
+            ("-n", &self.not_pattern),
+            ("-r", &self.revision),
+        ] {
+            if !arg.is_empty() {
+                args.push(option.to_owned());
+                args.push(arg.text.clone());
+            }
+        }
+        for path in self.paths() {
+            args.push("-p".to_owned());
+            args.push(path.to_owned());
+        }
+        if let Some(timeout) = self.timeout {
+            args.push("--timeout".to_owned());
+            args.push(timeout.as_secs().to_string());
+        }
+        if let Some(file) = &self.pathspec_file {
+            args.push("--pathspec-from-file".to_owned());
+            args.push(file.path.display().to_string());
+            if file.nul {
+                args.push("--pathspec-file-nul".to_owned());
+            }
+        }
+        if !self.pattern.is_empty() {
+            args.push(self.pattern.text.clone());
+        }
+        args
+    }
+
+    pub fn cli_command_line(&self) -> String {
+        self.cli_args()
+            .iter()
+            .map(|arg| GrepArg::other(arg).quoted_text().into_owned())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn call_raw(&self) -> std::io::Result<Output> {
+        let args = self.parsing_args();
+        let args = args.iter().map(|s| s.text.as_str()).collect::<Vec<_>>();
+        execute(Command::new("git").args(args), self.timeout)
+    }
+
     pub fn get_error_result(&self) -> Option<SearchResult> {
         let args = self.build_grep_args(Mode::External, Focus::SearchResult);
         let args = args.iter().map(|s| s.text.as_str()).collect::<Vec<_>>();
-        let output = Command::new("git").args(args).output().ok()?;
+        let output = execute(Command::new("git").args(args), self.timeout).ok()?;
         if output.stderr.is_empty() {
             return None;
         }
@@ -343,43 +862,378 @@ impl GrepOptions {
             })
     }
 
+    fn highlighted_terms(&self) -> Vec<&str> {
+        if !self.highlight_terms {
+            return Vec::new();
+        }
+        [&self.pattern, &self.and_pattern]
+            .into_iter()
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| arg.text.as_str())
+            .collect()
+    }
+
+    // Which patterns are given, as `git grep` needs at least one positive pattern to search.
+    fn query_kind(&self) -> QueryKind {
+        if !self.pattern.is_empty() {
+            QueryKind::Ready
+        } else if !self.and_pattern.is_empty() {
+            QueryKind::AndOnly
+        } else if !self.not_pattern.is_empty() {
+            QueryKind::NotOnly
+        } else {
+            QueryKind::Empty
+        }
+    }
+
     pub fn call(&self) -> orfail::Result<SearchResult> {
-        if self.pattern.is_empty() {
-            return Ok(SearchResult::default());
+        if self.bare && self.revision.is_empty() {
+            return Ok(SearchResult {
+                error: Some(
+                    "a revision (e.g., `HEAD`) is required in a bare repository".to_owned(),
+                ),
+                ..Default::default()
+            });
+        }
+        match self.query_kind() {
+            QueryKind::Ready => {}
+            QueryKind::AndOnly => {
+                let mut options = self.clone();
+                options.promote_and_pattern();
+                return options.call().or_fail();
+            }
+            QueryKind::NotOnly => {
+                return Ok(SearchResult {
+                    error: Some(
+                        "a pattern or an and-pattern is required with a not-pattern".to_owned(),
+                    ),
+                    ..Default::default()
+                });
+            }
+            QueryKind::Empty => return Ok(SearchResult::default()),
+        }
+        if self.filename_search {
+            return self.call_ls_files();
+        }
+        if self.added_lines_only {
+            return self.call_diff();
         }
 
+        let start = Instant::now();
         std::thread::scope(|s| {
             let handle0 = s.spawn(|| {
                 let args = self.build_grep_args(Mode::Highlight, Focus::SearchResult);
                 let args = args.iter().map(|s| s.text.as_str()).collect::<Vec<_>>();
-                let output = call(&args, false).or_fail()?;
-                Highlight::parse(&output).or_fail()
+                let output = call_with_timeout(&args, self.timeout).or_fail()?;
+                let parse_start = Instant::now();
+                let highlight = output
+                    .map(|output| Highlight::parse_with_columns(&output))
+                    .transpose()?;
+                Ok::<_, orfail::Failure>((highlight, parse_start.elapsed()))
             });
             let handle1 = s.spawn(|| {
                 let args = self.build_grep_args(Mode::Parsing, Focus::SearchResult);
                 let args = args.iter().map(|s| s.text.as_str()).collect::<Vec<_>>();
-                let output = call(&args, false).or_fail()?;
-                SearchResult::parse(&output, Highlight::default()).or_fail()
+                let grep_start = Instant::now();
+                let output = call_with_timeout(&args, self.timeout).or_fail()?;
+                let parse_start = Instant::now();
+                let search_result = output
+                    .map(|output| {
+                        if is_heading_format(&output) {
+                            SearchResult::parse(&output, Highlight::default())
+                        } else {
+                            Ok(SearchResult {
+                                error: Some(format!(
+                                    "unrecognized output of `git {}` (expected `git grep -n --heading` format)",
+                                    self.grep_command.join(" ")
+                                )),
+                                ..Default::default()
+                            })
+                        }
+                    })
+                    .transpose()?;
+                Ok::<_, orfail::Failure>((
+                    search_result,
+                    parse_start.duration_since(grep_start),
+                    parse_start.elapsed(),
+                ))
             });
+            // `git grep -o` does not tell which `-e` matched, so each term needs its own call.
+            let term_handles = self
+                .highlighted_terms()
+                .into_iter()
+                .map(|term| {
+                    s.spawn(move || {
+                        let args =
+                            self.build_grep_args(Mode::TermHighlight(term), Focus::SearchResult);
+                        let args = args.iter().map(|s| s.text.as_str()).collect::<Vec<_>>();
+                        let output = call_with_timeout(&args, self.timeout).or_fail()?;
+                        output
+                            .map(|output| {
+                                Highlight::parse_with_columns(&output)
+                                    .map(|h| (term.to_owned(), h.lines))
+                            })
+                            .transpose()
+                    })
+                })
+                .collect::<Vec<_>>();
 
-            let highlight = handle0
+            let (search_result, grep_duration, parse_duration) = handle1
                 .join()
                 .unwrap_or_else(|e| std::panic::resume_unwind(e))
                 .or_fail()?;
-            let mut search_result = handle1
+            if let Some(result) = &search_result
+                && result.error.is_some()
+            {
+                // The other outputs cannot be parsed either.
+                return Ok(search_result.expect("infallible"));
+            }
+            let (highlight, highlight_parse_duration) = handle0
                 .join()
                 .unwrap_or_else(|e| std::panic::resume_unwind(e))
                 .or_fail()?;
+            let terms = term_handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|e| std::panic::resume_unwind(e))
+                        .or_fail()
+                })
+                .collect::<orfail::Result<Option<Vec<_>>>>()?;
+            let (Some(mut highlight), Some(mut search_result), Some(terms)) =
+                (highlight, search_result, terms)
+            else {
+                let timeout = self.timeout.unwrap_or_default();
+                return Ok(SearchResult {
+                    error: Some(format!("`git grep` timed out after {timeout:?}")),
+                    ..Default::default()
+                });
+            };
+            highlight.terms = terms;
             search_result.highlight = highlight;
+            search_result.duration = start.elapsed();
+            search_result.timings = CallTimings {
+                grep: grep_duration,
+                parse: parse_duration,
+                highlight_parse: highlight_parse_duration,
+            };
             Ok(search_result)
         })
     }
 
+    fn call_ls_files(&self) -> orfail::Result<SearchResult> {
+        let start = Instant::now();
+        let args = self.build_ls_files_args(Focus::SearchResult);
+        let args = args.iter().map(|s| s.text.as_str()).collect::<Vec<_>>();
+        let Some(output) = call_with_timeout(&args, self.timeout).or_fail()? else {
+            let timeout = self.timeout.unwrap_or_default();
+            return Ok(SearchResult {
+                error: Some(format!("`git ls-files` timed out after {timeout:?}")),
+                ..Default::default()
+            });
+        };
+        let mut search_result = SearchResult::parse_file_list(&output);
+        search_result.duration = start.elapsed();
+        Ok(search_result)
+    }
+
+    // `git grep` cannot search the lines of a diff, so `grep` (which shares the regexp flags) filters the added lines.
+    fn call_diff(&self) -> orfail::Result<SearchResult> {
+        let start = Instant::now();
+        let base = if self.revision.is_empty() {
+            "HEAD"
+        } else {
+            self.revision.text.as_str()
+        };
+        let mut args = vec![
+            "diff",
+            "--unified=0",
+            "--no-color",
+            "--no-ext-diff",
+            "--relative",
+            "--src-prefix=a/",
+            "--dst-prefix=b/",
+            base,
+        ];
+        if !self.path.is_empty() {
+            args.push("--");
+            args.extend(self.paths());
+        }
+        let Some(diff) = call_with_timeout(&args, self.timeout).or_fail()? else {
+            let timeout = self.timeout.unwrap_or_default();
+            return Ok(SearchResult {
+                error: Some(format!("`git diff` timed out after {timeout:?}")),
+                ..Default::default()
+            });
+        };
+
+        let lines = parse_added_lines(&diff);
+        let texts = lines
+            .iter()
+            .map(|(_, l)| l.text.as_str())
+            .collect::<Vec<_>>();
+        let mut indices = self
+            .filter_lines(&texts, &self.pattern.text, false)
+            .or_fail()?;
+        if self.or_secondary && !self.and_pattern.is_empty() {
+            indices.extend(
+                self.filter_lines(&texts, &self.and_pattern.text, false)
+                    .or_fail()?,
+            );
+            indices.sort_unstable();
+            indices.dedup();
+        }
+        for (arg, invert) in [(&self.and_pattern, false), (&self.not_pattern, true)] {
+            if arg.is_empty() || (self.or_secondary && !invert) {
+                continue;
+            }
+            let texts = indices.iter().map(|&i| texts[i]).collect::<Vec<_>>();
+            indices = self
+                .filter_lines(&texts, &arg.text, invert)
+                .or_fail()?
+                .into_iter()
+                .map(|i| indices[i])
+                .collect();
+        }
+        let texts = indices.iter().map(|&i| texts[i]).collect::<Vec<_>>();
+        let hit_texts = self.match_texts(&texts).or_fail()?;
+
+        let mut search_result = SearchResult {
+            max_line_width: 1,
+            ..Default::default()
+        };
+        let mut hits = indices.into_iter().zip(hit_texts).peekable();
+        for (i, (path, line)) in lines.into_iter().enumerate() {
+            let Some((_, hit_texts)) = hits.next_if(|(j, _)| *j == i) else {
+                continue;
+            };
+            search_result.max_line_width = search_result
+                .max_line_width
+                .max(line.number.to_string().len());
+            search_result
+                .highlight
+                .lines
+                .entry(path.clone())
+                .or_default()
+                .insert(line.number, hit_texts);
+            search_result.files.entry(path).or_default().push(line);
+        }
+        search_result.duration = start.elapsed();
+        Ok(search_result)
+    }
+
+    fn grep_command(&self) -> Command {
+        let mut command = Command::new("grep");
+        command.arg("-a");
+        for (enabled, flag) in [
+            (self.ignore_case, "-i"),
+            (self.word_regexp, "-w"),
+            (self.extended_regexp, "-E"),
+            (self.fixed_strings, "-F"),
+            (self.perl_regexp, "-P"),
+        ] {
+            if enabled {
+                command.arg(flag);
+            }
+        }
+        command
+    }
+
+    // Returns the indices of the texts that match (or do not match if `invert`) the pattern.
+    fn filter_lines(
+        &self,
+        texts: &[&str],
+        pattern: &str,
+        invert: bool,
+    ) -> orfail::Result<Vec<usize>> {
+        let mut command = self.grep_command();
+        command.arg("-n");
+        if invert {
+            command.arg("-v");
+        }
+        command.args(["-e", pattern]);
+        let output = grep_stdin(command, texts).or_fail()?;
+        output
+            .lines()
+            .map(|line| {
+                let (n, _) = line.split_once(':').or_fail()?;
+                n.parse::<usize>().map(|n| n - 1).or_fail()
+            })
+            .collect()
+    }
+
+    // Returns the matched texts of the pattern in each text.
+    fn match_texts(&self, texts: &[&str]) -> orfail::Result<Vec<Vec<String>>> {
+        let mut command = self.grep_command();
+        command.args(["-n", "-o", "-e", &self.pattern.text]);
+        let output = grep_stdin(command, texts).or_fail()?;
+        let mut matches = vec![Vec::new(); texts.len()];
+        for line in output.lines() {
+            let (n, text) = line.split_once(':').or_fail()?;
+            let i = n.parse::<usize>().or_fail()? - 1;
+            matches.get_mut(i).or_fail()?.push(text.to_owned());
+        }
+        Ok(matches)
+    }
+
+    // Lists the files whose paths contain the pattern, instead of searching their contents.
+    fn build_ls_files_args(&self, focus: Focus) -> Vec<GrepArg> {
+        let mut args = vec![GrepArg::other("ls-files")];
+        if self.untracked {
+            args.push(GrepArg::other("--cached"));
+            args.push(GrepArg::other("--others"));
+            args.push(GrepArg::other("--exclude-standard"));
+        }
+        args.push(GrepArg::other("--"));
+
+        // The pattern is shown as typed while being edited so that the cursor position stays valid.
+        let mut pattern = self.pattern.clone().line_breakable();
+        if !pattern.kind.is_focused(focus) {
+            let magic = if self.ignore_case { ":(icase)" } else { "" };
+            pattern.text = format!("{magic}*{}*", pattern.text);
+        }
+        args.push(pattern);
+        args
+    }
+
+    // Fixed strings have no character classes, so `-i` is used for them as usual.
+    fn uses_ascii_case_classes(&self) -> bool {
+        self.ignore_case && self.ascii_only_ignore_case && !self.fixed_strings
+    }
+
+    fn case_folded(&self, pattern: &str) -> String {
+        if self.uses_ascii_case_classes() {
+            ascii_case_classes(pattern, self.engine())
+        } else {
+            pattern.to_owned()
+        }
+    }
+
+    // The focused arg is shown as typed.
+    fn pattern_arg(&self, arg: &GrepArg, focus: Focus) -> GrepArg {
+        if arg.kind.is_focused(focus) {
+            return arg.clone();
+        }
+        GrepArg {
+            text: self.case_folded(&arg.text),
+            ..arg.clone()
+        }
+    }
+
     fn build_grep_args(&self, mode: Mode, focus: Focus) -> Vec<GrepArg> {
-        let mut args = vec![GrepArg::other("grep")];
+        if self.filename_search {
+            return self.build_ls_files_args(focus);
+        }
+
+        let mut args = self
+            .grep_command
+            .iter()
+            .map(|arg| GrepArg::other(arg))
+            .collect::<Vec<_>>();
 
         let mut flags = "-nI".to_string();
-        if self.ignore_case {
+        if self.ignore_case && !self.uses_ascii_case_classes() {
             flags.push('i');
         }
         if self.word_regexp {
@@ -405,33 +1259,63 @@ impl GrepOptions {
         if self.no_recursive {
             args.push(GrepArg::other("--no-recursive"));
         }
+        if self.textconv {
+            args.push(GrepArg::other("--textconv"));
+        }
         if matches!(mode, Mode::Parsing) {
             args.push(GrepArg::other("--heading"));
             args.push(GrepArg::other("-C"));
-            args.push(GrepArg::other(&self.context_lines.0.to_string()));
+            let context_lines = if self.lazy_context {
+                ContextLines::MIN
+            } else {
+                self.context_lines
+            };
+            args.push(GrepArg::other(&context_lines.0.to_string()));
         }
         if matches!(mode, Mode::Highlight) {
             args.push(GrepArg::other("-o"));
+            args.push(GrepArg::other("--column"));
             args.push(GrepArg::other("--heading"));
         }
 
-        if self.not_pattern.is_enabled(focus) || self.and_pattern.is_enabled(focus) {
+        if let Mode::TermHighlight(term) = mode {
+            args.push(GrepArg::other("-o"));
+            args.push(GrepArg::other("--column"));
+            args.push(GrepArg::other("--heading"));
+            args.push(GrepArg::other("-e"));
+            args.push(GrepArg::other(&self.case_folded(term)));
+        } else if self.not_pattern.is_enabled(focus)
+            || self.and_pattern.is_enabled(focus)
+            // Otherwise taken as an option.
+            || self.pattern.text.starts_with('-')
+        {
             args.push(GrepArg::other("-e").line_breakable());
-            args.push(self.pattern.clone());
+            args.push(self.pattern_arg(&self.pattern, focus));
         } else {
-            args.push(self.pattern.clone().line_breakable());
+            args.push(self.pattern_arg(&self.pattern, focus).line_breakable());
         }
 
-        if self.and_pattern.is_enabled(focus) {
-            args.push(GrepArg::other("--and").line_breakable());
+        if self.and_pattern.is_enabled(focus) && !matches!(mode, Mode::TermHighlight(_)) {
+            // `--and` binds tighter than `--or`, so the alternatives are grouped before a not-pattern.
+            let grouped = self.or_secondary && self.not_pattern.is_enabled(focus);
+            if grouped {
+                let i = args.len() - 2;
+                args.insert(i, GrepArg::other("(").line_breakable());
+                args[i + 1].multiline_head = false;
+            }
+            let operator = if self.or_secondary { "--or" } else { "--and" };
+            args.push(GrepArg::other(operator).line_breakable().indented(1));
             args.push(GrepArg::other("-e"));
-            args.push(self.and_pattern.clone());
+            args.push(self.pattern_arg(&self.and_pattern, focus));
+            if grouped {
+                args.push(GrepArg::other(")"));
+            }
         }
-        if self.not_pattern.is_enabled(focus) {
-            args.push(GrepArg::other("--and").line_breakable());
+        if self.not_pattern.is_enabled(focus) && !matches!(mode, Mode::TermHighlight(_)) {
+            args.push(GrepArg::other("--and").line_breakable().indented(1));
             args.push(GrepArg::other("--not"));
             args.push(GrepArg::other("-e"));
-            args.push(self.not_pattern.clone());
+            args.push(self.pattern_arg(&self.not_pattern, focus));
         }
         if self.revision.is_enabled(focus) {
             args.push(self.revision.clone().line_breakable());
@@ -441,59 +1325,898 @@ impl GrepOptions {
         }
         if self.path.is_enabled(focus) {
             args.push(GrepArg::other("--").line_breakable());
-            args.push(self.path.clone());
+            if self.path.kind.is_focused(focus) {
+                // Edited as a single field.
+                args.push(self.path.clone());
+            } else {
+                args.extend(self.paths().map(|path| GrepArg {
+                    text: path.to_owned(),
+                    ..self.path.clone()
+                }));
+            }
+        }
+        let listed_paths = self
+            .pathspec_file
+            .iter()
+            .flat_map(|file| &file.pathspecs)
+            .chain(&self.excluded_paths)
+            .collect::<Vec<_>>();
+        if !listed_paths.is_empty() {
+            if !self.path.is_enabled(focus) && !self.revision.is_enabled(focus) {
+                args.push(GrepArg::other("--").line_breakable());
+            }
+            for pathspec in listed_paths {
+                args.push(GrepArg::other(pathspec));
+            }
         }
         args
     }
 }
 
-pub fn is_available() -> bool {
-    // Check if `git` is accessible and we are within a Git directory.
-    call(&["rev-parse", "--is-inside-work-tree"], true)
-        .ok()
-        .filter(|s| s.trim() == "true")
-        .is_some()
+// `git grep` has no `--pathspec-from-file` option (unlike `git add` and others),
+// so the file is read beforehand and its entries are passed as pathspec args.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathspecFile {
+    pub path: PathBuf,
+    // Whether the entries are separated by NUL (`--pathspec-file-nul`) rather than by newlines.
+    pub nul: bool,
+    pub pathspecs: Vec<String>,
 }
 
-fn call(args: &[&str], check_status: bool) -> orfail::Result<String> {
-    let mut command = Command::new("git");
-    let output = command
-        .args(args)
-        .output()
-        .or_fail_with(|e| format!("Failed to execute `$ {command:?}`: {e}"))?;
+impl PathspecFile {
+    pub fn read<P: AsRef<Path>>(path: P, nul: bool) -> orfail::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .or_fail_with(|e| format!("Failed to read {}: {e}", path.display()))?;
+        let pathspecs = parse_pathspec_list(&text, nul);
+        (!pathspecs.is_empty()).or_fail_with(|()| format!("No pathspecs in {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            nul,
+            pathspecs,
+        })
+    }
+}
 
-    let error = |()| {
-        format!(
-            "Failed to execute `$ {command:?}`:\n{}\n",
-            String::from_utf8_lossy(&output.stderr)
-        )
+// Empty entries are skipped, as an empty pathspec would match every file.
+fn parse_pathspec_list(text: &str, nul: bool) -> Vec<String> {
+    let entries: Box<dyn Iterator<Item = &str>> = if nul {
+        Box::new(text.split('\0'))
+    } else {
+        Box::new(text.lines())
     };
-    (!check_status || output.status.success()).or_fail_with(error)?;
-    (check_status || output.stderr.is_empty()).or_fail_with(error)?;
-
-    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    entries
+        .filter(|entry| !entry.is_empty())
+        .map(str::to_owned)
+        .collect()
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+// Escapes the metacharacters of the engine.
+//
+// In BREs, `+`, `?`, `|`, `{` and `(` are literal and become special (as GNU extensions) when escaped,
+// so they are kept as is.
+pub fn escape_regex(text: &str, engine: RegexpEngine) -> String {
+    let metachars = match engine {
+        RegexpEngine::Basic => r"\.[*^$",
+        RegexpEngine::Extended => r"\.[*^$+?(){|",
+        RegexpEngine::Perl => r"\.[]*^$+?(){}|",
+        RegexpEngine::FixedStrings => return text.to_owned(),
+    };
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if metachars.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
 
-    #[test]
-    fn parse_search_result() -> orfail::Result<()> {
-        let output = r#"src/canvas.rs
-315:        line.draw_token(2, Token::new("foo"));
-316:        assert_eq!(line.text(), "  foo");
-"#;
-        let result = SearchResult::parse(output, Highlight::default()).or_fail()?;
-        assert_eq!(result.files.len(), 1);
+// Rewrites ASCII letters into classes matching both cases (e.g., `foo` to `[fF][oO][oO]`).
+// Escapes, bracket expressions and (PCRE) group syntax are kept intact, except that the other cases are
+// added to the letters and letter ranges in bracket expressions.
+fn ascii_case_classes(pattern: &str, engine: RegexpEngine) -> String {
+    let chars = pattern.chars().collect::<Vec<_>>();
+    let mut folded = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' => {
+                let end = escape_end(&chars, i, engine);
+                folded.extend(&chars[i..end]);
+                i = end;
+            }
+            '[' => {
+                let end = bracket_end(&chars, i);
+                folded.push_str(&fold_bracket(&chars[i..end]));
+                i = end;
+            }
+            '(' if engine == RegexpEngine::Perl && chars.get(i + 1) == Some(&'?') => {
+                let end = group_prefix_end(&chars, i + 2);
+                folded.extend(&chars[i..end]);
+                i = end;
+            }
+            _ if c.is_ascii_alphabetic() => {
+                folded.push('[');
+                folded.push(c);
+                folded.push(swap_ascii_case(c));
+                folded.push(']');
+                i += 1;
+            }
+            _ => {
+                folded.push(c);
+                i += 1;
+            }
+        }
+    }
+    folded
+}
 
-        let lines = result
-            .files
-            .get(&PathBuf::from("src/canvas.rs"))
-            .or_fail()?;
-        assert_eq!(lines.len(), 2);
+fn swap_ascii_case(c: char) -> char {
+    if c.is_ascii_lowercase() {
+        c.to_ascii_uppercase()
+    } else {
+        c.to_ascii_lowercase()
+    }
+}
 
-        assert_eq!(lines[0].number.get(), 315);
+// End of the escape sequence starting at `i` (e.g., `\w`, `\x41` or `\p{Lu}` in PCRE).
+fn escape_end(chars: &[char], i: usize, engine: RegexpEngine) -> usize {
+    let end = (i + 2).min(chars.len());
+    if engine != RegexpEngine::Perl {
+        return end;
+    }
+    let closing = match chars.get(end) {
+        Some('{') => Some('}'),
+        Some('<') => Some('>'),
+        Some('\'') => Some('\''),
+        _ => None,
+    };
+    match (chars.get(i + 1), closing) {
+        (Some('x' | 'p' | 'P' | 'N' | 'o' | 'g' | 'k'), Some('}')) | (Some('g' | 'k'), Some(_)) => {
+            chars[end + 1..]
+                .iter()
+                .position(|c| Some(*c) == closing)
+                .map_or(chars.len(), |n| end + n + 2)
+        }
+        (Some('x'), _) => {
+            end + chars[end..]
+                .iter()
+                .take(2)
+                .take_while(|c| c.is_ascii_hexdigit())
+                .count()
+        }
+        (Some('p' | 'P'), _) => (end + 1).min(chars.len()),
+        _ => end,
+    }
+}
+
+// End of the bracket expression starting at `i` (the rest of the pattern if unterminated).
+fn bracket_end(chars: &[char], i: usize) -> usize {
+    let mut j = i + 1;
+    if chars.get(j) == Some(&'^') {
+        j += 1;
+    }
+    // A leading `]` is a literal.
+    if chars.get(j) == Some(&']') {
+        j += 1;
+    }
+    while j < chars.len() {
+        match chars[j] {
+            ']' => return j + 1,
+            '[' if matches!(chars.get(j + 1), Some(':' | '=' | '.')) => {
+                // POSIX classes such as `[:alpha:]`.
+                let delimiter = chars[j + 1];
+                j += 2;
+                while j + 1 < chars.len() && !(chars[j] == delimiter && chars[j + 1] == ']') {
+                    j += 1;
+                }
+                j += 2;
+            }
+            '\\' => j += 2,
+            _ => j += 1,
+        }
+    }
+    chars.len()
+}
+
+// Adds the other cases of the letters and letter ranges to the bracket expression.
+fn fold_bracket(bracket: &[char]) -> String {
+    let text = bracket.iter().collect::<String>();
+    if bracket.last() != Some(&']') {
+        return text;
+    }
+    let inner = &bracket[..bracket.len() - 1];
+    let mut additions = String::new();
+    let mut j = 1;
+    while j < inner.len() {
+        match inner[j] {
+            '[' if matches!(inner.get(j + 1), Some(':' | '=' | '.')) => {
+                let delimiter = inner[j + 1];
+                j += 2;
+                while j + 1 < inner.len() && !(inner[j] == delimiter && inner[j + 1] == ']') {
+                    j += 1;
+                }
+                j += 2;
+            }
+            '\\' => j += 2,
+            c if c.is_ascii_alphabetic() => {
+                if inner.get(j + 1) == Some(&'-')
+                    && let Some(&end) = inner.get(j + 2)
+                    && end.is_ascii_alphabetic()
+                    && c.is_ascii_lowercase() == end.is_ascii_lowercase()
+                {
+                    additions.extend([swap_ascii_case(c), '-', swap_ascii_case(end)]);
+                    j += 3;
+                } else {
+                    additions.push(swap_ascii_case(c));
+                    j += 1;
+                }
+            }
+            _ => j += 1,
+        }
+    }
+    let mut folded = inner.iter().collect::<String>();
+    folded.push_str(&additions);
+    folded.push(']');
+    folded
+}
+
+// End of the PCRE group syntax starting at `i` (right after `(?`), such as `(?<name>` or `(?i:`.
+fn group_prefix_end(chars: &[char], i: usize) -> usize {
+    let rest = &chars[i..];
+    let is_lookbehind = rest.first() == Some(&'<') && matches!(rest.get(1), Some('=' | '!'));
+    if !is_lookbehind && matches!(rest.first(), Some('<' | '&' | '\'' | 'P')) {
+        // Named groups and references.
+        return rest
+            .iter()
+            .skip(1)
+            .position(|c| matches!(c, '>' | ')' | '\''))
+            .map_or(chars.len(), |n| i + n + 2);
+    }
+    // Inline flags such as `(?i)` or `(?s-m:`.
+    i + rest
+        .iter()
+        .take_while(|c| c.is_ascii_alphabetic() || matches!(c, '-' | '^'))
+        .count()
+}
+
+fn exclude_pathspec(glob: &str) -> String {
+    format!(":(exclude){glob}")
+}
+
+// Accepts both `rs` and `.rs` (and `*.rs`) forms, ignoring duplicates and empty items.
+pub fn extension_pathspecs(list: &str) -> Vec<String> {
+    let mut pathspecs = Vec::new();
+    for ext in list.split(',') {
+        let ext = ext.trim();
+        let ext = ext.strip_prefix('*').unwrap_or(ext);
+        let ext = ext.strip_prefix('.').unwrap_or(ext);
+        let pathspec = format!("*.{ext}");
+        if pathspec_extension(&pathspec).is_some() && !pathspecs.contains(&pathspec) {
+            pathspecs.push(pathspec);
+        }
+    }
+    pathspecs
+}
+
+fn pathspec_extension(pathspec: &str) -> Option<&str> {
+    pathspec
+        .strip_prefix("*.")
+        .filter(|ext| !ext.is_empty())
+        .filter(|ext| !ext.contains(['/', '*', '?', '[', ':', ' ']))
+}
+
+// Extracts the added lines (with their new line numbers) from `git diff` output.
+fn parse_added_lines(diff: &str) -> Vec<(PathBuf, Line)> {
+    let mut lines = Vec::new();
+    let mut current = None;
+    let mut in_hunk = false;
+    let mut number = 0;
+    for line in diff.lines() {
+        if line.starts_with("diff ") {
+            in_hunk = false;
+        } else if !in_hunk && let Some(path) = line.strip_prefix("+++ ") {
+            // `+++ /dev/null` means the file was deleted.
+            current = path.strip_prefix("b/").map(PathBuf::from);
+        } else if let Some(hunk) = line.strip_prefix("@@ ") {
+            // e.g., `@@ -10,2 +12,3 @@`
+            in_hunk = true;
+            number = hunk
+                .split(' ')
+                .find_map(|s| s.strip_prefix('+'))
+                .and_then(|s| s.split(',').next())
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_default();
+        } else if in_hunk && let (Some(text), Some(path)) = (line.strip_prefix('+'), &current) {
+            if let Some(number) = NonZeroUsize::new(number) {
+                let text = text.to_owned();
+                lines.push((
+                    path.clone(),
+                    Line {
+                        number,
+                        text,
+                        hit: true,
+                    },
+                ));
+            }
+            number += 1;
+        }
+    }
+    lines
+}
+
+fn grep_stdin(mut command: Command, texts: &[&str]) -> orfail::Result<String> {
+    use std::io::Write;
+
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .or_fail_with(|e| format!("Failed to execute `$ {command:?}`: {e}"))?;
+    let mut stdin = child.stdin.take().or_fail()?;
+    let input = texts.iter().map(|t| format!("{t}\n")).collect::<String>();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output().or_fail()?;
+    writer
+        .join()
+        .unwrap_or_else(|e| std::panic::resume_unwind(e))
+        .or_fail()?;
+
+    // `grep` exits with 1 if no lines are selected.
+    matches!(output.status.code(), Some(0 | 1)).or_fail_with(|()| {
+        format!(
+            "Failed to execute `$ {command:?}`:\n{}\n",
+            String::from_utf8_lossy(&output.stderr)
+        )
+    })?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// A search running in a background thread.
+//
+// The fd becomes readable (at EOF) when the search has finished, so it can be polled with the terminal input.
+#[derive(Debug)]
+pub struct BackgroundSearch {
+    reader: std::io::PipeReader,
+    handle: std::thread::JoinHandle<(GrepOptions, orfail::Result<SearchResult>)>,
+}
+
+impl BackgroundSearch {
+    pub fn spawn(options: GrepOptions) -> orfail::Result<Self> {
+        let (reader, writer) = std::io::pipe().or_fail()?;
+        let handle = std::thread::spawn(move || {
+            let result = options.call();
+            // Closing the write end wakes up the poller.
+            std::mem::drop(writer);
+            (options, result)
+        });
+        Ok(Self { reader, handle })
+    }
+
+    pub fn fd(&self) -> std::os::fd::RawFd {
+        use std::os::fd::AsRawFd;
+        self.reader.as_raw_fd()
+    }
+
+    // Waits for the search (if not finished yet) and returns the options used with the result.
+    pub fn finish(mut self) -> (GrepOptions, orfail::Result<SearchResult>) {
+        // Blocks until the write end is closed.
+        let _ = self.reader.read_to_end(&mut Vec::new());
+        self.handle
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepositoryKind {
+    WorkTree,
+    // Only revisions can be searched (e.g., `git grep foo HEAD`).
+    Bare,
+}
+
+// Returns `None` if `git` is not accessible or we are not within a Git directory
+// (the `.git` directory of a non-bare repository is neither).
+pub fn repository_kind() -> Option<RepositoryKind> {
+    call(
+        &["rev-parse", "--is-inside-work-tree", "--is-bare-repository"],
+        true,
+    )
+    .ok()
+    .and_then(|output| parse_repository_kind(&output))
+}
+
+fn parse_repository_kind(output: &str) -> Option<RepositoryKind> {
+    let mut lines = output.lines().map(str::trim);
+    match (lines.next()?, lines.next()?) {
+        ("true", _) => Some(RepositoryKind::WorkTree),
+        ("false", "true") => Some(RepositoryKind::Bare),
+        _ => None,
+    }
+}
+
+// Whether `dir` is within a Git working tree or is a bare repository (checked without changing the current directory).
+pub fn is_repository(dir: &Path) -> bool {
+    dir.to_str()
+        .and_then(|dir| {
+            call(
+                &[
+                    "-C",
+                    dir,
+                    "rev-parse",
+                    "--is-inside-work-tree",
+                    "--is-bare-repository",
+                ],
+                true,
+            )
+            .ok()
+        })
+        .and_then(|output| parse_repository_kind(&output))
+        .is_some()
+}
+
+// Resolves `revision` (e.g., `HEAD`) to a commit SHA.
+pub fn rev_parse(revision: &str) -> orfail::Result<String> {
+    let revision = format!("{revision}^{{commit}}");
+    call(&["rev-parse", "--verify", &revision], true).map(|s| s.trim().to_owned())
+}
+
+// Returns the short name of the current branch, or `None` on a detached HEAD.
+pub fn current_branch() -> orfail::Result<Option<String>> {
+    // `--quiet` makes a detached HEAD exit with a non-zero status and no error message.
+    let output = call(&["symbolic-ref", "--quiet", "--short", "HEAD"], false).or_fail()?;
+    Ok(Some(output.trim().to_owned()).filter(|branch| !branch.is_empty()))
+}
+
+pub fn remote_url(remote: &str) -> orfail::Result<String> {
+    call(&["remote", "get-url", remote], true).map(|s| s.trim().to_owned())
+}
+
+// Converts a remote URL (e.g., `git@github.com:sile/mamegrep.git`) to the web URL of the repository
+// (e.g., `https://github.com/sile/mamegrep`).
+pub fn web_base_url(remote_url: &str) -> Option<String> {
+    let (host, path) = if let Some((scheme, rest)) = remote_url.split_once("://") {
+        if !matches!(scheme, "https" | "http" | "ssh" | "git") {
+            return None;
+        }
+        let (authority, path) = rest.split_once('/')?;
+        let host = authority
+            .rsplit_once('@')
+            .map_or(authority, |(_, host)| host);
+        // SSH ports have nothing to do with the web server.
+        let host = match (scheme, host.rsplit_once(':')) {
+            ("ssh" | "git", Some((host, _port))) => host,
+            _ => host,
+        };
+        (host, path)
+    } else {
+        // The scp-like syntax (`[user@]host:path`).
+        let (authority, path) = remote_url.split_once(':')?;
+        let host = authority
+            .rsplit_once('@')
+            .map_or(authority, |(_, host)| host);
+        (host, path)
+    };
+    let path = path.trim_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    if host.is_empty() || path.is_empty() {
+        return None;
+    }
+    Some(format!("https://{host}/{path}"))
+}
+
+// Returns the path of the current directory relative to the top of the worktree (e.g., `src/`).
+pub fn show_prefix() -> orfail::Result<String> {
+    call(&["rev-parse", "--show-prefix"], true).map(|s| s.trim().to_owned())
+}
+
+// Returns the content of `path` (relative to the current directory) at `revision`.
+pub fn show_file(revision: &str, path: &str) -> orfail::Result<String> {
+    call(&["show", &format!("{revision}:./{path}")], true)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitInfo {
+    pub hash: String,
+    pub author: String,
+    pub date: String,
+    pub subject: String,
+}
+
+impl std::fmt::Display for CommitInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "commit {}", self.hash)?;
+        writeln!(f, "Author: {}", self.author)?;
+        writeln!(f, "Date:   {}", self.date)?;
+        writeln!(f)?;
+        writeln!(f, "    {}", self.subject)
+    }
+}
+
+// Returns `None` if the file has no history (e.g., an untracked file).
+pub fn last_commit(revision: Option<&str>, path: &str) -> orfail::Result<Option<CommitInfo>> {
+    let mut args = vec![
+        "log",
+        "-1",
+        "--date=iso",
+        "--format=%H%x00%an <%ae>%x00%ad%x00%s",
+    ];
+    args.extend(revision);
+    args.push("--");
+    args.push(path);
+    let output = call(&args, true).or_fail()?;
+    Ok(parse_commit_info(&output))
+}
+
+fn parse_commit_info(output: &str) -> Option<CommitInfo> {
+    let mut fields = output.trim_end_matches('\n').splitn(4, '\0');
+    Some(CommitInfo {
+        hash: fields.next().filter(|hash| !hash.is_empty())?.to_owned(),
+        author: fields.next()?.to_owned(),
+        date: fields.next()?.to_owned(),
+        subject: fields.next()?.to_owned(),
+    })
+}
+
+// Returns the committer timestamps of the last commits (reachable from `revision`) that touched the paths.
+// Paths without history (e.g., untracked files) are omitted.
+pub fn last_commit_times(
+    revision: Option<&str>,
+    paths: &[&str],
+) -> orfail::Result<BTreeMap<String, i64>> {
+    let mut times = BTreeMap::new();
+    // Passing all paths at once may exceed the command line length limit.
+    for chunk in paths.chunks(500) {
+        let mut args = vec!["log", "--format=%x00%ct", "--name-only", "--relative"];
+        args.extend(revision);
+        args.push("--");
+        args.extend(chunk);
+        let output = call(&args, true).or_fail()?;
+        parse_commit_times(&output, &mut times);
+    }
+    Ok(times)
+}
+
+// Parses `git log --format=%x00%ct --name-only` output (newest first).
+fn parse_commit_times(output: &str, times: &mut BTreeMap<String, i64>) {
+    let mut time = None;
+    for line in output.lines() {
+        if let Some(t) = line.strip_prefix('\0') {
+            time = t.parse().ok();
+        } else if !line.is_empty()
+            && let Some(time) = time
+        {
+            times.entry(line.to_owned()).or_insert(time);
+        }
+    }
+}
+
+// Pending changes of a file in the working tree, shown as a marker after the file heading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    // Changed in the index only (i.e., ready to be committed).
+    Staged,
+    // Changed in the working tree (whether or not also staged) or unmerged.
+    Modified,
+    Untracked,
+}
+
+impl FileStatus {
+    pub fn marker(self) -> &'static str {
+        match self {
+            Self::Staged => "[S]",
+            Self::Modified => "[M]",
+            Self::Untracked => "[?]",
+        }
+    }
+
+    // `xy` is the two-letter status code of `git status --porcelain`.
+    fn from_code(xy: &str) -> Option<Self> {
+        let mut chars = xy.chars();
+        let (x, y) = (chars.next()?, chars.next()?);
+        match (x, y) {
+            ('?', '?') => Some(Self::Untracked),
+            ('!', '!') => None,
+            (_, ' ') => Some(Self::Staged),
+            _ => Some(Self::Modified),
+        }
+    }
+}
+
+// Returns the statuses of the changed files keyed by paths relative to the current directory.
+pub fn file_statuses() -> orfail::Result<BTreeMap<String, FileStatus>> {
+    let output = call(
+        &[
+            "status",
+            "--porcelain",
+            "-z",
+            "--untracked-files=all",
+            "--",
+            ".",
+        ],
+        true,
+    )
+    .or_fail()?;
+    let prefix = show_prefix().or_fail()?;
+    Ok(parse_file_statuses(&output, &prefix))
+}
+
+// Parses `git status --porcelain -z` output, whose paths are relative to the top of the worktree.
+fn parse_file_statuses(output: &str, prefix: &str) -> BTreeMap<String, FileStatus> {
+    let mut statuses = BTreeMap::new();
+    let mut entries = output.split('\0').filter(|entry| !entry.is_empty());
+    while let Some(entry) = entries.next() {
+        let Some((xy, path)) = entry.split_at_checked(2) else {
+            continue;
+        };
+        // Renamed or copied entries are followed by their original paths.
+        if xy.contains(['R', 'C']) {
+            entries.next();
+        }
+        let (Some(status), Some(path)) = (
+            FileStatus::from_code(xy),
+            path.strip_prefix(' ').and_then(|p| p.strip_prefix(prefix)),
+        ) else {
+            continue;
+        };
+        statuses.insert(path.to_owned(), status);
+    }
+    statuses
+}
+
+// Directories (relative to the current directory) containing tracked files, including the current directory.
+pub fn tracked_dirs() -> orfail::Result<Vec<PathBuf>> {
+    let output = call(&["ls-files"], true).or_fail()?;
+    let mut dirs = BTreeSet::from([PathBuf::from(".")]);
+    for file in output.lines() {
+        let mut dir = Path::new(file).parent();
+        while let Some(d) = dir.filter(|d| !d.as_os_str().is_empty()) {
+            if !dirs.insert(d.to_path_buf()) {
+                break;
+            }
+            dir = d.parent();
+        }
+    }
+    Ok(dirs.into_iter().collect())
+}
+
+pub fn show_toplevel() -> orfail::Result<PathBuf> {
+    call(&["rev-parse", "--show-toplevel"], true).map(|s| PathBuf::from(s.trim()))
+}
+
+// Whether the output consists of file headings each followed by numbered lines (and `--` separators).
+fn is_heading_format(output: &str) -> bool {
+    // Whether the current heading has lines (`None` before the first heading).
+    let mut has_lines = None;
+    for line in output.lines().filter(|line| *line != "--") {
+        if Line::parse(line).is_some() {
+            if has_lines.is_none() {
+                return false;
+            }
+            has_lines = Some(true);
+        } else if has_lines == Some(false) {
+            return false;
+        } else {
+            has_lines = Some(false);
+        }
+    }
+    has_lines != Some(false)
+}
+
+fn call(args: &[&str], check_status: bool) -> orfail::Result<String> {
+    let mut command = Command::new("git");
+    let output = command
+        .args(args)
+        .output()
+        .or_fail_with(|e| format!("Failed to execute `$ {command:?}`: {e}"))?;
+    check_output(&command, output, check_status)
+}
+
+// Returns `Ok(None)` if the command did not finish within the timeout.
+fn call_with_timeout(args: &[&str], timeout: Option<Duration>) -> orfail::Result<Option<String>> {
+    let mut command = Command::new("git");
+    command.args(args);
+    match execute(&mut command, timeout) {
+        Ok(output) => check_output(&command, output, false).map(Some),
+        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => Ok(None),
+        Err(e) => Err(orfail::Failure::new(format!(
+            "Failed to execute `$ {command:?}`: {e}"
+        ))),
+    }
+}
+
+fn check_output(command: &Command, output: Output, check_status: bool) -> orfail::Result<String> {
+    let error = |()| {
+        format!(
+            "Failed to execute `$ {command:?}`:\n{}\n",
+            String::from_utf8_lossy(&output.stderr)
+        )
+    };
+    (!check_status || output.status.success()).or_fail_with(error)?;
+    (check_status || output.stderr.is_empty()).or_fail_with(error)?;
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn execute(command: &mut Command, timeout: Option<Duration>) -> std::io::Result<Output> {
+    let Some(timeout) = timeout else {
+        return command.output();
+    };
+
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Drain the pipes in the background so that the child never blocks on a full pipe.
+    fn read_all<R: 'static + Send + Read>(
+        mut reader: R,
+    ) -> std::thread::JoinHandle<std::io::Result<Vec<u8>>> {
+        std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            reader.read_to_end(&mut buf)?;
+            Ok(buf)
+        })
+    }
+    let stdout = read_all(child.stdout.take().expect("infallible"));
+    let stderr = read_all(child.stderr.take().expect("infallible"));
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("timed out after {timeout:?}"),
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+
+    let join = |handle: std::thread::JoinHandle<_>| {
+        handle
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+    };
+    Ok(Output {
+        status,
+        stdout: join(stdout)?,
+        stderr: join(stderr)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_only_ignore_case() {
+        use RegexpEngine::*;
+
+        let fold = |pattern| ascii_case_classes(pattern, Extended);
+        assert_eq!(fold("foo"), "[fF][oO][oO]");
+        assert_eq!(fold("Ab_1 日本"), "[Aa][bB]_1 日本");
+        assert_eq!(fold("\\bfoo\\w+"), "\\b[fF][oO][oO]\\w+");
+        assert_eq!(fold("[a-z_X]+"), "[a-z_XA-Zx]+");
+        assert_eq!(fold("[^]a][[:alpha:]]"), "[^]aA][[:alpha:]]");
+        assert_eq!(fold("x{2,3}"), "[xX]{2,3}");
+        assert_eq!(fold("[ab"), "[ab");
+
+        let fold = |pattern| ascii_case_classes(pattern, Perl);
+        assert_eq!(fold("\\x41b"), "\\x41[bB]");
+        assert_eq!(fold("\\p{Lu}a\\pLb"), "\\p{Lu}[aA]\\pL[bB]");
+        assert_eq!(fold("(?<name>a)\\k<name>"), "(?<name>[aA])\\k<name>");
+        assert_eq!(fold("(?<=a)(?i)b(?P=name)"), "(?<=[aA])(?i)[bB](?P=name)");
+
+        let mut options = GrepOptions::default();
+        options.pattern.text = "foo".to_owned();
+        options.ignore_case = true;
+        options.ascii_only_ignore_case = true;
+        let texts = |options: &GrepOptions, focus| {
+            options
+                .args(focus)
+                .into_iter()
+                .map(|arg| arg.text)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            texts(&options, Focus::SearchResult),
+            ["grep", "-nI", "[fF][oO][oO]"]
+        );
+        // The focused pattern is shown as typed.
+        assert_eq!(texts(&options, Focus::Pattern), ["grep", "-nI", "foo"]);
+
+        options.set_engine(FixedStrings);
+        assert_eq!(
+            texts(&options, Focus::SearchResult),
+            ["grep", "-nIiF", "foo"]
+        );
+    }
+
+    #[test]
+    fn multiple_paths() {
+        let mut options = GrepOptions::default();
+        options.pattern.text = "foo".to_owned();
+        options.path.text = ["src/", "tests/"].join(" ");
+        let texts = |focus| {
+            options
+                .args(focus)
+                .into_iter()
+                .map(|arg| arg.text)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            texts(Focus::SearchResult),
+            ["grep", "-nI", "foo", "--", "src/", "tests/"]
+        );
+        assert_eq!(
+            texts(Focus::Path),
+            ["grep", "-nI", "foo", "--", "src/ tests/"]
+        );
+    }
+
+    #[test]
+    fn heading_format() {
+        assert!(is_heading_format(""));
+        assert!(is_heading_format(
+            "a.rs\n1:foo\n2-bar\n--\n5:foo\nb.rs\n3:foo\n"
+        ));
+        assert!(!is_heading_format("1:foo\n"));
+        assert!(!is_heading_format("a.rs\nb.rs\n1:foo\n"));
+        assert!(!is_heading_format("commit 0123abcd\n"));
+    }
+
+    #[test]
+    fn custom_grep_command() -> orfail::Result<()> {
+        let mut options = GrepOptions::default();
+        options.pattern.text = "foo".to_owned();
+        options.grep_command = [
+            "-c",
+            "alias.canned=!f() { printf 'a.rs\\n1:foo\\n'; }; f",
+            "canned",
+        ]
+        .map(str::to_owned)
+        .to_vec();
+        let result = options.call().or_fail()?;
+        assert_eq!(result.error, None);
+        assert_eq!(result.hit_lines(), 1);
+        assert_eq!(result.hit_texts(), 1);
+
+        options.grep_command[1] = "alias.canned=!f() { echo unknown; }; f".to_owned();
+        let result = options.call().or_fail()?;
+        assert!(
+            result
+                .error
+                .is_some_and(|e| e.contains("git -c alias.canned"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_search_result() -> orfail::Result<()> {
+        let output = r#"src/canvas.rs
+315:        line.draw_token(2, Token::new("foo"));
+316:        assert_eq!(line.text(), "  foo");
+"#;
+        let result = SearchResult::parse(output, Highlight::default()).or_fail()?;
+        assert_eq!(result.files.len(), 1);
+
+        let lines = result
+            .files
+            .get(&PathBuf::from("src/canvas.rs"))
+            .or_fail()?;
+        assert_eq!(lines.len(), 2);
+
+        assert_eq!(lines[0].number.get(), 315);
         assert_eq!(
             lines[0].text,
             r#"        line.draw_token(2, Token::new("foo"));"#
@@ -508,6 +2231,88 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn parse_file_list() {
+        let result = SearchResult::parse_file_list("Cargo.toml\nsrc/main.rs\n");
+        assert_eq!(
+            result.files.keys().cloned().collect::<Vec<_>>(),
+            [PathBuf::from("Cargo.toml"), PathBuf::from("src/main.rs")]
+        );
+        assert!(result.files.values().all(|lines| lines.is_empty()));
+        assert_eq!(result.hit_files(), 2);
+        assert_eq!(result.hit_lines(), 0);
+
+        let mut options = GrepOptions {
+            filename_search: true,
+            ignore_case: true,
+            ..Default::default()
+        };
+        options.pattern.text = "main".to_owned();
+        let args = options.args(Focus::SearchResult);
+        let args = args.iter().map(|a| a.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(args, ["ls-files", "--", ":(icase)*main*"]);
+    }
+
+    #[test]
+    fn parse_diff_added_lines() {
+        let diff = r#"diff --git a/src/a.rs b/src/a.rs
+index 1111111..2222222 100644
+--- a/src/a.rs
++++ b/src/a.rs
+@@ -3 +3,2 @@ fn main() {
+-    foo();
++    bar();
++++ baz();
+@@ -10,0 +12 @@
++// qux
+diff --git a/old.rs b/old.rs
+deleted file mode 100644
+--- a/old.rs
++++ /dev/null
+@@ -1 +0,0 @@
+-foo
+"#;
+        let lines = parse_added_lines(diff)
+            .into_iter()
+            .map(|(path, line)| (path.display().to_string(), line.number.get(), line.text))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                ("src/a.rs".to_owned(), 3, "    bar();".to_owned()),
+                ("src/a.rs".to_owned(), 4, "++ baz();".to_owned()),
+                ("src/a.rs".to_owned(), 12, "// qux".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn filter_added_lines() -> orfail::Result<()> {
+        let mut options = GrepOptions::default();
+        options.pattern.text = "ba.".to_owned();
+        let texts = ["foo bar", "baz", "qux"];
+        assert_eq!(
+            options.filter_lines(&texts, "ba.", false).or_fail()?,
+            [0, 1]
+        );
+        assert_eq!(options.filter_lines(&texts, "z", true).or_fail()?, [0, 2]);
+        assert_eq!(
+            options.match_texts(&texts).or_fail()?,
+            [vec!["bar".to_owned()], vec!["baz".to_owned()], vec![]]
+        );
+
+        // The regexp flags are shared with `git grep`.
+        options.fixed_strings = true;
+        assert_eq!(
+            options.filter_lines(&texts, "ba.", false).or_fail()?,
+            [0; 0]
+        );
+        options.fixed_strings = false;
+        options.ignore_case = true;
+        assert_eq!(options.filter_lines(&texts, "QUX", false).or_fail()?, [2]);
+        Ok(())
+    }
+
     #[test]
     fn parse_highlight() -> orfail::Result<()> {
         let output = r#"src/canvas.rs
@@ -523,4 +2328,655 @@ src/git.rs
 
         Ok(())
     }
+
+    #[test]
+    fn highlight_texts_sorted_by_column() -> orfail::Result<()> {
+        let output = "a.rs\n1:9:foo\n1:1:bar\n1:5:baz\n";
+        let highlight = Highlight::parse_with_columns(output).or_fail()?;
+        let result = SearchResult::parse("a.rs\n1:bar baz foo\n", highlight).or_fail()?;
+        let file = PathBuf::from("a.rs");
+        let line = &result.files[&file][0];
+
+        let number = NonZeroUsize::MIN;
+        assert_eq!(
+            result.hit_texts_in_line(&file, number),
+            ["bar", "baz", "foo"]
+        );
+        assert_eq!(result.hit_ranges_in_line(&file, line), [0..3, 4..7, 8..11]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn query_kind() {
+        let kind = |pattern: &str, and_pattern: &str, not_pattern: &str| {
+            let mut options = GrepOptions::default();
+            options.pattern.text = pattern.to_owned();
+            options.and_pattern.text = and_pattern.to_owned();
+            options.not_pattern.text = not_pattern.to_owned();
+            options.query_kind()
+        };
+        assert_eq!(kind("", "", ""), QueryKind::Empty);
+        assert_eq!(kind("a", "", ""), QueryKind::Ready);
+        assert_eq!(kind("a", "b", ""), QueryKind::Ready);
+        assert_eq!(kind("a", "", "c"), QueryKind::Ready);
+        assert_eq!(kind("a", "b", "c"), QueryKind::Ready);
+        assert_eq!(kind("", "b", ""), QueryKind::AndOnly);
+        assert_eq!(kind("", "b", "c"), QueryKind::AndOnly);
+        assert_eq!(kind("", "", "c"), QueryKind::NotOnly);
+
+        // Neither of them runs `git`.
+        let mut options = GrepOptions::default();
+        assert!(options.call().expect("infallible").error.is_none());
+        options.not_pattern.text = "c".to_owned();
+        let result = options.call().expect("infallible");
+        assert!(result.error.is_some());
+        assert!(result.files.is_empty());
+    }
+
+    #[test]
+    fn promote_and_pattern() {
+        let mut options = GrepOptions::default();
+        options.and_pattern.text = "foo".to_owned();
+        options.not_pattern.text = "bar".to_owned();
+        options.promote_and_pattern();
+        assert_eq!(options.pattern.text, "foo");
+        assert!(options.and_pattern.is_empty());
+        assert_eq!(options.not_pattern.text, "bar");
+
+        // An existing primary pattern is kept as is.
+        options.and_pattern.text = "baz".to_owned();
+        options.promote_and_pattern();
+        assert_eq!(options.pattern.text, "foo");
+        assert_eq!(options.and_pattern.text, "baz");
+
+        // Only a not-pattern is not enough to search.
+        let mut options = GrepOptions::default();
+        options.not_pattern.text = "bar".to_owned();
+        options.promote_and_pattern();
+        assert!(options.pattern.is_empty());
+    }
+
+    #[test]
+    fn swap_pattern_and() {
+        fn args(options: &GrepOptions) -> Vec<String> {
+            options
+                .args(Focus::SearchResult)
+                .into_iter()
+                .map(|a| a.text)
+                .collect()
+        }
+
+        let mut options = GrepOptions::default();
+        options.pattern.text = "foo".to_owned();
+        options.and_pattern.text = "bar".to_owned();
+        options.swap_pattern_and();
+        assert_eq!(options.pattern.kind, GrepArgKind::Pattern);
+        assert_eq!(options.and_pattern.kind, GrepArgKind::AndPattern);
+        assert_eq!(
+            args(&options),
+            ["grep", "-nI", "-e", "bar", "--and", "-e", "foo"]
+        );
+
+        // An empty and-pattern is dropped along with the `-e` prefix of the pattern.
+        options.pattern.text.clear();
+        options.swap_pattern_and();
+        assert_eq!(args(&options), ["grep", "-nI", "foo"]);
+
+        // The `-e` prefix is kept while a not-pattern exists.
+        options.and_pattern.text = "bar".to_owned();
+        options.not_pattern.text = "baz".to_owned();
+        options.swap_pattern_and();
+        assert_eq!(
+            args(&options),
+            [
+                "grep", "-nI", "-e", "bar", "--and", "-e", "foo", "--and", "--not", "-e", "baz"
+            ]
+        );
+    }
+
+    #[test]
+    fn or_secondary_args() {
+        fn args(options: &GrepOptions) -> Vec<String> {
+            options
+                .args(Focus::SearchResult)
+                .into_iter()
+                .map(|a| a.text)
+                .collect()
+        }
+
+        let mut options = GrepOptions::default();
+        options.pattern.text = "foo".to_owned();
+        options.and_pattern.text = "bar".to_owned();
+        assert_eq!(
+            args(&options),
+            ["grep", "-nI", "-e", "foo", "--and", "-e", "bar"]
+        );
+
+        options.or_secondary = true;
+        assert_eq!(
+            args(&options),
+            ["grep", "-nI", "-e", "foo", "--or", "-e", "bar"]
+        );
+
+        // The alternatives are grouped so that the not-pattern applies to both.
+        options.not_pattern.text = "baz".to_owned();
+        assert_eq!(
+            args(&options),
+            [
+                "grep", "-nI", "(", "-e", "foo", "--or", "-e", "bar", ")", "--and", "--not", "-e",
+                "baz"
+            ]
+        );
+        assert_eq!(
+            options
+                .args(Focus::SearchResult)
+                .iter()
+                .map(|a| a.maybe_quoted_text(Focus::SearchResult))
+                .filter(|t| t.contains('('))
+                .collect::<Vec<_>>(),
+            ["'('"]
+        );
+
+        // Without an and-pattern, the mode has no effect.
+        options.and_pattern.text.clear();
+        assert_eq!(
+            args(&options),
+            ["grep", "-nI", "-e", "foo", "--and", "--not", "-e", "baz"]
+        );
+    }
+
+    #[test]
+    fn dash_prefixed_pattern() {
+        let mut options = GrepOptions::default();
+        options.pattern.text = "- item".to_owned();
+        let args = options
+            .args(Focus::SearchResult)
+            .into_iter()
+            .map(|a| a.text)
+            .collect::<Vec<_>>();
+        assert_eq!(args, ["grep", "-nI", "-e", "- item"]);
+    }
+
+    #[test]
+    fn repository_kind_detection() -> orfail::Result<()> {
+        assert_eq!(
+            parse_repository_kind("true\nfalse\n"),
+            Some(RepositoryKind::WorkTree)
+        );
+        assert_eq!(
+            parse_repository_kind("false\ntrue\n"),
+            Some(RepositoryKind::Bare)
+        );
+        // The `.git` directory of a non-bare repository.
+        assert_eq!(parse_repository_kind("false\nfalse\n"), None);
+        assert_eq!(parse_repository_kind(""), None);
+
+        // A revision is required to search a bare repository.
+        let mut options = GrepOptions {
+            bare: true,
+            ..Default::default()
+        };
+        options.pattern.text = "foo".to_owned();
+        let result = options.call().or_fail()?;
+        assert!(result.error.is_some_and(|e| e.contains("revision")));
+        Ok(())
+    }
+
+    #[test]
+    fn commit_times() {
+        let output = "\x00300\n\nsrc/a.rs\nsrc/b.rs\n\x00200\n\nsrc/a.rs\nsrc/c.rs\n";
+        let mut times = BTreeMap::new();
+        parse_commit_times(output, &mut times);
+        assert_eq!(
+            times.into_iter().collect::<Vec<_>>(),
+            [
+                ("src/a.rs".to_owned(), 300),
+                ("src/b.rs".to_owned(), 300),
+                ("src/c.rs".to_owned(), 200),
+            ]
+        );
+    }
+
+    #[test]
+    fn commit_info() {
+        let output =
+            "0123abc\x00Alice <alice@example.com>\x002024-01-02 03:04:05 +0900\x00Fix: a\x00b\n";
+        let info = parse_commit_info(output).expect("commit info");
+        assert_eq!(info.hash, "0123abc");
+        assert_eq!(info.author, "Alice <alice@example.com>");
+        assert_eq!(info.date, "2024-01-02 03:04:05 +0900");
+        assert_eq!(info.subject, "Fix: a\x00b");
+        assert_eq!(
+            info.to_string(),
+            "commit 0123abc\nAuthor: Alice <alice@example.com>\nDate:   2024-01-02 03:04:05 +0900\n\n    Fix: a\x00b\n"
+        );
+
+        // No history.
+        assert_eq!(parse_commit_info(""), None);
+        assert_eq!(parse_commit_info("0123abc\x00Alice"), None);
+    }
+
+    #[test]
+    fn extension_filter() {
+        assert_eq!(extension_pathspecs("rs"), ["*.rs"]);
+        assert_eq!(
+            extension_pathspecs(".rs, toml,*.md"),
+            ["*.rs", "*.toml", "*.md"]
+        );
+        assert_eq!(extension_pathspecs("rs,.rs,,  ,."), ["*.rs"]);
+        assert_eq!(extension_pathspecs("tar.gz"), ["*.tar.gz"]);
+        assert!(extension_pathspecs("src/*").is_empty());
+
+        // Only the extension pathspecs are replaced.
+        let mut options = GrepOptions::default();
+        options.path.text = "src/ *.rs".to_owned();
+        assert_eq!(options.extensions(), ["rs"]);
+        options.set_extensions(".toml,md");
+        assert_eq!(options.path.text, "src/ *.toml *.md");
+        assert_eq!(options.extensions(), ["toml", "md"]);
+        options.set_extensions("");
+        assert_eq!(options.path.text, "src/");
+        assert!(options.extensions().is_empty());
+    }
+
+    #[test]
+    fn repository_check() -> orfail::Result<()> {
+        assert!(is_repository(Path::new(env!("CARGO_MANIFEST_DIR"))));
+        assert!(is_repository(Path::new("src")));
+        assert!(!is_repository(Path::new("no/such/dir")));
+
+        let bare = std::env::temp_dir().join(format!("mamegrep-bare-{}", std::process::id()));
+        call(
+            &["init", "--quiet", "--bare", bare.to_str().or_fail()?],
+            true,
+        )
+        .or_fail()?;
+        assert!(is_repository(&bare));
+        std::fs::remove_dir_all(&bare).or_fail()?;
+        Ok(())
+    }
+
+    #[test]
+    fn regex_escaping() {
+        let text = r"a.b*c[d]^$\ (e)+f?{1}|g";
+        assert_eq!(
+            escape_regex(text, RegexpEngine::Basic),
+            r"a\.b\*c\[d]\^\$\\ (e)+f?{1}|g"
+        );
+        assert_eq!(
+            escape_regex(text, RegexpEngine::Extended),
+            r"a\.b\*c\[d]\^\$\\ \(e\)\+f\?\{1}\|g"
+        );
+        assert_eq!(
+            escape_regex(text, RegexpEngine::Perl),
+            r"a\.b\*c\[d\]\^\$\\ \(e\)\+f\?\{1\}\|g"
+        );
+        assert_eq!(escape_regex(text, RegexpEngine::FixedStrings), text);
+
+        // Only the focused pattern is escaped.
+        let mut options = GrepOptions::default();
+        options.pattern.text = "a.b".to_owned();
+        options.and_pattern.text = "c.d".to_owned();
+        options.escape_pattern(Focus::AndPattern);
+        assert_eq!(options.pattern.text, "a.b");
+        assert_eq!(options.and_pattern.text, r"c\.d");
+        options.escape_pattern(Focus::SearchResult);
+        assert_eq!(options.pattern.text, r"a\.b");
+    }
+
+    #[test]
+    fn pathspec_file() -> orfail::Result<()> {
+        let path = std::env::temp_dir().join(format!("mamegrep-pathspecs-{}", std::process::id()));
+        std::fs::write(&path, "src/a.rs\r\n\nsrc/b c.rs\n").or_fail()?;
+        let file = PathspecFile::read(&path, false)?;
+        assert_eq!(file.pathspecs, ["src/a.rs", "src/b c.rs"]);
+
+        // A NUL-separated list may contain newlines in the paths.
+        std::fs::write(&path, "a\nb\0c\0").or_fail()?;
+        let file = PathspecFile::read(&path, true)?;
+        assert_eq!(file.pathspecs, ["a\nb", "c"]);
+
+        // An empty list would search every file.
+        std::fs::write(&path, "\n\n").or_fail()?;
+        assert!(PathspecFile::read(&path, false).is_err());
+        std::fs::remove_file(&path).or_fail()?;
+        assert!(PathspecFile::read(&path, false).is_err());
+
+        let args = |options: &GrepOptions| {
+            options
+                .args(Focus::SearchResult)
+                .into_iter()
+                .map(|a| a.text)
+                .collect::<Vec<_>>()
+        };
+        let mut options = GrepOptions::default();
+        options.pattern.text = "foo".to_owned();
+        options.excluded_paths = vec![":(exclude)tests/*".to_owned()];
+        options.pathspec_file = Some(PathspecFile {
+            path: PathBuf::from("list"),
+            nul: false,
+            pathspecs: vec!["a".to_owned(), "b".to_owned()],
+        });
+        assert_eq!(
+            args(&options),
+            ["grep", "-nI", "foo", "--", "a", "b", ":(exclude)tests/*"]
+        );
+
+        // After the path field.
+        options.path.text = "src/".to_owned();
+        assert_eq!(
+            args(&options),
+            [
+                "grep",
+                "-nI",
+                "foo",
+                "--",
+                "src/",
+                "a",
+                "b",
+                ":(exclude)tests/*"
+            ]
+        );
+        assert_eq!(
+            options.cli_args(),
+            [
+                "mamegrep",
+                "-p",
+                "src/",
+                "--pathspec-from-file",
+                "list",
+                "foo"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn toggle_exclusions() {
+        let globs = ["*_test.*".to_owned(), "tests/*".to_owned()];
+        let mut options = GrepOptions::default();
+        options.pattern.text = "foo".to_owned();
+        options.excluded_paths = vec![":(exclude)tests/*".to_owned()];
+        assert!(!options.is_excluding(&globs));
+
+        // Only the missing pathspecs are added.
+        options.toggle_exclusions(&globs);
+        assert!(options.is_excluding(&globs));
+        assert_eq!(
+            options.excluded_paths,
+            [":(exclude)tests/*", ":(exclude)*_test.*"]
+        );
+        let args = options.args(Focus::SearchResult);
+        assert_eq!(
+            args.iter().map(|a| a.text.as_str()).collect::<Vec<_>>(),
+            [
+                "grep",
+                "-nI",
+                "foo",
+                "--",
+                ":(exclude)tests/*",
+                ":(exclude)*_test.*"
+            ]
+        );
+
+        // Repeated toggles never accumulate duplicates.
+        options.toggle_exclusions(&globs);
+        assert!(options.excluded_paths.is_empty());
+        options.toggle_exclusions(&globs);
+        options.toggle_exclusions(&globs);
+        options.toggle_exclusions(&globs);
+        assert_eq!(options.excluded_paths.len(), 2);
+    }
+
+    #[test]
+    fn set_engine() {
+        let mut options = GrepOptions {
+            extended_regexp: true,
+            ..Default::default()
+        };
+        options.set_engine(RegexpEngine::Perl);
+        assert_eq!(options.engine(), RegexpEngine::Perl);
+        assert!(!options.extended_regexp && !options.fixed_strings);
+
+        options.set_engine(RegexpEngine::FixedStrings);
+        assert_eq!(options.engine(), RegexpEngine::FixedStrings);
+        assert!(!options.perl_regexp);
+
+        options.set_engine(RegexpEngine::Basic);
+        assert!(!options.extended_regexp && !options.perl_regexp && !options.fixed_strings);
+    }
+
+    #[test]
+    fn term_hit_ranges() -> orfail::Result<()> {
+        let output = "a.rs\n1:foo bar foo\n";
+        let mut highlight = Highlight::parse("a.rs\n1:foo\n1:bar\n1:foo\n").or_fail()?;
+        highlight.terms = vec![
+            (
+                "foo".to_owned(),
+                Highlight::parse("a.rs\n1:foo\n1:foo\n").or_fail()?.lines,
+            ),
+            (
+                "bar".to_owned(),
+                Highlight::parse("a.rs\n1:bar\n").or_fail()?.lines,
+            ),
+        ];
+        let result = SearchResult::parse(output, highlight).or_fail()?;
+        let file = PathBuf::from("a.rs");
+        let line = &result.files[&file][0];
+
+        assert_eq!(result.hit_ranges_in_line(&file, line), [0..3, 4..7, 8..11]);
+        assert_eq!(
+            result.term_hit_ranges_in_line(0, &file, line),
+            [0..3, 8..11]
+        );
+        assert_eq!(result.term_hit_ranges_in_line(1, &file, line), vec![4..7]);
+        assert!(result.term_hit_ranges_in_line(2, &file, line).is_empty());
+
+        let options = GrepOptions {
+            highlight_terms: true,
+            pattern: GrepArg {
+                text: "foo".to_owned(),
+                ..GrepArg::new(GrepArgKind::Pattern)
+            },
+            and_pattern: GrepArg {
+                text: "bar".to_owned(),
+                ..GrepArg::new(GrepArgKind::AndPattern)
+            },
+            ..Default::default()
+        };
+        assert_eq!(options.highlighted_terms(), ["foo", "bar"]);
+        let args = options.build_grep_args(Mode::TermHighlight("bar"), Focus::SearchResult);
+        let args = args.iter().map(|a| a.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            args,
+            ["grep", "-nI", "-o", "--column", "--heading", "-e", "bar"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn execute_with_timeout() -> orfail::Result<()> {
+        let timeout = Some(Duration::from_millis(100));
+
+        let start = Instant::now();
+        let error = execute(Command::new("sleep").arg("5"), timeout)
+            .err()
+            .or_fail()?;
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let output = execute(Command::new("echo").arg("foo"), timeout).or_fail()?;
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"foo\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn textconv_args() {
+        let mut options = GrepOptions::default();
+        options.pattern.text = "foo".to_owned();
+        let args = |options: &GrepOptions, mode| {
+            options
+                .build_grep_args(mode, Focus::SearchResult)
+                .into_iter()
+                .map(|a| a.text)
+                .collect::<Vec<_>>()
+        };
+
+        // Git's default (`--no-textconv`) is not passed explicitly.
+        assert_eq!(args(&options, Mode::External), ["grep", "-nI", "foo"]);
+
+        options.textconv = true;
+        assert_eq!(
+            args(&options, Mode::External),
+            ["grep", "-nI", "--textconv", "foo"]
+        );
+        assert_eq!(
+            args(&options, Mode::Highlight),
+            [
+                "grep",
+                "-nI",
+                "--textconv",
+                "-o",
+                "--column",
+                "--heading",
+                "foo"
+            ]
+        );
+        assert_eq!(options.enabled_flags().collect::<Vec<_>>(), ["textconv"]);
+    }
+
+    #[test]
+    fn directory_hits() -> orfail::Result<()> {
+        let output = "a.rs\n1:x x\nsrc/b.rs\n1:x\nsrc/c/d.rs\n1:x\ntests/e.rs\n1:x x\n2:x\n";
+        let highlight =
+            "a.rs\n1:x\n1:x\nsrc/b.rs\n1:x\nsrc/c/d.rs\n1:x\ntests/e.rs\n1:x\n1:x\n2:x\n";
+        let result =
+            SearchResult::parse(output, Highlight::parse(highlight).or_fail()?).or_fail()?;
+        assert_eq!(
+            result.directory_hits(),
+            [
+                ("tests".to_owned(), 3),
+                (".".to_owned(), 2),
+                ("src".to_owned(), 2)
+            ]
+        );
+
+        assert_eq!(top_level_dir(Path::new("a.rs")), ".");
+        assert_eq!(top_level_dir(Path::new("src/c/d.rs")), "src");
+        assert!(SearchResult::default().directory_hits().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn line_number_width() -> orfail::Result<()> {
+        let result = SearchResult::parse("a.rs\n9:a\n120:b\n", Highlight::default()).or_fail()?;
+        assert_eq!(result.line_number_width(None), 3);
+        assert_eq!(result.line_number_width(Some(5)), 5);
+        assert_eq!(result.line_number_width(Some(2)), 3);
+
+        // File-only results (e.g., `-l`) and empty ones have no line numbers.
+        let result = SearchResult::parse_file_list("a.rs\n");
+        assert_eq!(result.line_number_width(None), 1);
+        assert_eq!(SearchResult::default().line_number_width(None), 1);
+        assert_eq!(SearchResult::default().line_number_width(Some(4)), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn web_base_url_from_remote() {
+        let expected = Some("https://github.com/sile/mamegrep".to_owned());
+        for remote in [
+            "git@github.com:sile/mamegrep.git",
+            "git@github.com:sile/mamegrep",
+            "ssh://git@github.com/sile/mamegrep.git",
+            "ssh://git@github.com:22/sile/mamegrep.git",
+            "https://github.com/sile/mamegrep.git",
+            "https://github.com/sile/mamegrep/",
+            "https://user@github.com/sile/mamegrep",
+        ] {
+            assert_eq!(web_base_url(remote), expected, "{remote}");
+        }
+        assert_eq!(
+            web_base_url("https://gitlab.example.com:8443/group/sub/repo.git").as_deref(),
+            Some("https://gitlab.example.com:8443/group/sub/repo")
+        );
+
+        for remote in [
+            "/path/to/repo.git",
+            "file:///path/to/repo.git",
+            "github.com:",
+            "",
+        ] {
+            assert_eq!(web_base_url(remote), None, "{remote}");
+        }
+    }
+
+    #[test]
+    fn file_statuses() {
+        let output = [
+            " M src/a.rs",
+            "M  src/b.rs",
+            "MM src/c.rs",
+            "?? src/new.rs",
+            "R  src/renamed.rs",
+            "src/old.rs",
+            "UU src/conflict.rs",
+            " D src/deleted.rs",
+            "A  README.md",
+            "",
+        ]
+        .join("\0");
+        let statuses = parse_file_statuses(&output, "src/");
+        let markers = statuses
+            .iter()
+            .map(|(path, status)| (path.as_str(), status.marker()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            markers,
+            [
+                ("a.rs", "[M]"),
+                ("b.rs", "[S]"),
+                ("c.rs", "[M]"),
+                ("conflict.rs", "[M]"),
+                ("deleted.rs", "[M]"),
+                ("new.rs", "[?]"),
+                ("renamed.rs", "[S]"),
+            ]
+        );
+
+        // Without a prefix (i.e., at the top of the worktree), all paths are kept as is.
+        let statuses = parse_file_statuses(&output, "");
+        assert_eq!(statuses.get("README.md"), Some(&FileStatus::Staged));
+        assert_eq!(statuses.get("src/old.rs"), None);
+    }
+
+    #[test]
+    fn insert_context_lines() -> orfail::Result<()> {
+        let file = PathBuf::from("a.rs");
+        let mut result =
+            SearchResult::parse("a.rs\n2:foo\n4:foo\n", Highlight::default()).or_fail()?;
+        let content = ["a", "foo", "c", "foo", "e"].map(str::to_owned);
+        let number = |n| NonZeroUsize::new(n).expect("non-zero");
+        let lines = |result: &SearchResult| {
+            result.files[&file]
+                .iter()
+                .map(|l| format!("{}{}{}", l.number, if l.hit { ':' } else { '-' }, l.text))
+                .collect::<Vec<_>>()
+        };
+
+        assert!(result.insert_context_lines(&file, number(2), 1, &content));
+        assert_eq!(lines(&result), ["1-a", "2:foo", "3-c", "4:foo"]);
+
+        // Clamped to the end of the file, and the existing lines are not duplicated.
+        assert!(result.insert_context_lines(&file, number(4), 9, &content));
+        assert_eq!(lines(&result), ["1-a", "2:foo", "3-c", "4:foo", "5-e"]);
+        assert!(!result.insert_context_lines(&file, number(4), 1, &content));
+
+        assert!(!result.insert_context_lines(&PathBuf::from("b.rs"), number(1), 1, &content));
+        Ok(())
+    }
 }
@@ -1,7 +1,14 @@
 pub mod action;
 pub mod app;
+pub mod bench;
 pub mod canvas;
+pub mod clipboard;
+pub mod config;
+pub mod export;
 pub mod git;
+pub mod replace;
+pub mod settings;
+pub mod watch;
 pub mod widget_command_editor;
 pub mod widget_legend;
 pub mod widget_search_result;
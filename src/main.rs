@@ -1,9 +1,15 @@
-use std::path::PathBuf;
+use std::{
+    io::{BufRead, IsTerminal},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use mame::action::BindingConfig;
 use mamegrep::{
     app::App,
-    git::{self, GrepOptions},
+    config,
+    git::{self, GrepOptions, PathspecFile},
 };
 use orfail::OrFail;
 
@@ -18,13 +24,20 @@ fn main() -> noargs::Result<()> {
         return Ok(());
     }
     noargs::HELP_FLAG.take_help(&mut args).is_present();
+    let verbose_help = noargs::flag("verbose-help")
+        .doc("Print help including the options for debugging")
+        .take_help(&mut args)
+        .is_present();
 
-    let config_path: Option<PathBuf> = noargs::opt("config")
+    let mut config_path: Option<PathBuf> = noargs::opt("config")
         .short('c')
         .ty("PATH")
         .doc(concat!(
             "Path to configuration file\n",
             "\n",
+            "If omitted, `.mamegrep.jsonc` at the top of the repository is used (if any).\n",
+            "Its `setup` and `bindings` contexts override the default ones.\n",
+            "\n",
             "Default: https://github.com/sile/mamegrep/blob/main/configs/default.jsonc"
         ))
         .example("/path/to/config.jsonc")
@@ -32,57 +45,303 @@ fn main() -> noargs::Result<()> {
         .take(&mut args)
         .present_and_then(|a| a.value().parse())?;
 
+    take_query_options(&mut args, &mut options)?;
+    let auto_open_single = noargs::flag("auto-open-single")
+        .doc(concat!(
+            "If the search of the given pattern has exactly one hit line,\n",
+            "run the first `execute-command` binding for it right after the launch"
+        ))
+        .take(&mut args)
+        .is_present();
+    let no_print_command = noargs::flag("no-print-command")
+        .doc("Do not print the `git grep` command of the last query on exit")
+        .take(&mut args)
+        .is_present();
+    // Hidden from the normal help.
+    let bench_iterations: Option<usize> = if !args.metadata().help_mode || verbose_help {
+        noargs::opt("bench")
+            .ty("N")
+            .doc(concat!(
+                "Run the search N times without the TUI and print the timing percentiles\n",
+                "(in seconds) of the `git grep` call, the parse, and the highlight parse as JSON"
+            ))
+            .take(&mut args)
+            .present_and_then(|a| a.value().parse())?
+    } else {
+        None
+    };
+    options.pattern.text = take_pattern(&mut args)?;
+    if let Some(help) = args.finish()? {
+        print!("{help}");
+        return Ok(());
+    }
+
+    // The TUI reads keys from STDIN, so it must be a pipe (not the terminal) to be consumed here.
+    let pattern_from_stdin = options.pattern.text.is_empty() && !std::io::stdin().is_terminal();
+    if pattern_from_stdin {
+        options.pattern.text = read_pattern(std::io::stdin().lock()).or_fail()?;
+    }
+
+    if let Some(dir) = options.repo_dir.clone() {
+        if let Err(e) = check_repo_dir(&dir) {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+        config_path = config_path.map(std::path::absolute).transpose().or_fail()?;
+        // Changing the directory (rather than passing `-C` to each `git` call) also makes
+        // the result paths valid for reading files and for external programs like the pager.
+        std::env::set_current_dir(&dir).or_fail()?;
+    }
+
+    let Some(repository_kind) = git::repository_kind() else {
+        eprintln!("error: no `git` command found, or not a Git directory");
+        std::process::exit(1);
+    };
+    options.bare = repository_kind == git::RepositoryKind::Bare;
+
+    if let Some(iterations) = bench_iterations {
+        println!("{}", mamegrep::bench::run(&options, iterations).or_fail()?);
+        return Ok(());
+    }
+
+    let repo_config_path = git::show_toplevel()
+        .ok()
+        .map(|dir| dir.join(config::REPO_CONFIG_FILE_NAME))
+        .filter(|path| path.exists());
+    let config = if let Some(path) = config_path {
+        BindingConfig::load_from_file(path)?
+    } else if let Some(path) = repo_config_path {
+        config::load_repo_config(&path)?
+    } else {
+        config::load_default_config()?
+    };
+
+    if pattern_from_stdin {
+        reopen_stdin_as_tty().or_fail()?;
+    }
+    let mut app = App::new(options, config).or_fail()?;
+    app.set_auto_open_single(auto_open_single);
+    app.set_print_command(!no_print_command);
+    app.run().or_fail()?;
+
+    Ok(())
+}
+
+// The options reproduced by `GrepOptions::cli_args()`.
+fn take_query_options(args: &mut noargs::RawArgs, options: &mut GrepOptions) -> noargs::Result<()> {
+    options.repo_dir = noargs::opt("repo")
+        .short('C')
+        .ty("PATH")
+        .doc("Search the repository at PATH as if mamegrep was started there (like `git -C`)")
+        .take(args)
+        .present_and_then(|a| a.value().parse())?;
     options.and_pattern.text = noargs::opt("and-pattern")
         .short('a')
         .ty("PATTERN")
         .doc("`--and` search pattern")
-        .take(&mut args)
+        .take(args)
         .present_and_then(|a| a.value().parse())?
         .unwrap_or_default();
     options.not_pattern.text = noargs::opt("not-pattern")
         .short('n')
         .ty("PATTERN")
         .doc("`--not` search pattern")
-        .take(&mut args)
+        .take(args)
         .present_and_then(|a| a.value().parse())?
         .unwrap_or_default();
     options.revision.text = noargs::opt("revision")
         .short('r')
         .ty("REVISION")
         .doc("Revision")
-        .take(&mut args)
+        .take(args)
         .present_and_then(|a| a.value().parse())?
         .unwrap_or_default();
-    options.path.text = noargs::opt("path")
-        .short('p')
+    options.path.text = take_paths(args)?.join(" ");
+    options.timeout = noargs::opt("timeout")
+        .ty("SECONDS")
+        .doc("Abort `git grep` calls that take longer than this (no timeout by default)")
+        .take(args)
+        .present_and_then(|a| a.value().parse().map(Duration::from_secs))?;
+    let pathspec_file: Option<PathBuf> = noargs::opt("pathspec-from-file")
         .ty("PATH")
-        .doc("Path")
-        .take(&mut args)
-        .present_and_then(|a| a.value().parse())?
-        .unwrap_or_default();
-    options.pattern.text = noargs::arg("PATTERN")
-        .doc("Search pattern")
-        .take(&mut args)
+        .doc(concat!(
+            "Search only the pathspecs listed in the file (one per line, like `git add`)\n",
+            "\n",
+            "The file is read at the launch and its entries are passed to `git grep` as pathspecs."
+        ))
+        .take(args)
+        .present_and_then(|a| a.value().parse())?;
+    let pathspec_file_nul = noargs::flag("pathspec-file-nul")
+        .doc("The entries of `--pathspec-from-file` are separated by NUL (e.g., `git ls-files -z`)")
+        .take(args)
+        .is_present();
+    options.pathspec_file = pathspec_file
+        .map(|path| PathspecFile::read(path, pathspec_file_nul))
+        .transpose()?;
+    Ok(())
+}
+
+fn check_repo_dir(dir: &Path) -> Result<(), String> {
+    if !git::is_repository(dir) {
+        return Err(format!("`{}` is not in a Git repository", dir.display()));
+    }
+    Ok(())
+}
+
+// Must be taken after all the options and flags, as it takes the first remaining arg.
+fn take_pattern(args: &mut noargs::RawArgs) -> noargs::Result<String> {
+    let pattern = noargs::arg("PATTERN")
+        .doc(concat!(
+            "Search pattern\n",
+            "\n",
+            "If omitted and STDIN is not a terminal (e.g., `echo foo | mamegrep`),\n",
+            "the first line of STDIN is used."
+        ))
+        .take(args)
         .present_and_then(|a| a.value().parse())?
         .unwrap_or_default();
-    if let Some(help) = args.finish()? {
-        print!("{help}");
-        return Ok(());
+    Ok(pattern)
+}
+
+fn read_pattern(mut reader: impl BufRead) -> std::io::Result<String> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+// Replaces the consumed STDIN with the controlling terminal for the key input of the TUI.
+fn reopen_stdin_as_tty() -> std::io::Result<()> {
+    let tty = std::fs::File::options()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")?;
+    // SAFETY: `tty` is a valid file descriptor, and `dup2()` leaves it owned by `tty`.
+    if unsafe { libc::dup2(tty.as_raw_fd(), libc::STDIN_FILENO) } == -1 {
+        return Err(std::io::Error::last_os_error());
     }
+    Ok(())
+}
 
-    if !git::is_available() {
-        eprintln!("error: no `git` command found, or not a Git directory");
-        std::process::exit(1);
-    };
+// `-p` can be given multiple times.
+fn take_paths(args: &mut noargs::RawArgs) -> noargs::Result<Vec<String>> {
+    let mut paths = Vec::new();
+    while let Some(path) = noargs::opt("path")
+        .short('p')
+        .ty("PATH")
+        .doc("Path (can be specified multiple times)")
+        .take(args)
+        .present_and_then(|a| a.value().parse::<String>())?
+    {
+        paths.push(path);
+    }
+    Ok(paths)
+}
 
-    let config = if let Some(path) = config_path {
-        BindingConfig::load_from_file(path)?
-    } else {
-        BindingConfig::load_from_str("<DEFAULT>", include_str!("../configs/default.jsonc"))?
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let app = App::new(options, config).or_fail()?;
-    app.run().or_fail()?;
+    #[test]
+    fn multiple_paths() -> noargs::Result<()> {
+        let raw = ["mamegrep", "-p", "src/", "foo", "--path", "tests/"];
+        let mut args = noargs::RawArgs::new(raw.iter().map(|a| a.to_string()));
+        assert_eq!(take_paths(&mut args)?, ["src/", "tests/"]);
 
-    Ok(())
+        let mut args = noargs::RawArgs::new(["mamegrep", "foo"].iter().map(|a| a.to_string()));
+        assert!(take_paths(&mut args)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn pattern_from_reader() -> std::io::Result<()> {
+        assert_eq!(read_pattern(&b"foo bar\r\nbaz\n"[..])?, "foo bar");
+        assert_eq!(read_pattern(&b"foo"[..])?, "foo");
+        assert_eq!(read_pattern(&b""[..])?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn cli_args_round_trip() -> noargs::Result<()> {
+        let mut options = GrepOptions::default();
+        options.pattern.text = "foo bar".to_owned();
+        options.and_pattern.text = "-x".to_owned();
+        options.not_pattern.text = "it's".to_owned();
+        options.revision.text = "HEAD~1".to_owned();
+        options.path.text = "src/ tests/".to_owned();
+        options.timeout = Some(Duration::from_secs(3));
+        options.ignore_case = true;
+
+        let mut args = noargs::RawArgs::new(options.cli_args().into_iter());
+        let mut parsed = GrepOptions::default();
+        take_query_options(&mut args, &mut parsed)?;
+        parsed.pattern.text = take_pattern(&mut args)?;
+        assert!(args.finish()?.is_none());
+
+        assert_eq!(parsed.pattern.text, options.pattern.text);
+        assert_eq!(parsed.and_pattern.text, options.and_pattern.text);
+        assert_eq!(parsed.not_pattern.text, options.not_pattern.text);
+        assert_eq!(parsed.revision.text, options.revision.text);
+        assert_eq!(parsed.path.text, options.path.text);
+        assert_eq!(parsed.timeout, options.timeout);
+        // Flags are not reproduced.
+        assert!(!parsed.ignore_case);
+
+        assert_eq!(
+            options.cli_command_line(),
+            r"mamegrep -a -x -n 'it'\''s' -r HEAD~1 -p src/ -p tests/ --timeout 3 'foo bar'"
+        );
+
+        // An empty query.
+        assert_eq!(GrepOptions::default().cli_args(), ["mamegrep"]);
+        Ok(())
+    }
+
+    #[test]
+    fn pathspec_file_options() -> noargs::Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("mamegrep-cli-pathspecs-{}", std::process::id()));
+        std::fs::write(&path, "a\0b\0").or_fail()?;
+        let raw = [
+            "mamegrep".to_owned(),
+            "--pathspec-from-file".to_owned(),
+            path.display().to_string(),
+            "--pathspec-file-nul".to_owned(),
+        ];
+        let mut args = noargs::RawArgs::new(raw.clone().into_iter());
+        let mut options = GrepOptions::default();
+        take_query_options(&mut args, &mut options)?;
+        std::fs::remove_file(&path).or_fail()?;
+        assert!(args.finish()?.is_none());
+
+        let file = options.pathspec_file.as_ref().or_fail()?;
+        assert_eq!(file.pathspecs, ["a", "b"]);
+        assert!(file.nul);
+        assert_eq!(options.cli_args()[1..], raw[1..]);
+        Ok(())
+    }
+
+    #[test]
+    fn repo_dir_option() -> noargs::Result<()> {
+        let raw = ["mamegrep", "-C", "../other repo", "foo"].map(str::to_owned);
+        let mut args = noargs::RawArgs::new(raw.clone().into_iter());
+        let mut options = GrepOptions::default();
+        take_query_options(&mut args, &mut options)?;
+        options.pattern.text = take_pattern(&mut args)?;
+        assert!(args.finish()?.is_none());
+
+        assert_eq!(options.repo_dir, Some(PathBuf::from("../other repo")));
+        assert_eq!(options.cli_args(), raw);
+        assert_eq!(
+            options.cli_command_line(),
+            "mamegrep -C '../other repo' foo"
+        );
+
+        assert!(check_repo_dir(Path::new(env!("CARGO_MANIFEST_DIR"))).is_ok());
+        assert_eq!(
+            check_repo_dir(Path::new("no/such/dir")),
+            Err("`no/such/dir` is not in a Git repository".to_owned())
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,152 @@
+use std::{ops::Range, path::Path};
+
+use crate::git::Line;
+
+// The preview is for a glance before running a real replacement, so long ones are truncated.
+pub const MAX_PREVIEW_LINES: usize = 1000;
+
+// Formats the hit lines as a diff of `path` headings followed by `-N: before` and `+N: after` lines.
+// Lines without matches (or unchanged by the replacement) are omitted.
+pub fn preview<'a, I>(files: I, replacement: &str) -> String
+where
+    I: IntoIterator<Item = (&'a Path, Vec<(&'a Line, Vec<Range<usize>>)>)>,
+{
+    let mut text = String::new();
+    let mut written = 0;
+    let mut omitted = 0;
+    for (path, lines) in files {
+        let mut heading = Some(format!("{}\n", path.display()));
+        for (line, ranges) in lines {
+            let replaced = replace_ranges(&line.text, &ranges, replacement);
+            if replaced == line.text {
+                continue;
+            }
+            if written == MAX_PREVIEW_LINES {
+                omitted += 1;
+                continue;
+            }
+            if let Some(heading) = heading.take() {
+                text.push_str(&heading);
+            }
+            text.push_str(&format!("-{}: {}\n", line.number, line.text));
+            text.push_str(&format!("+{}: {replaced}\n", line.number));
+            written += 1;
+        }
+    }
+
+    if written == 0 {
+        text.push_str("(no lines are changed)\n");
+    } else if omitted > 0 {
+        text.push_str(&format!("(truncated: {omitted} more lines)\n"));
+    }
+    text
+}
+
+// Replaces the (sorted and non-overlapping) ranges with `replacement`,
+// where `$0` (or `${0}`) is expanded to the matched text and `$$` to `$`.
+//
+// The matches come from `git grep -o` rather than a regex engine, so other capture groups are not available
+// (and are left as is).
+pub fn replace_ranges(text: &str, ranges: &[Range<usize>], replacement: &str) -> String {
+    let mut replaced = String::with_capacity(text.len());
+    let mut end = 0;
+    for range in ranges {
+        let Some(matched) = text.get(range.clone()) else {
+            continue;
+        };
+        if range.start < end {
+            continue;
+        }
+        replaced.push_str(&text[end..range.start]);
+        replaced.push_str(&expand(replacement, matched));
+        end = range.end;
+    }
+    replaced.push_str(&text[end..]);
+    replaced
+}
+
+fn expand(replacement: &str, matched: &str) -> String {
+    let mut expanded = String::new();
+    let mut rest = replacement;
+    while let Some(i) = rest.find('$') {
+        expanded.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if let Some(r) = rest.strip_prefix("$$") {
+            expanded.push('$');
+            rest = r;
+        } else if let Some(r) = rest
+            .strip_prefix("${0}")
+            .or_else(|| rest.strip_prefix("$0"))
+        {
+            expanded.push_str(matched);
+            rest = r;
+        } else {
+            expanded.push('$');
+            rest = &rest[1..];
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use super::*;
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn replacement() {
+        let text = "let foo = foo_bar(foo);";
+        let ranges = [4..7, 10..13, 18..21];
+        assert_eq!(
+            replace_ranges(text, &ranges, "baz"),
+            "let baz = baz_bar(baz);"
+        );
+        assert_eq!(
+            replace_ranges(text, &ranges, "[$0]"),
+            "let [foo] = [foo]_bar([foo]);"
+        );
+        assert_eq!(
+            replace_ranges(text, &ranges[..1], "${0}s $$0 $1"),
+            "let foos $0 $1 = foo_bar(foo);"
+        );
+        assert_eq!(replace_ranges(text, &[], "baz"), text);
+
+        // Out-of-range or overlapping ranges (e.g., stale highlights) are skipped.
+        assert_eq!(
+            replace_ranges(text, &[4..7, 5..8, 100..103], "x"),
+            "let x = foo_bar(foo);"
+        );
+
+        // Multibyte text.
+        assert_eq!(replace_ranges("日本語", &[3..6], "-$0-"), "日-本-語");
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn diff_preview() {
+        let line = |number: usize, text: &str| Line {
+            number: NonZeroUsize::new(number).expect("non-zero"),
+            text: text.to_owned(),
+            hit: true,
+        };
+        let a = [line(1, "foo()"), line(3, "bar")];
+        let b = [line(2, "x = foo")];
+        let files = || {
+            vec![
+                (
+                    Path::new("a.rs"),
+                    vec![(&a[0], vec![0..3]), (&a[1], Vec::new())],
+                ),
+                (Path::new("b.rs"), vec![(&b[0], vec![4..7])]),
+            ]
+        };
+        assert_eq!(
+            preview(files(), "baz"),
+            "a.rs\n-1: foo()\n+1: baz()\nb.rs\n-2: x = foo\n+2: x = baz\n"
+        );
+        assert_eq!(preview(files(), "$0"), "(no lines are changed)\n");
+    }
+}
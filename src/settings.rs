@@ -0,0 +1,1099 @@
+use std::{borrow::Cow, collections::BTreeMap, time::Duration};
+
+use tuinix::TerminalColor;
+
+use crate::git::{ContextLines, RegexpEngine};
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub scrolloff: usize,
+    pub header_format: Option<HeaderFormat>,
+    pub show_whitespace: bool,
+    pub auto_expand_max_hits: Option<usize>,
+    pub code_reference_format: CodeReferenceFormat,
+    pub remote_url_template: Option<RemoteUrlTemplate>,
+    pub browse_url_template: BrowseUrlTemplate,
+    pub max_rendered_files: usize,
+    pub file_breaks: bool,
+    pub arg_quoting: ArgQuoting,
+    pub confirm_quit_while_editing: bool,
+    pub term_colors: Vec<TerminalColor>,
+    pub fold_blank_context: bool,
+    pub merge_context: bool,
+    pub sticky_file_heading: bool,
+    pub collapsed_indicator: String,
+    pub show_file_indices: bool,
+    pub max_context_lines: ContextLines,
+    pub smart_multiline_indent: bool,
+    pub test_exclude_globs: Vec<String>,
+    pub show_flag_status: bool,
+    pub flag_status_letters: BTreeMap<String, String>,
+    pub hyperlink_format: Option<HyperlinkFormat>,
+    pub sort_order: SortOrder,
+    pub auto_run_when_ready: bool,
+    pub cursor_markers: CursorMarkers,
+    pub grep_command: Vec<String>,
+    pub control_chars: ControlChars,
+    pub basename_only: bool,
+    pub ascii_only_ignore_case: bool,
+    pub auto_refresh_delay: Option<Duration>,
+    pub summary_format: SummaryFormat,
+    pub refine_mode: RefineMode,
+    pub trim_whole_line_search: bool,
+    pub show_breadcrumb: bool,
+    pub line_number_width: Option<usize>,
+    pub pattern_templates: BTreeMap<String, PatternTemplate>,
+    pub legend_toggle_indicators: bool,
+    pub file_activation: FileActivation,
+    pub dim_visited: bool,
+    pub clipboard_confirm_bytes: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            scrolloff: 0,
+            header_format: None,
+            show_whitespace: false,
+            auto_expand_max_hits: None,
+            code_reference_format: CodeReferenceFormat::default(),
+            remote_url_template: None,
+            browse_url_template: BrowseUrlTemplate::default(),
+            max_rendered_files: 1000,
+            file_breaks: false,
+            arg_quoting: ArgQuoting::default(),
+            confirm_quit_while_editing: false,
+            term_colors: vec![TerminalColor::YELLOW, TerminalColor::CYAN],
+            fold_blank_context: false,
+            merge_context: true,
+            sticky_file_heading: false,
+            collapsed_indicator: "…".to_owned(),
+            show_file_indices: true,
+            max_context_lines: ContextLines(20),
+            smart_multiline_indent: false,
+            test_exclude_globs: ["*_test.*", "tests/*", "*/tests/*"]
+                .map(str::to_owned)
+                .to_vec(),
+            show_flag_status: true,
+            flag_status_letters: [
+                ("ignore-case", "i"),
+                ("untracked", "u"),
+                ("no-index", "I"),
+                ("no-recursive", "R"),
+                ("textconv", "V"),
+                ("whole-word", "w"),
+                ("fixed-strings", "F"),
+                ("extended-regexp", "E"),
+                ("perl-regexp", "P"),
+            ]
+            .into_iter()
+            .map(|(name, letter)| (name.to_owned(), letter.to_owned()))
+            .collect(),
+            hyperlink_format: None,
+            sort_order: SortOrder::default(),
+            auto_run_when_ready: false,
+            cursor_markers: CursorMarkers::default(),
+            grep_command: vec!["grep".to_owned()],
+            control_chars: ControlChars::default(),
+            basename_only: false,
+            ascii_only_ignore_case: false,
+            auto_refresh_delay: None,
+            summary_format: SummaryFormat::default(),
+            refine_mode: RefineMode::default(),
+            trim_whole_line_search: true,
+            show_breadcrumb: false,
+            line_number_width: None,
+            pattern_templates: BTreeMap::new(),
+            legend_toggle_indicators: false,
+            file_activation: FileActivation::default(),
+            dim_visited: false,
+            clipboard_confirm_bytes: 100_000,
+        }
+    }
+}
+
+impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for Settings {
+    type Error = nojson::JsonParseError;
+
+    fn try_from(value: nojson::RawJsonValue<'text, 'raw>) -> Result<Self, Self::Error> {
+        let default = Self::default();
+        Ok(Self {
+            scrolloff: value
+                .to_member("scrolloff")?
+                .map(usize::try_from)?
+                .unwrap_or(default.scrolloff),
+            header_format: value
+                .to_member("header-format")?
+                .map(mame::json::parse_from_flattened_string)?
+                .or(default.header_format),
+            show_whitespace: value
+                .to_member("show-whitespace")?
+                .map(bool::try_from)?
+                .unwrap_or(default.show_whitespace),
+            auto_expand_max_hits: value
+                .to_member("auto-expand-max-hits")?
+                .map(usize::try_from)?
+                .or(default.auto_expand_max_hits),
+            code_reference_format: value
+                .to_member("code-reference-format")?
+                .map(mame::json::parse_from_flattened_string)?
+                .unwrap_or(default.code_reference_format),
+            remote_url_template: value
+                .to_member("remote-url-template")?
+                .map(mame::json::parse_from_flattened_string)?
+                .or(default.remote_url_template),
+            browse_url_template: value
+                .to_member("browse-url-template")?
+                .map(mame::json::parse_from_flattened_string)?
+                .unwrap_or(default.browse_url_template),
+            max_rendered_files: value
+                .to_member("max-rendered-files")?
+                .map(usize::try_from)?
+                .unwrap_or(default.max_rendered_files),
+            file_breaks: value
+                .to_member("file-breaks")?
+                .map(bool::try_from)?
+                .unwrap_or(default.file_breaks),
+            arg_quoting: value
+                .to_member("arg-quoting")?
+                .map(ArgQuoting::try_from)?
+                .unwrap_or(default.arg_quoting),
+            confirm_quit_while_editing: value
+                .to_member("confirm-quit-while-editing")?
+                .map(bool::try_from)?
+                .unwrap_or(default.confirm_quit_while_editing),
+            term_colors: value
+                .to_member("term-colors")?
+                .map(|v| v.to_array()?.map(parse_color).collect())?
+                .unwrap_or(default.term_colors),
+            fold_blank_context: value
+                .to_member("fold-blank-context")?
+                .map(bool::try_from)?
+                .unwrap_or(default.fold_blank_context),
+            merge_context: value
+                .to_member("merge-context")?
+                .map(bool::try_from)?
+                .unwrap_or(default.merge_context),
+            sticky_file_heading: value
+                .to_member("sticky-file-heading")?
+                .map(bool::try_from)?
+                .unwrap_or(default.sticky_file_heading),
+            collapsed_indicator: value
+                .to_member("collapsed-indicator")?
+                .map(parse_collapsed_indicator)?
+                .unwrap_or(default.collapsed_indicator),
+            show_file_indices: value
+                .to_member("show-file-indices")?
+                .map(bool::try_from)?
+                .unwrap_or(default.show_file_indices),
+            max_context_lines: value
+                .to_member("max-context-lines")?
+                .map(|v| usize::try_from(v).map(|n| ContextLines(n.min(ContextLines::MAX.0))))?
+                .unwrap_or(default.max_context_lines),
+            smart_multiline_indent: value
+                .to_member("smart-multiline-indent")?
+                .map(bool::try_from)?
+                .unwrap_or(default.smart_multiline_indent),
+            test_exclude_globs: value
+                .to_member("test-exclude-globs")?
+                .map(Vec::try_from)?
+                .unwrap_or(default.test_exclude_globs),
+            show_flag_status: value
+                .to_member("show-flag-status")?
+                .map(bool::try_from)?
+                .unwrap_or(default.show_flag_status),
+            flag_status_letters: value
+                .to_member("flag-status-letters")?
+                .map(|v| parse_flag_status_letters(v, default.flag_status_letters.clone()))?
+                .unwrap_or(default.flag_status_letters),
+            hyperlink_format: value
+                .to_member("hyperlink-format")?
+                .map(mame::json::parse_from_flattened_string)?
+                .or(default.hyperlink_format),
+            sort_order: value
+                .to_member("sort-order")?
+                .map(SortOrder::try_from)?
+                .unwrap_or(default.sort_order),
+            auto_run_when_ready: value
+                .to_member("auto-run-when-ready")?
+                .map(bool::try_from)?
+                .unwrap_or(default.auto_run_when_ready),
+            cursor_markers: value
+                .to_member("cursor-markers")?
+                .map(CursorMarkers::try_from)?
+                .unwrap_or(default.cursor_markers),
+            grep_command: value
+                .to_member("grep-command")?
+                .map(|v| {
+                    let args = Vec::<String>::try_from(v)?;
+                    if args.is_empty() {
+                        return Err(v.invalid("expected at least one arg"));
+                    }
+                    Ok(args)
+                })?
+                .unwrap_or(default.grep_command),
+            control_chars: value
+                .to_member("control-chars")?
+                .map(ControlChars::try_from)?
+                .unwrap_or(default.control_chars),
+            basename_only: value
+                .to_member("basename-only")?
+                .map(bool::try_from)?
+                .unwrap_or(default.basename_only),
+            ascii_only_ignore_case: value
+                .to_member("ascii-only-ignore-case")?
+                .map(bool::try_from)?
+                .unwrap_or(default.ascii_only_ignore_case),
+            auto_refresh_delay: value
+                .to_member("auto-refresh-delay-ms")?
+                .map(|v| u64::try_from(v).map(Duration::from_millis))?
+                .or(default.auto_refresh_delay),
+            summary_format: value
+                .to_member("summary-format")?
+                .map(mame::json::parse_from_flattened_string)?
+                .unwrap_or(default.summary_format),
+            refine_mode: value
+                .to_member("refine-mode")?
+                .map(RefineMode::try_from)?
+                .unwrap_or(default.refine_mode),
+            trim_whole_line_search: value
+                .to_member("trim-whole-line-search")?
+                .map(bool::try_from)?
+                .unwrap_or(default.trim_whole_line_search),
+            show_breadcrumb: value
+                .to_member("show-breadcrumb")?
+                .map(bool::try_from)?
+                .unwrap_or(default.show_breadcrumb),
+            line_number_width: value
+                .to_member("line-number-width")?
+                .map(parse_line_number_width)?
+                .or(default.line_number_width),
+            pattern_templates: value
+                .to_member("pattern-templates")?
+                .map(|v| {
+                    v.to_object()?
+                        .map(|(name, template)| {
+                            Ok((
+                                name.to_unquoted_string_str()?.into_owned(),
+                                mame::json::parse_from_flattened_string(template)?,
+                            ))
+                        })
+                        .collect()
+                })?
+                .unwrap_or(default.pattern_templates),
+            legend_toggle_indicators: value
+                .to_member("legend-toggle-indicators")?
+                .map(bool::try_from)?
+                .unwrap_or(default.legend_toggle_indicators),
+            file_activation: value
+                .to_member("file-activation")?
+                .map(FileActivation::try_from)?
+                .unwrap_or(default.file_activation),
+            dim_visited: value
+                .to_member("dim-visited")?
+                .map(bool::try_from)?
+                .unwrap_or(default.dim_visited),
+            clipboard_confirm_bytes: value
+                .to_member("clipboard-confirm-bytes")?
+                .map(usize::try_from)?
+                .unwrap_or(default.clipboard_confirm_bytes),
+        })
+    }
+}
+
+const MAX_COLLAPSED_INDICATOR_COLS: usize = 8;
+
+// Drawn right after the file heading, so it is kept short and on the line.
+fn parse_collapsed_indicator(
+    value: nojson::RawJsonValue<'_, '_>,
+) -> Result<String, nojson::JsonParseError> {
+    let indicator = String::try_from(value)?;
+    let cols = mame::terminal::str_cols(&indicator);
+    if !(1..=MAX_COLLAPSED_INDICATOR_COLS).contains(&cols)
+        || indicator.chars().any(char::is_control)
+    {
+        return Err(value.invalid(format!(
+            "expected a string of 1 to {MAX_COLLAPSED_INDICATOR_COLS} columns without control chars"
+        )));
+    }
+    Ok(indicator)
+}
+
+const MAX_LINE_NUMBER_WIDTH: usize = 10;
+
+fn parse_line_number_width(
+    value: nojson::RawJsonValue<'_, '_>,
+) -> Result<usize, nojson::JsonParseError> {
+    let width = usize::try_from(value)?;
+    if !(1..=MAX_LINE_NUMBER_WIDTH).contains(&width) {
+        return Err(value.invalid(format!("expected 1 to {MAX_LINE_NUMBER_WIDTH}")));
+    }
+    Ok(width)
+}
+
+// Overrides the letters of the given flags, keeping the defaults for the others.
+fn parse_flag_status_letters(
+    value: nojson::RawJsonValue<'_, '_>,
+    mut letters: BTreeMap<String, String>,
+) -> Result<BTreeMap<String, String>, nojson::JsonParseError> {
+    for (name, letter) in value.to_object()? {
+        let letter = letter.to_unquoted_string_str()?.into_owned();
+        let Some(slot) = letters.get_mut(name.to_unquoted_string_str()?.as_ref()) else {
+            return Err(name.invalid("unknown flag name"));
+        };
+        *slot = letter;
+    }
+    Ok(letters)
+}
+
+// Accepts basic color names (e.g., "yellow") or "#RRGGBB".
+fn parse_color(
+    value: nojson::RawJsonValue<'_, '_>,
+) -> Result<TerminalColor, nojson::JsonParseError> {
+    let s = value.to_unquoted_string_str()?;
+    let color = match s.as_ref() {
+        "black" => TerminalColor::BLACK,
+        "red" => TerminalColor::RED,
+        "green" => TerminalColor::GREEN,
+        "yellow" => TerminalColor::YELLOW,
+        "blue" => TerminalColor::BLUE,
+        "magenta" => TerminalColor::MAGENTA,
+        "cyan" => TerminalColor::CYAN,
+        "white" => TerminalColor::WHITE,
+        s => {
+            let rgb = s
+                .strip_prefix('#')
+                .filter(|hex| hex.len() == 6)
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| value.invalid("expected a color name or \"#RRGGBB\""))?;
+            TerminalColor::new((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+        }
+    };
+    Ok(color)
+}
+
+// How control chars (e.g., tabs) in texts are rendered.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ControlChars {
+    // Rust escapes (e.g., `\t`).
+    #[default]
+    Escape,
+    // Caret notation (e.g., `^I`), falling back to the escapes for non-ASCII ones.
+    Caret,
+    // A single `�` per char.
+    Placeholder,
+}
+
+impl ControlChars {
+    pub fn apply(self, text: &str) -> Cow<'_, str> {
+        if !text.chars().any(|c| c.is_control()) {
+            return Cow::Borrowed(text);
+        }
+        let mut replaced = String::new();
+        for c in text.chars() {
+            match self {
+                _ if !c.is_control() => replaced.push(c),
+                Self::Caret if c.is_ascii() => {
+                    replaced.push('^');
+                    replaced.push((c as u8 ^ 0x40) as char);
+                }
+                Self::Placeholder => replaced.push('\u{FFFD}'),
+                Self::Escape | Self::Caret => replaced.extend(c.escape_default()),
+            }
+        }
+        Cow::Owned(replaced)
+    }
+}
+
+impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for ControlChars {
+    type Error = nojson::JsonParseError;
+
+    fn try_from(value: nojson::RawJsonValue<'text, 'raw>) -> Result<Self, Self::Error> {
+        match value.to_unquoted_string_str()?.as_ref() {
+            "escape" => Ok(Self::Escape),
+            "caret" => Ok(Self::Caret),
+            "placeholder" => Ok(Self::Placeholder),
+            _ => Err(value.invalid("expected \"escape\", \"caret\" or \"placeholder\"")),
+        }
+    }
+}
+
+// How `refine-with-word-under-cursor` uses the word.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RefineMode {
+    // Replaces the pattern with the word.
+    #[default]
+    Replace,
+    // Sets the word as the and-pattern.
+    And,
+}
+
+impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for RefineMode {
+    type Error = nojson::JsonParseError;
+
+    fn try_from(value: nojson::RawJsonValue<'text, 'raw>) -> Result<Self, Self::Error> {
+        match value.to_unquoted_string_str()?.as_ref() {
+            "replace" => Ok(Self::Replace),
+            "and" => Ok(Self::And),
+            _ => Err(value.invalid("expected \"replace\" or \"and\"")),
+        }
+    }
+}
+
+// What `activate-file` does to the focused file (at file level).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FileActivation {
+    // Expands or collapses the file.
+    #[default]
+    ToggleExpansion,
+    // Moves the cursor to the first hit line of the file.
+    EnterFirstLine,
+    // Opens the file at the first hit line with `$VISUAL` or `$EDITOR`.
+    OpenInEditor,
+}
+
+impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for FileActivation {
+    type Error = nojson::JsonParseError;
+
+    fn try_from(value: nojson::RawJsonValue<'text, 'raw>) -> Result<Self, Self::Error> {
+        match value.to_unquoted_string_str()?.as_ref() {
+            "toggle-expansion" => Ok(Self::ToggleExpansion),
+            "enter-first-line" => Ok(Self::EnterFirstLine),
+            "open-in-editor" => Ok(Self::OpenInEditor),
+            _ => Err(value.invalid(
+                "expected \"toggle-expansion\", \"enter-first-line\" or \"open-in-editor\"",
+            )),
+        }
+    }
+}
+
+// How the grep args are shown in the command editor.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ArgQuoting {
+    // Quotes all args except the focused one.
+    #[default]
+    Auto,
+    // Shows all args as executed by a shell.
+    Quoted,
+    // Shows all args as typed.
+    Unquoted,
+}
+
+impl ArgQuoting {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Auto => Self::Quoted,
+            Self::Quoted => Self::Unquoted,
+            Self::Unquoted => Self::Auto,
+        }
+    }
+}
+
+impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for ArgQuoting {
+    type Error = nojson::JsonParseError;
+
+    fn try_from(value: nojson::RawJsonValue<'text, 'raw>) -> Result<Self, Self::Error> {
+        match value.to_unquoted_string_str()?.as_ref() {
+            "auto" => Ok(Self::Auto),
+            "quoted" => Ok(Self::Quoted),
+            "unquoted" => Ok(Self::Unquoted),
+            _ => Err(value.invalid("expected one of \"auto\", \"quoted\" or \"unquoted\"")),
+        }
+    }
+}
+
+// Order of the files in the search result.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    #[default]
+    Path,
+    // Recently committed files first, followed by files without history (e.g., untracked ones).
+    Recency,
+}
+
+impl SortOrder {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Path => Self::Recency,
+            Self::Recency => Self::Path,
+        }
+    }
+}
+
+impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for SortOrder {
+    type Error = nojson::JsonParseError;
+
+    fn try_from(value: nojson::RawJsonValue<'text, 'raw>) -> Result<Self, Self::Error> {
+        match value.to_unquoted_string_str()?.as_ref() {
+            "path" => Ok(Self::Path),
+            "recency" => Ok(Self::Recency),
+            _ => Err(value.invalid("expected \"path\" or \"recency\"")),
+        }
+    }
+}
+
+// Markers of the focused file and line entries, padded to the widths of their entry prefixes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CursorMarkers {
+    file: String,
+    line: String,
+}
+
+impl CursorMarkers {
+    pub fn new(file: &str, line: &str) -> Self {
+        // Each marker is followed by a space, and line entries are indented under file entries.
+        let file_cols = mame::terminal::str_cols(file) + 1;
+        let line_cols = (mame::terminal::str_cols(line) + 1).max(file_cols + 2);
+        let pad = |marker: &str, cols: usize| {
+            format!(
+                "{marker}{}",
+                " ".repeat(cols - mame::terminal::str_cols(marker))
+            )
+        };
+        Self {
+            file: pad(file, file_cols),
+            line: pad(line, line_cols),
+        }
+    }
+
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    pub fn file_cols(&self) -> usize {
+        mame::terminal::str_cols(&self.file)
+    }
+
+    pub fn line_cols(&self) -> usize {
+        mame::terminal::str_cols(&self.line)
+    }
+}
+
+impl Default for CursorMarkers {
+    fn default() -> Self {
+        Self::new("->", "--->")
+    }
+}
+
+impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for CursorMarkers {
+    type Error = nojson::JsonParseError;
+
+    fn try_from(value: nojson::RawJsonValue<'text, 'raw>) -> Result<Self, Self::Error> {
+        let file: Option<String> = value.to_member("file")?.map(String::try_from)?;
+        let line: Option<String> = value.to_member("line")?.map(String::try_from)?;
+        Ok(Self::new(
+            file.as_deref().unwrap_or("->"),
+            line.as_deref().unwrap_or("--->"),
+        ))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TemplateSegment {
+    Text(String),
+    Placeholder(&'static str),
+}
+
+// A string with `{name}` placeholders, where the available names are fixed at parse time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Template {
+    segments: Vec<TemplateSegment>,
+}
+
+impl Template {
+    fn parse(s: &str, names: &[&'static str]) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                segments.push(TemplateSegment::Text(rest[..start].to_owned()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .map(|i| start + i)
+                .ok_or_else(|| format!("unclosed placeholder: {:?}", &rest[start..]))?;
+            let name = &rest[start + 1..end];
+            let Some(name) = names.iter().find(|n| **n == name) else {
+                let expected = names
+                    .iter()
+                    .map(|n| format!("{{{n}}}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                return Err(format!(
+                    "unknown placeholder: {{{name}}} (expected one of {expected})"
+                ));
+            };
+            segments.push(TemplateSegment::Placeholder(name));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(TemplateSegment::Text(rest.to_owned()));
+        }
+        Ok(Self { segments })
+    }
+
+    fn render<F>(&self, value: F) -> String
+    where
+        F: Fn(&str) -> String,
+    {
+        let mut s = String::new();
+        for segment in &self.segments {
+            match segment {
+                TemplateSegment::Text(text) => s.push_str(text),
+                TemplateSegment::Placeholder(name) => s.push_str(&value(name)),
+            }
+        }
+        s
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderFormat(Template);
+
+impl HeaderFormat {
+    pub fn format(&self, hits: usize, lines: usize, files: usize, duration: Duration) -> String {
+        self.0.render(|name| match name {
+            "hits" => hits.to_string(),
+            "lines" => lines.to_string(),
+            "files" => files.to_string(),
+            _ => format!("{duration:.2?}"),
+        })
+    }
+}
+
+impl std::str::FromStr for HeaderFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Template::parse(s, &["hits", "lines", "files", "duration"]).map(Self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SummaryFormat(Template);
+
+impl SummaryFormat {
+    pub fn format(&self, hits: usize, lines: usize, files: usize, pattern: &str) -> String {
+        self.0.render(|name| match name {
+            "hits" => hits.to_string(),
+            "lines" => lines.to_string(),
+            "files" => files.to_string(),
+            _ => pattern.to_owned(),
+        })
+    }
+}
+
+impl Default for SummaryFormat {
+    fn default() -> Self {
+        "{hits} hits across {files} files for `{pattern}`"
+            .parse()
+            .expect("infallible")
+    }
+}
+
+impl std::str::FromStr for SummaryFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Template::parse(s, &["hits", "lines", "files", "pattern"]).map(Self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeReferenceFormat(Template);
+
+impl CodeReferenceFormat {
+    pub fn format(&self, path: &str, line: usize) -> String {
+        self.0.render(|name| match name {
+            "path" => path.to_owned(),
+            _ => line.to_string(),
+        })
+    }
+}
+
+impl Default for CodeReferenceFormat {
+    fn default() -> Self {
+        "{path}#L{line}".parse().expect("infallible")
+    }
+}
+
+impl std::str::FromStr for CodeReferenceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Template::parse(s, &["path", "line"]).map(Self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteUrlTemplate(Template);
+
+impl RemoteUrlTemplate {
+    pub fn format(&self, sha: &str, path: &str, line: usize) -> String {
+        self.0.render(|name| match name {
+            "sha" => sha.to_owned(),
+            "path" => path.to_owned(),
+            _ => line.to_string(),
+        })
+    }
+}
+
+impl std::str::FromStr for RemoteUrlTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Template::parse(s, &["sha", "path", "line"]).map(Self)
+    }
+}
+
+// `{base}` is the web URL of the repository derived from the remote URL (e.g., `https://github.com/sile/mamegrep`),
+// and `{ref}` is the current branch (or the commit SHA on a detached HEAD or for a searched revision).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrowseUrlTemplate(Template);
+
+impl BrowseUrlTemplate {
+    pub fn format(&self, base: &str, reference: &str, path: &str, line: usize) -> String {
+        self.0.render(|name| match name {
+            "base" => base.to_owned(),
+            "ref" => reference.to_owned(),
+            "path" => path.to_owned(),
+            _ => line.to_string(),
+        })
+    }
+}
+
+impl Default for BrowseUrlTemplate {
+    fn default() -> Self {
+        "{base}/blob/{ref}/{path}#L{line}"
+            .parse()
+            .expect("infallible")
+    }
+}
+
+impl std::str::FromStr for BrowseUrlTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Template::parse(s, &["base", "ref", "path", "line"]).map(Self)
+    }
+}
+
+// A pattern with `{name}` placeholders, each of which is filled by a prompt of `apply-template`.
+//
+// Unlike `Template`, the names are arbitrary identifiers, and braces around anything else
+// (e.g., the `{2,}` interval) are kept as part of the pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternTemplate {
+    segments: Vec<PatternSegment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PatternSegment {
+    Text(String),
+    Placeholder(String),
+}
+
+impl PatternTemplate {
+    // Names in order of first appearance (a name used more than once is prompted only once).
+    pub fn placeholders(&self) -> Vec<&str> {
+        let mut names = Vec::<&str>::new();
+        for segment in &self.segments {
+            if let PatternSegment::Placeholder(name) = segment
+                && !names.contains(&name.as_str())
+            {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    // The values are escaped so that they match literally with `engine`.
+    pub fn fill(&self, values: &BTreeMap<String, String>, engine: RegexpEngine) -> String {
+        let mut pattern = String::new();
+        for segment in &self.segments {
+            match segment {
+                PatternSegment::Text(text) => pattern.push_str(text),
+                PatternSegment::Placeholder(name) => {
+                    let value = values.get(name).map(String::as_str).unwrap_or_default();
+                    pattern.push_str(&crate::git::escape_regex(value, engine));
+                }
+            }
+        }
+        pattern
+    }
+}
+
+impl std::str::FromStr for PatternTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("empty pattern template".to_owned());
+        }
+
+        let is_name = |name: &str| {
+            name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        };
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            text.push_str(&rest[..start]);
+            rest = &rest[start..];
+            match rest[1..].find('}').map(|end| &rest[1..1 + end]) {
+                Some(name) if is_name(name) => {
+                    if !text.is_empty() {
+                        segments.push(PatternSegment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(PatternSegment::Placeholder(name.to_owned()));
+                    rest = &rest[name.len() + 2..];
+                }
+                _ => {
+                    text.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            segments.push(PatternSegment::Text(text));
+        }
+        Ok(Self { segments })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperlinkFormat(Template);
+
+impl HyperlinkFormat {
+    pub fn format(&self, path: &str) -> String {
+        self.0.render(|_| path.to_owned())
+    }
+}
+
+impl std::str::FromStr for HyperlinkFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Template::parse(s, &["path"]).map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use orfail::OrFail;
+
+    use super::*;
+
+    #[test]
+    fn control_chars() {
+        let text = "a\tb\x7f\u{85}";
+        assert_eq!(ControlChars::Escape.apply(text), "a\\tb\\u{7f}\\u{85}");
+        assert_eq!(ControlChars::Caret.apply(text), "a^Ib^?\\u{85}");
+        assert_eq!(
+            ControlChars::Placeholder.apply(text),
+            "a\u{FFFD}b\u{FFFD}\u{FFFD}"
+        );
+        assert!(matches!(
+            ControlChars::Caret.apply("ab"),
+            Cow::Borrowed("ab")
+        ));
+
+        // The widths are those of the rendered texts (no zero-width control chars are left).
+        for policy in [
+            ControlChars::Escape,
+            ControlChars::Caret,
+            ControlChars::Placeholder,
+        ] {
+            let applied = policy.apply(text);
+            assert_eq!(
+                mame::terminal::str_cols(&applied),
+                applied.chars().count(),
+                "{policy:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn cursor_markers() {
+        let markers = CursorMarkers::default();
+        assert_eq!((markers.file(), markers.line()), ("-> ", "---> "));
+
+        // A short line marker is padded to stay indented under file entries.
+        let markers = CursorMarkers::new("▶▶", "▶");
+        assert_eq!((markers.file(), markers.line()), ("▶▶ ", "▶    "));
+        assert_eq!((markers.file_cols(), markers.line_cols()), (3, 5));
+
+        let markers = CursorMarkers::new("", "=>>>>>");
+        assert_eq!((markers.file(), markers.line()), (" ", "=>>>>> "));
+
+        // Wide characters are counted by their columns.
+        let markers = CursorMarkers::new("→", "語");
+        assert_eq!((markers.file(), markers.line()), ("→ ", "語  "));
+    }
+
+    #[test]
+    fn summary_format() -> orfail::Result<()> {
+        let format = "{pattern}: {hits} hits, {lines} lines, {files} files"
+            .parse::<SummaryFormat>()
+            .map_err(orfail::Failure::new)?;
+        assert_eq!(
+            format.format(3, 2, 1, "foo"),
+            "foo: 3 hits, 2 lines, 1 files"
+        );
+        assert!("{duration}".parse::<SummaryFormat>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn header_format() -> orfail::Result<()> {
+        let format = "{hits} hits in {files} files ({duration})"
+            .parse::<HeaderFormat>()
+            .map_err(orfail::Failure::new)?;
+        assert_eq!(
+            format.format(10, 5, 2, Duration::from_millis(3)),
+            "10 hits in 2 files (3.00ms)"
+        );
+
+        let format = "no placeholders"
+            .parse::<HeaderFormat>()
+            .map_err(orfail::Failure::new)?;
+        assert_eq!(format.format(1, 1, 1, Duration::ZERO), "no placeholders");
+
+        assert!("{hits} {foo}".parse::<HeaderFormat>().is_err());
+        assert!("{hits".parse::<HeaderFormat>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn code_reference_format() -> orfail::Result<()> {
+        let format = CodeReferenceFormat::default();
+        assert_eq!(format.format("src/main.rs", 10), "src/main.rs#L10");
+
+        let format = "{path}:L{line}"
+            .parse::<CodeReferenceFormat>()
+            .map_err(orfail::Failure::new)?;
+        assert_eq!(format.format("src/main.rs", 10), "src/main.rs:L10");
+        assert!("{path}#L{sha}".parse::<CodeReferenceFormat>().is_err());
+
+        let template = "https://github.com/sile/mamegrep/blob/{sha}/{path}#L{line}"
+            .parse::<RemoteUrlTemplate>()
+            .map_err(orfail::Failure::new)?;
+        assert_eq!(
+            template.format("abc123", "src/main.rs", 10),
+            "https://github.com/sile/mamegrep/blob/abc123/src/main.rs#L10"
+        );
+
+        let template = BrowseUrlTemplate::default();
+        assert_eq!(
+            template.format(
+                "https://github.com/sile/mamegrep",
+                "main",
+                "src/main.rs",
+                10
+            ),
+            "https://github.com/sile/mamegrep/blob/main/src/main.rs#L10"
+        );
+        let template = "{base}/-/blob/{ref}/{path}#L{line}"
+            .parse::<BrowseUrlTemplate>()
+            .map_err(orfail::Failure::new)?;
+        assert_eq!(
+            template.format("https://gitlab.example.com/a/b", "abc123", "x.rs", 3),
+            "https://gitlab.example.com/a/b/-/blob/abc123/x.rs#L3"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_placeholder_is_rejected_at_load() -> orfail::Result<()> {
+        let json = nojson::RawJson::parse(r#"{"header-format": "{hits} {bar}"}"#).or_fail()?;
+        let error = Settings::try_from(json.value()).err().or_fail()?;
+        assert!(error.to_string().contains("unknown placeholder: {bar}"));
+        Ok(())
+    }
+
+    #[test]
+    fn collapsed_indicator() -> orfail::Result<()> {
+        let parse = |text: &str| {
+            let json = nojson::RawJson::parse(text).expect("json");
+            Settings::try_from(json.value()).map(|s| s.collapsed_indicator)
+        };
+        assert_eq!(parse("{}").or_fail()?, "…");
+        assert_eq!(
+            parse(r#"{"collapsed-indicator": " [+]"}"#).or_fail()?,
+            " [+]"
+        );
+
+        for invalid in [r#""""#, r#""\t""#, r#""123456789""#] {
+            let text = format!(r#"{{"collapsed-indicator": {invalid}}}"#);
+            assert!(parse(&text).is_err(), "{invalid}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn line_number_width() -> orfail::Result<()> {
+        let parse = |text: &str| {
+            let json = nojson::RawJson::parse(text).expect("json");
+            Settings::try_from(json.value()).map(|s| s.line_number_width)
+        };
+        assert_eq!(parse("{}").or_fail()?, None);
+        assert_eq!(parse(r#"{"line-number-width": 4}"#).or_fail()?, Some(4));
+        assert!(parse(r#"{"line-number-width": 0}"#).is_err());
+        assert!(parse(r#"{"line-number-width": 11}"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn pattern_template() -> orfail::Result<()> {
+        let parse = |s: &str| s.parse::<PatternTemplate>().map_err(orfail::Failure::new);
+        let values = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<BTreeMap<_, _>>()
+        };
+
+        let template = parse(r"fn {name}\(")?;
+        assert_eq!(template.placeholders(), ["name"]);
+        assert_eq!(
+            template.fill(&values(&[("name", "new")]), RegexpEngine::Basic),
+            r"fn new\("
+        );
+
+        // Values are escaped for the engine.
+        let template = parse("{recv}\\.{method}(")?;
+        assert_eq!(template.placeholders(), ["recv", "method"]);
+        let v = values(&[("recv", "a.b"), ("method", "c+")]);
+        assert_eq!(template.fill(&v, RegexpEngine::Basic), r"a\.b\.c+(");
+        assert_eq!(template.fill(&v, RegexpEngine::Extended), r"a\.b\.c\+(");
+        assert_eq!(template.fill(&v, RegexpEngine::FixedStrings), r"a.b\.c+(");
+
+        // Non-identifier braces are literal, and repeated names are prompted once.
+        let template = parse("{x}[0-9]{2,}{x}{}{")?;
+        assert_eq!(template.placeholders(), ["x"]);
+        assert_eq!(
+            template.fill(&values(&[("x", "_")]), RegexpEngine::Extended),
+            "_[0-9]{2,}_{}{"
+        );
+
+        // No placeholders.
+        assert!(parse("TODO")?.placeholders().is_empty());
+        assert!(parse("").is_err());
+
+        let json =
+            nojson::RawJson::parse(r#"{"pattern-templates": {"call": "{f}\\("}}"#).or_fail()?;
+        let settings = Settings::try_from(json.value()).or_fail()?;
+        assert_eq!(settings.pattern_templates["call"], parse(r"{f}\(")?);
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    os::fd::{AsRawFd, OwnedFd, RawFd},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+// Watches directories (non-recursively) with inotify for the `auto-refresh-delay-ms` setting.
+// Directories created after the start are not watched.
+#[derive(Debug)]
+pub struct FileWatcher {
+    fd: OwnedFd,
+}
+
+impl FileWatcher {
+    // inotify is Linux-only, so the setting has no effect elsewhere (`None` is returned).
+    #[cfg(not(target_os = "linux"))]
+    pub fn new<I, P>(_dirs: I) -> orfail::Result<Option<Self>>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        Ok(None)
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn new<I, P>(dirs: I) -> orfail::Result<Option<Self>>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        use std::{ffi::CString, os::fd::FromRawFd, os::unix::ffi::OsStrExt};
+
+        use orfail::OrFail;
+
+        // SAFETY: `inotify_init1()` takes no pointers.
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd == -1 {
+            return Err(std::io::Error::last_os_error()).or_fail();
+        }
+        // SAFETY: `fd` is a newly created file descriptor owned by no one else.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // Modifications are only reported once the file is closed, so that a save is a single change.
+        let mask = libc::IN_CLOSE_WRITE
+            | libc::IN_CREATE
+            | libc::IN_DELETE
+            | libc::IN_MOVED_FROM
+            | libc::IN_MOVED_TO;
+        for dir in dirs {
+            let path = CString::new(dir.as_ref().as_os_str().as_bytes()).or_fail()?;
+            // SAFETY: `path` is a valid NUL-terminated string.
+            if unsafe { libc::inotify_add_watch(fd.as_raw_fd(), path.as_ptr(), mask) } != -1 {
+                continue;
+            }
+            let e = std::io::Error::last_os_error();
+            // Directories removed in the meantime are just not watched.
+            if e.kind() == std::io::ErrorKind::NotFound {
+                continue;
+            }
+            let hint = if e.raw_os_error() == Some(libc::ENOSPC) {
+                " (see `fs.inotify.max_user_watches`)"
+            } else {
+                ""
+            };
+            return Err(e).or_fail_with(|e| {
+                format!("Failed to watch `{}`: {e}{hint}", dir.as_ref().display())
+            });
+        }
+        Ok(Some(Self { fd }))
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+
+    // Discards the pending events, as only the fact that something has changed matters.
+    pub fn drain(&self) {
+        let mut buf = [0u8; 4096];
+        loop {
+            // SAFETY: `buf` is valid for writes of `buf.len()` bytes.
+            let n = unsafe { libc::read(self.fd(), buf.as_mut_ptr().cast(), buf.len()) };
+            if n <= 0 {
+                break;
+            }
+        }
+    }
+}
+
+// Delays a refresh until no change has been notified for `delay` (e.g., while a branch is being checked out).
+#[derive(Debug)]
+pub struct Debounce {
+    delay: Duration,
+    last_change: Option<Instant>,
+}
+
+impl Debounce {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            last_change: None,
+        }
+    }
+
+    pub fn notify(&mut self, now: Instant) {
+        self.last_change = Some(now);
+    }
+
+    // Returns the time to wait for the changes to settle (`None` if there are no pending changes).
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.last_change
+            .map(|t| (t + self.delay).saturating_duration_since(now))
+    }
+
+    // Returns `true` (only once per batch of changes) if the changes have settled.
+    pub fn take_due(&mut self, now: Instant) -> bool {
+        if self.remaining(now) == Some(Duration::ZERO) {
+            self.last_change = None;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debounce() {
+        let delay = Duration::from_millis(100);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut debounce = Debounce::new(delay);
+        assert_eq!(debounce.remaining(at(0)), None);
+        assert!(!debounce.take_due(at(1000)));
+
+        // Each change restarts the delay.
+        debounce.notify(at(0));
+        debounce.notify(at(50));
+        assert_eq!(debounce.remaining(at(100)), Some(Duration::from_millis(50)));
+        assert!(!debounce.take_due(at(100)));
+        assert!(debounce.take_due(at(150)));
+
+        // Fired only once.
+        assert!(!debounce.take_due(at(200)));
+        assert_eq!(debounce.remaining(at(200)), None);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn file_watcher() -> orfail::Result<()> {
+        use orfail::OrFail;
+
+        let dir = std::env::temp_dir().join(format!("mamegrep-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).or_fail()?;
+        let watcher = FileWatcher::new([&dir]).or_fail()?.or_fail()?;
+        std::fs::write(dir.join("a.txt"), "foo").or_fail()?;
+
+        let mut buf = [0u8; 4096];
+        // SAFETY: `buf` is valid for writes of `buf.len()` bytes.
+        let n = unsafe { libc::read(watcher.fd(), buf.as_mut_ptr().cast(), buf.len()) };
+        std::fs::remove_dir_all(&dir).or_fail()?;
+        assert!(n > 0);
+
+        // Nothing is left after the events are drained.
+        watcher.drain();
+        // SAFETY: `buf` is valid for writes of `buf.len()` bytes.
+        let n = unsafe { libc::read(watcher.fd(), buf.as_mut_ptr().cast(), buf.len()) };
+        assert_eq!(n, -1);
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn file_watcher_errors() -> orfail::Result<()> {
+        use orfail::OrFail;
+
+        // Missing directories are skipped, but the other failures are reported.
+        FileWatcher::new(["no/such/dir"]).or_fail()?.or_fail()?;
+        let e = FileWatcher::new(["Cargo.toml/dir"]).err().or_fail()?;
+        assert!(e.message.starts_with("Failed to watch `Cargo.toml/dir`"));
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 use orfail::OrFail;
 use tuinix::{TerminalPosition, TerminalStyle};
 
@@ -5,7 +7,8 @@ use crate::{
     action::Action,
     app::{AppState, Focus},
     canvas::{Canvas, Token},
-    git::GrepArg,
+    git::{GrepArg, GrepArgKind},
+    settings::ArgQuoting,
 };
 
 #[derive(Debug, Default)]
@@ -18,6 +21,8 @@ pub struct CommandEditorWidget {
 impl CommandEditorWidget {
     const ROW_OFFSET: usize = 1;
     const COL_OFFSET: usize = "$ git".len();
+    // Aligns `--and` lines with the pattern after ` -e `.
+    const INDENT_COLS: usize = " -e".len();
 
     pub fn set_available_cols(&mut self, cols: usize) {
         self.available_cols = cols;
@@ -31,6 +36,14 @@ impl CommandEditorWidget {
         self.index = arg.len();
     }
 
+    // Whether the focused grep arg has been changed since the editing started.
+    pub fn has_unsaved_edit(&self, state: &AppState) -> bool {
+        state.focus != Focus::Prompt
+            && state
+                .focused_arg()
+                .is_some_and(|arg| arg.text != self.original_text)
+    }
+
     pub fn handle_action(&mut self, state: &mut AppState, action: Action) -> orfail::Result<()> {
         match action {
             Action::AcceptInput => {
@@ -44,14 +57,14 @@ impl CommandEditorWidget {
             }
             Action::DeleteBackward => {
                 let arg = state.focused_arg_mut().or_fail()?;
-                if let Some(c) = arg.prev_char(self.index) {
-                    self.index -= c.len_utf8();
-                    arg.remove(self.index).or_fail()?;
+                if let Some(g) = arg.prev_grapheme(self.index) {
+                    self.index -= g.len();
+                    arg.remove_grapheme(self.index).or_fail()?;
                 }
             }
             Action::DeleteChar => {
                 let arg = state.focused_arg_mut().or_fail()?;
-                arg.remove(self.index);
+                arg.remove_grapheme(self.index);
             }
             Action::DeleteToEnd => {
                 let arg = state.focused_arg_mut().or_fail()?;
@@ -59,20 +72,18 @@ impl CommandEditorWidget {
             }
             Action::MoveBackward | Action::CursorLeft => {
                 let arg = state.focused_arg_mut().or_fail()?;
-                if let Some(c) = arg.prev_char(self.index) {
-                    self.index -= c.len_utf8();
+                if let Some(g) = arg.prev_grapheme(self.index) {
+                    self.index -= g.len();
                 }
             }
             Action::MoveForward | Action::CursorRight => {
                 let arg = state.focused_arg_mut().or_fail()?;
-                if let Some(c) = arg.next_char(self.index) {
-                    self.index += c.len_utf8();
+                if let Some(g) = arg.next_grapheme(self.index) {
+                    self.index += g.len();
                 }
             }
             Action::MoveToStart => {
-                if self.index > 0 {
-                    self.index = 0;
-                }
+                self.index = 0;
             }
             Action::MoveToEnd => {
                 let arg = state.focused_arg_mut().or_fail()?;
@@ -90,7 +101,28 @@ impl CommandEditorWidget {
     }
 
     pub fn render(&self, state: &AppState, canvas: &mut Canvas) {
-        if state.focus.is_editing() {
+        if let Some(prompt) = &state.prompt {
+            canvas.draw(Token::with_style(
+                format!("[{}]: ", prompt.kind.label()),
+                TerminalStyle::new().bold(),
+            ));
+            canvas.drawln(Token::new(&prompt.input.text));
+        } else if state.confirming_quit {
+            canvas.drawln(Token::with_style(
+                "[COMMAND]: discard the edit and quit? (y/N)",
+                TerminalStyle::new().bold(),
+            ));
+        } else if state.focus == Focus::AndPattern {
+            let operator = if state.grep.or_secondary {
+                "--or"
+            } else {
+                "--and"
+            };
+            canvas.drawln(Token::with_style(
+                format!("[COMMAND]: editing the {operator} pattern…"),
+                TerminalStyle::new().bold(),
+            ));
+        } else if state.focus.is_editing() {
             canvas.drawln(Token::with_style(
                 "[COMMAND]: editing…",
                 TerminalStyle::new().bold(),
@@ -102,6 +134,43 @@ impl CommandEditorWidget {
         canvas.draw(Token::new("$ git"));
 
         self.render_grep_args(state, canvas, &state.grep.args(state.focus));
+        if state.settings.show_breadcrumb && !state.breadcrumb.is_empty() {
+            canvas.drawln(Token::with_style(
+                self.breadcrumb_text(state),
+                TerminalStyle::new().dim(),
+            ));
+        }
+    }
+
+    // The most recent entries that fit in the available columns, e.g., `…> 2:foo > 3:foo & bar`.
+    fn breadcrumb_text(&self, state: &AppState) -> String {
+        const OMISSION: &str = "…> ";
+        let entries = state
+            .breadcrumb
+            .labels()
+            .enumerate()
+            .map(|(i, label)| format!("{}:{label}", i + 1))
+            .collect::<Vec<_>>();
+        let mut text = String::new();
+        for (i, entry) in entries.iter().enumerate().rev() {
+            let candidate = if text.is_empty() {
+                entry.clone()
+            } else {
+                format!("{entry} > {text}")
+            };
+            let omission_cols = if i > 0 {
+                mame::terminal::str_cols(OMISSION)
+            } else {
+                0
+            };
+            if !text.is_empty()
+                && mame::terminal::str_cols(&candidate) + omission_cols > self.available_cols
+            {
+                return format!("{OMISSION}{text}");
+            }
+            text = candidate;
+        }
+        text
     }
 
     fn render_grep_args(&self, state: &AppState, canvas: &mut Canvas, args: &[GrepArg]) {
@@ -110,55 +179,420 @@ impl CommandEditorWidget {
             let focused = arg.kind.is_focused(state.focus);
             if multiline && arg.multiline_head {
                 canvas.newline();
-                canvas.set_cursor_col(Self::COL_OFFSET);
+                canvas.set_cursor_col(Self::line_head_col(state, arg));
             }
             let style = if focused {
                 TerminalStyle::new().bold()
             } else {
                 TerminalStyle::new()
             };
-            canvas.draw(Token::with_style(
-                format!(" {}", arg.maybe_quoted_text(state.focus)),
-                style,
-            ));
+            canvas.draw(arg_token(arg, state, style));
         }
         canvas.newline();
     }
 
+    // The command with the same line breaks and indentation as `render_grep_args()`.
+    pub fn displayed_command(&self, state: &AppState) -> String {
+        let multiline = self.is_multiline(state);
+        let mut text = "$ git".to_owned();
+        for arg in state.grep.args(state.focus) {
+            if multiline && arg.multiline_head {
+                text.push('\n');
+                text.push_str(&" ".repeat(Self::line_head_col(state, &arg)));
+            }
+            text.push_str(arg_token(&arg, state, TerminalStyle::new()).text());
+        }
+        text
+    }
+
     pub fn update_cursor_position(&self, state: &mut AppState) {
         if !state.focus.is_editing() {
             state.show_terminal_cursor = None;
             return;
         }
 
+        if let Some(prompt) = &state.prompt {
+            let col = Token::new(format!("[{}]: ", prompt.kind.label())).cols()
+                + Token::new(&prompt.input.text[0..self.index]).cols();
+            state.show_terminal_cursor = Some(TerminalPosition::row_col(0, col));
+            return;
+        }
+
         let multiline = self.is_multiline(state);
         let mut pos = TerminalPosition::row_col(Self::ROW_OFFSET, Self::COL_OFFSET);
         for arg in state.grep.args(state.focus) {
             let focused = arg.kind.is_focused(state.focus);
             if multiline && arg.multiline_head {
                 pos.row += 1;
-                pos.col = Self::COL_OFFSET;
+                pos.col = Self::line_head_col(state, &arg);
             }
-            pos.col += 1; // for ' ' prefix
-
             if focused {
-                pos.col += mame::terminal::str_cols(&arg.text[0..self.index]);
+                pos.col += 1; // for ' ' prefix
+                let prefix = if state.settings.arg_quoting == ArgQuoting::Quoted {
+                    arg.quoted_prefix(self.index)
+                } else {
+                    Cow::Borrowed(&arg.text[0..self.index])
+                };
+                pos.col += Token::new(prefix).cols();
                 state.show_terminal_cursor = Some(pos);
                 return;
             } else {
-                pos.col += arg.width(state.focus);
+                pos.col += arg_token(&arg, state, TerminalStyle::new()).cols();
             }
         }
     }
 
+    // Column where a line started by `arg` begins in multiline rendering.
+    fn line_head_col(state: &AppState, arg: &GrepArg) -> usize {
+        if state.settings.smart_multiline_indent {
+            Self::COL_OFFSET + arg.multiline_indent * Self::INDENT_COLS
+        } else {
+            Self::COL_OFFSET
+        }
+    }
+
     fn is_multiline(&self, state: &AppState) -> bool {
         let cols = Self::COL_OFFSET
             + state
                 .grep
                 .args(state.focus)
                 .iter()
-                .map(|a| a.width(state.focus) + 1) // +1 for ' ' prefix
+                .map(|a| arg_token(a, state, TerminalStyle::new()).cols())
                 .sum::<usize>();
         cols > self.available_cols
     }
 }
+
+// Column computations must be based on this token (rather than the raw arg text)
+// because `Token` escapes control chars, which changes the width.
+fn arg_token(arg: &GrepArg, state: &AppState, style: TerminalStyle) -> Token {
+    let text = match state.settings.arg_quoting {
+        ArgQuoting::Auto => arg.maybe_quoted_text(state.focus),
+        ArgQuoting::Quoted if arg.kind != GrepArgKind::Other || arg.is_grouping() => {
+            arg.quoted_text()
+        }
+        ArgQuoting::Quoted | ArgQuoting::Unquoted => Cow::Borrowed(arg.text.as_str()),
+    };
+    Token::with_style(format!(" {text}"), style)
+}
+
+#[cfg(test)]
+mod tests {
+    use orfail::OrFail;
+
+    use super::*;
+
+    fn render_and_get_cursor(
+        editor: &CommandEditorWidget,
+        state: &mut AppState,
+    ) -> (String, TerminalPosition) {
+        let mut canvas = Canvas::new(tuinix::TerminalSize::rows_cols(10, 200));
+        editor.render(state, &mut canvas);
+        editor.update_cursor_position(state);
+        let pos = state.show_terminal_cursor.expect("cursor");
+        let line = canvas
+            .into_frame()
+            .into_lines()
+            .nth(pos.row)
+            .expect("line")
+            .text();
+        (line, pos)
+    }
+
+    // Returns the rendered text preceding the cursor.
+    fn text_before_cursor(line: &str, pos: TerminalPosition) -> &str {
+        let mut cols = 0;
+        for (i, c) in line.char_indices() {
+            if cols == pos.col {
+                return &line[..i];
+            }
+            cols += mame::terminal::char_cols(c);
+        }
+        line
+    }
+
+    #[test]
+    fn smart_multiline_indent() {
+        let mut state = AppState::default();
+        state.grep.pattern.text = "foo".to_owned();
+        state.grep.and_pattern.text = "bar".to_owned();
+        state.grep.not_pattern.text = "baz".to_owned();
+        state.grep.path.text = "src/".to_owned();
+        state.focus = Focus::NotPattern;
+        state.settings.smart_multiline_indent = true;
+
+        let mut editor = CommandEditorWidget::default();
+        editor.set_available_cols(20);
+        editor.handle_focus_change(&mut state);
+        editor.index = 1;
+
+        let mut canvas = Canvas::new(tuinix::TerminalSize::rows_cols(10, 200));
+        editor.render(&state, &mut canvas);
+        let lines = canvas
+            .into_frame()
+            .into_lines()
+            .map(|l| l.text())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            &lines[1..6],
+            [
+                "$ git grep -nI",
+                "      -e foo",
+                "         --and -e bar",
+                "         --and --not -e baz",
+                "      -- src/",
+            ]
+        );
+
+        let (line, pos) = render_and_get_cursor(&editor, &mut state);
+        assert_eq!(text_before_cursor(&line, pos), "         --and --not -e b");
+
+        // Single-line rendering is unchanged when the command fits.
+        editor.set_available_cols(200);
+        let (line, _) = render_and_get_cursor(&editor, &mut state);
+        assert!(line.starts_with("$ git grep -nI -e foo --and -e bar"));
+    }
+
+    #[test]
+    fn displayed_command() {
+        let mut state = AppState::default();
+        state.grep.pattern.text = "foo bar".to_owned();
+        state.grep.and_pattern.text = "baz".to_owned();
+        state.settings.smart_multiline_indent = true;
+
+        let mut editor = CommandEditorWidget::default();
+        editor.set_available_cols(200);
+        assert_eq!(
+            editor.displayed_command(&state),
+            "$ git grep -nI -e 'foo bar' --and -e baz"
+        );
+
+        editor.set_available_cols(20);
+        assert_eq!(
+            editor.displayed_command(&state),
+            "$ git grep -nI\n      -e 'foo bar'\n         --and -e baz"
+        );
+    }
+
+    #[test]
+    fn unsaved_edit_detection() -> orfail::Result<()> {
+        let mut state = AppState::default();
+        state.grep.pattern.text = "foo".to_owned();
+        let mut editor = CommandEditorWidget::default();
+        assert!(!editor.has_unsaved_edit(&state));
+
+        state.focus = Focus::Pattern;
+        editor.handle_focus_change(&mut state);
+        assert!(!editor.has_unsaved_edit(&state));
+
+        state.last_input_char = 'x';
+        editor
+            .handle_action(&mut state, Action::InsertChar)
+            .or_fail()?;
+        assert!(editor.has_unsaved_edit(&state));
+
+        // Reverting the text makes it clean again.
+        editor
+            .handle_action(&mut state, Action::DeleteBackward)
+            .or_fail()?;
+        assert!(!editor.has_unsaved_edit(&state));
+
+        Ok(())
+    }
+
+    #[test]
+    fn grapheme_cluster_editing() -> orfail::Result<()> {
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let mut state = AppState::default();
+        state.grep.pattern.text = format!("e\u{301}{family}x");
+        state.focus = Focus::Pattern;
+        let mut editor = CommandEditorWidget::default();
+        editor.handle_focus_change(&mut state);
+
+        editor
+            .handle_action(&mut state, Action::MoveToStart)
+            .or_fail()?;
+        editor
+            .handle_action(&mut state, Action::MoveForward)
+            .or_fail()?;
+        assert_eq!(editor.index, "e\u{301}".len());
+        editor
+            .handle_action(&mut state, Action::MoveForward)
+            .or_fail()?;
+        assert_eq!(editor.index, "e\u{301}".len() + family.len());
+        editor
+            .handle_action(&mut state, Action::MoveBackward)
+            .or_fail()?;
+        assert_eq!(editor.index, "e\u{301}".len());
+
+        // Deletion removes a whole cluster, not just a code point.
+        editor
+            .handle_action(&mut state, Action::DeleteChar)
+            .or_fail()?;
+        assert_eq!(state.grep.pattern.text, "e\u{301}x");
+        editor
+            .handle_action(&mut state, Action::DeleteBackward)
+            .or_fail()?;
+        assert_eq!(state.grep.pattern.text, "x");
+        assert_eq!(editor.index, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn cursor_column_with_wide_chars() {
+        let mut state = AppState::default();
+        state.grep.pattern.text = "日本語".to_owned();
+        state.grep.and_pattern.text = "\tfoo".to_owned();
+        state.focus = Focus::Pattern;
+
+        let mut editor = CommandEditorWidget::default();
+        editor.set_available_cols(200);
+        editor.handle_focus_change(&mut state);
+        editor.index = "日".len();
+
+        let (line, pos) = render_and_get_cursor(&editor, &mut state);
+        assert_eq!(pos.col, "$ git grep -nI -e ".len() + 2);
+        assert_eq!(text_before_cursor(&line, pos), "$ git grep -nI -e 日");
+
+        // The cursor stays on the right char after an arg containing a control char.
+        state.focus = Focus::NotPattern;
+        state.grep.not_pattern.text = "語".to_owned();
+        editor.handle_focus_change(&mut state);
+        editor.index = 0;
+
+        let (line, pos) = render_and_get_cursor(&editor, &mut state);
+        assert_eq!(
+            text_before_cursor(&line, pos),
+            "$ git grep -nI -e 日本語 --and -e \\tfoo --and --not -e "
+        );
+    }
+
+    #[test]
+    fn cursor_column_after_quoted_args() {
+        let mut state = AppState::default();
+        state.grep.pattern.text = "foo bar".to_owned();
+        state.grep.and_pattern.text = "it's".to_owned();
+        state.focus = Focus::AndPattern;
+
+        let mut editor = CommandEditorWidget::default();
+        editor.set_available_cols(200);
+        editor.handle_focus_change(&mut state);
+        editor.index = "it".len();
+
+        // The focused arg is drawn unquoted while the preceding ones are quoted.
+        let (line, pos) = render_and_get_cursor(&editor, &mut state);
+        assert_eq!(line.trim_end(), "$ git grep -nI -e 'foo bar' --and -e it's");
+        assert_eq!(
+            text_before_cursor(&line, pos),
+            "$ git grep -nI -e 'foo bar' --and -e it"
+        );
+
+        // Same for an arg containing both spaces and single quotes.
+        state.grep.and_pattern.text.clear();
+        state.grep.pattern.text = "it's a".to_owned();
+        state.grep.path.text = "src dir".to_owned();
+        state.focus = Focus::Path;
+        editor.handle_focus_change(&mut state);
+        editor.index = "src".len();
+
+        let (line, pos) = render_and_get_cursor(&editor, &mut state);
+        assert_eq!(
+            text_before_cursor(&line, pos),
+            r"$ git grep -nI 'it'\''s a' -- src"
+        );
+    }
+
+    #[test]
+    fn cursor_column_with_arg_quoting() {
+        let mut state = AppState::default();
+        state.grep.pattern.text = "a b".to_owned();
+        state.grep.and_pattern.text = "it's x".to_owned();
+        state.focus = Focus::AndPattern;
+
+        let mut editor = CommandEditorWidget::default();
+        editor.set_available_cols(200);
+        editor.handle_focus_change(&mut state);
+        editor.index = "it's".len();
+
+        state.settings.arg_quoting = ArgQuoting::Unquoted;
+        let (line, pos) = render_and_get_cursor(&editor, &mut state);
+        assert_eq!(line.trim_end(), "$ git grep -nI -e a b --and -e it's x");
+        assert_eq!(
+            text_before_cursor(&line, pos),
+            "$ git grep -nI -e a b --and -e it's"
+        );
+
+        state.settings.arg_quoting = ArgQuoting::Quoted;
+        let (line, pos) = render_and_get_cursor(&editor, &mut state);
+        assert_eq!(
+            line.trim_end(),
+            r"$ git grep -nI -e 'a b' --and -e 'it'\''s x'"
+        );
+        assert_eq!(
+            text_before_cursor(&line, pos),
+            r"$ git grep -nI -e 'a b' --and -e 'it'\''s"
+        );
+
+        editor.index = 0;
+        let (line, pos) = render_and_get_cursor(&editor, &mut state);
+        assert_eq!(
+            text_before_cursor(&line, pos),
+            "$ git grep -nI -e 'a b' --and -e '"
+        );
+    }
+
+    #[test]
+    fn breadcrumb_line() {
+        let mut state = AppState::default();
+        state.settings.show_breadcrumb = true;
+        for pattern in ["foo", "foobar", "baz"] {
+            state.grep.pattern.text = pattern.to_owned();
+            state.breadcrumb.push(&state.grep.clone());
+        }
+
+        let mut editor = CommandEditorWidget::default();
+        editor.set_available_cols(80);
+        assert_eq!(editor.breadcrumb_text(&state), "1:foo > 2:foobar > 3:baz");
+
+        // Older entries are omitted if they do not fit.
+        editor.set_available_cols(20);
+        assert_eq!(editor.breadcrumb_text(&state), "…> 2:foobar > 3:baz");
+        editor.set_available_cols(5);
+        assert_eq!(editor.breadcrumb_text(&state), "…> 3:baz");
+
+        editor.set_available_cols(80);
+        let mut canvas = Canvas::new(tuinix::TerminalSize::rows_cols(4, 80));
+        editor.render(&state, &mut canvas);
+        let lines = canvas
+            .into_frame()
+            .into_lines()
+            .map(|l| l.text().trim_end().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(lines[1], "$ git grep -nI baz");
+        assert_eq!(lines[2], "1:foo > 2:foobar > 3:baz");
+    }
+
+    #[test]
+    fn focus_change_resets_editing_position() {
+        let mut state = AppState::default();
+        state.grep.pattern.text = "foo".to_owned();
+        state.grep.and_pattern.text = "barbaz".to_owned();
+
+        let mut editor = CommandEditorWidget::default();
+        editor.set_available_cols(200);
+        state.focus = Focus::Pattern;
+        editor.handle_focus_change(&mut state);
+        editor.index = 1;
+
+        // Each transition starts editing at the end of the newly focused arg.
+        state.focus = state.focus.next();
+        editor.handle_focus_change(&mut state);
+        assert_eq!((editor.original_text.as_str(), editor.index), ("barbaz", 6));
+        assert!(!editor.has_unsaved_edit(&state));
+
+        state.focus = state.focus.next();
+        editor.handle_focus_change(&mut state);
+        assert_eq!((editor.original_text.as_str(), editor.index), ("", 0));
+    }
+}
@@ -57,18 +57,100 @@ impl LegendWidget {
             .filter(|_| !self.hide)
             .filter(|b| b.action.as_ref().is_none_or(|a| a.is_applicable(state)))
             .filter_map(|b| {
-                let label = b.label.as_ref()?;
+                let label = item_label(b, state)?;
                 Some(
-                    if label.starts_with('[')
+                    if b.label.as_ref().is_some_and(|l| l.starts_with('['))
                         || b.action.as_ref().is_some_and(|a| a.is_flag_set(state))
                     {
                         let style = tuinix::TerminalStyle::new().bold();
                         let reset = tuinix::TerminalStyle::RESET;
                         format!("{style}{label}{reset}")
                     } else {
-                        label.to_owned()
+                        label
                     },
                 )
             })
     }
 }
+
+// With `legend-toggle-indicators`, toggles are prefixed with `[x]` or `[ ]`,
+// and the other actions are indented by the same width so that the labels stay aligned.
+// Labels without actions (e.g., headings) are kept as is.
+fn item_label(binding: &Binding<Action>, state: &AppState) -> Option<String> {
+    let label = binding.label.as_ref()?;
+    let Some(action) = binding
+        .action
+        .as_ref()
+        .filter(|_| state.settings.legend_toggle_indicators)
+    else {
+        return Some(label.to_owned());
+    };
+    let indicator = if !action.is_toggle() {
+        "   "
+    } else if action.is_flag_set(state) {
+        "[x]"
+    } else {
+        "[ ]"
+    };
+    Some(format!("{indicator}{label}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggle_indicators() {
+        let binding = |label: &str, action: Option<Action>| Binding {
+            triggers: Vec::new(),
+            label: Some(label.to_owned()),
+            action,
+            context: None,
+        };
+        let bindings = [
+            binding("[ACTIONS]", None),
+            binding(" (i)gnore case", Some(Action::FlipIgnoreCase)),
+            binding(" (w)hole word", Some(Action::FlipWholeWord)),
+            binding(" (c)opy reference", Some(Action::YankCodeReference)),
+        ];
+        let mut state = AppState::default();
+        state.grep.ignore_case = true;
+        let labels = |state: &AppState| {
+            bindings
+                .iter()
+                .filter_map(|b| item_label(b, state))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            labels(&state),
+            [
+                "[ACTIONS]",
+                " (i)gnore case",
+                " (w)hole word",
+                " (c)opy reference"
+            ]
+        );
+
+        state.settings.legend_toggle_indicators = true;
+        assert_eq!(
+            labels(&state),
+            [
+                "[ACTIONS]",
+                "[x] (i)gnore case",
+                "[ ] (w)hole word",
+                "    (c)opy reference"
+            ]
+        );
+        assert_eq!(
+            item_label(
+                &Binding {
+                    label: None,
+                    ..binding("", None)
+                },
+                &state
+            ),
+            None
+        );
+    }
+}
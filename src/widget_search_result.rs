@@ -1,36 +1,195 @@
-use std::{num::NonZeroUsize, path::PathBuf};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    num::NonZeroUsize,
+    ops::{Range, RangeInclusive},
+    path::{Path, PathBuf},
+};
 
 use orfail::OrFail;
-use tuinix::TerminalStyle;
+use tuinix::{TerminalPosition, TerminalSize, TerminalStyle};
 
 use crate::{
     action::Action,
     app::AppState,
-    canvas::{Canvas, Token},
+    canvas::{Canvas, Frame, FrameLine, Token},
     git::{ContextLines, Line},
+    settings::{ControlChars, CursorMarkers},
 };
 
 #[derive(Debug, Default)]
-pub struct SearchResultWidget {}
+pub struct SearchResultWidget {
+    row_offset: usize,
+    // Row offset and height of the file list in the last rendering (the base of manual scrolling).
+    shown_row_offset: usize,
+    shown_rows: usize,
+    // Number of rendered lines whose hit texts could not be all found (e.g., edited during the search).
+    highlight_mismatches: usize,
+    // Canvas rows of the file headings in the last rendering (for the sticky heading).
+    heading_rows: Vec<HeadingRow>,
+    // Rows of the unfocused hit lines, which stay the same while the cursor moves or the view scrolls.
+    row_cache: RowCache,
+    // Glyphs of the minimap by canvas row, drawn in the column reserved at the right edge.
+    minimap_rows: BTreeMap<usize, char>,
+}
+
+// Only the cursor markers, the focused line and its context are rendered anew on a cursor move,
+// and the other rows are taken from here until the result or the view settings change.
+#[derive(Debug, Default)]
+struct RowCache {
+    view_generation: u64,
+    cols: usize,
+    // Rows (with whether their highlights are mismatched) per file.
+    rows: BTreeMap<PathBuf, BTreeMap<RowKey, (FrameLine, bool)>>,
+}
+
+// Line number, whether it is a hit, and the style of the line (e.g., dimmed if visited).
+type RowKey = (NonZeroUsize, bool, TerminalStyle);
+
+impl RowCache {
+    fn invalidate_if_changed(&mut self, state: &AppState, cols: usize) {
+        if self.view_generation != state.view_generation || self.cols != cols {
+            self.view_generation = state.view_generation;
+            self.cols = cols;
+            self.rows.clear();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct HeadingRow {
+    row: usize,
+    file_index: usize,
+    file: PathBuf,
+    name: String,
+}
 
 impl SearchResultWidget {
-    pub fn render(&self, state: &AppState, canvas: &mut Canvas) {
+    pub fn render(&mut self, state: &AppState, canvas: &mut Canvas) {
         if let Some(error) = &state.search_result.error {
             self.render_error(state, canvas, error);
             return;
         }
-        self.render_header_line(state, canvas);
-
+        // The files are rendered first as the header tells the mismatches found in them.
         let mut size = canvas.frame_size();
-        size.rows = size.rows.saturating_sub(canvas.cursor().row);
+        size.rows = size.rows.saturating_sub(canvas.cursor().row + 1);
+        let frame = self.render_files_frame(state, size);
 
-        let mut tmp_canvas = Canvas::new(size);
-        tmp_canvas.set_auto_scroll(true);
-        self.render_files(state, &mut tmp_canvas);
+        self.render_header_line(state, canvas);
 
-        for line in tmp_canvas.into_frame().into_lines() {
+        let first_row = canvas.cursor().row;
+        for line in frame.into_lines() {
             canvas.draw_frame_line(line);
         }
+        if state.show_minimap {
+            let col = canvas.frame_size().cols.saturating_sub(1);
+            for (row, glyph) in self
+                .minimap_rows
+                .range(self.shown_row_offset..self.shown_row_offset + size.rows)
+            {
+                let position =
+                    TerminalPosition::row_col(first_row + row - self.shown_row_offset, col);
+                canvas.draw_at(position, Token::new(glyph.to_string()));
+            }
+        }
+        if state.settings.sticky_file_heading {
+            self.render_sticky_heading(state, canvas, first_row);
+        }
+    }
+
+    fn render_files_frame(&mut self, state: &AppState, mut size: TerminalSize) -> Frame {
+        if state.show_minimap {
+            // Reserves the last column so that the minimap never covers the text.
+            size.cols = size.cols.saturating_sub(1);
+        }
+        self.row_cache.invalidate_if_changed(state, size.cols);
+        let tmp_canvas = if let Some(offset) = state.scroll_offset {
+            let tmp_canvas = self.render_scrolled_files(state, size, offset);
+            // Scrolling beyond the end shows the last page instead.
+            let max_offset = tmp_canvas.cursor().row.saturating_sub(size.rows);
+            if offset > max_offset {
+                self.render_scrolled_files(state, size, max_offset)
+            } else {
+                tmp_canvas
+            }
+        } else {
+            let mut tmp_canvas = Canvas::new(size);
+            tmp_canvas.set_auto_scroll(true);
+            self.render_files(state, &mut tmp_canvas);
+            tmp_canvas
+        };
+        self.shown_row_offset = tmp_canvas.row_offset();
+        self.shown_rows = size.rows;
+        tmp_canvas.into_frame()
+    }
+
+    // Pins the heading of the file at the top row if the heading itself has been scrolled off.
+    // The top row is covered even when it is the last line of the file (i.e., the next heading follows).
+    fn render_sticky_heading(&self, state: &AppState, canvas: &mut Canvas, row: usize) {
+        let Some(heading) = self
+            .heading_rows
+            .iter()
+            .take_while(|h| h.row <= self.shown_row_offset)
+            .last()
+            .filter(|h| h.row < self.shown_row_offset)
+        else {
+            return;
+        };
+
+        let cols = canvas.frame_size().cols;
+        let mut col = state.settings.cursor_markers.file_cols();
+        canvas.draw_at(
+            TerminalPosition::row_col(row, 0),
+            Token::new(" ".repeat(cols)),
+        );
+        for token in file_heading_tokens(state, heading.file_index, &heading.file, &heading.name) {
+            let token_cols = token.cols();
+            canvas.draw_at(TerminalPosition::row_col(row, col), token);
+            col += token_cols;
+        }
+    }
+
+    // Maps the rows of the file onto its lines (from the first to the last one in the result),
+    // so that each glyph tells how many hits are in that region of the file.
+    fn add_minimap(
+        &mut self,
+        state: &AppState,
+        file: &PathBuf,
+        lines: &[Line],
+        rows: Range<usize>,
+    ) {
+        let (Some(first), Some(last)) = (lines.first(), lines.last()) else {
+            return;
+        };
+        let hits = lines
+            .iter()
+            .filter(|l| state.is_hit_shown(file, l))
+            .map(|l| l.number.get());
+        let buckets = minimap_buckets(hits, first.number.get()..=last.number.get(), rows.len());
+        let max = buckets.iter().copied().max().unwrap_or_default();
+        for (row, count) in rows.zip(buckets) {
+            self.minimap_rows.insert(row, minimap_glyph(count, max));
+        }
+    }
+
+    fn render_scrolled_files(
+        &mut self,
+        state: &AppState,
+        size: TerminalSize,
+        offset: usize,
+    ) -> Canvas {
+        let mut canvas = Canvas::new(size);
+        canvas.scroll(offset);
+        self.render_files(state, &mut canvas);
+        canvas
+    }
+
+    fn scroll(&self, state: &mut AppState, delta: isize) {
+        state.scroll_offset = Some(self.shown_row_offset.saturating_add_signed(delta));
+    }
+
+    fn page_rows(&self) -> isize {
+        self.shown_rows.saturating_sub(1).max(1) as isize
     }
 
     fn render_error(&self, state: &AppState, canvas: &mut Canvas, error: &str) {
@@ -51,121 +210,580 @@ impl SearchResultWidget {
             TerminalStyle::new().bold()
         };
 
-        canvas.drawln(Token::with_style(
+        let header = if let Some(format) = &state.settings.header_format {
+            let (hit_texts, hit_lines, hit_files) = state.shown_hit_counts();
+            let mut header = format!(
+                "[RESULT]: {}",
+                format.format(
+                    hit_texts,
+                    hit_lines,
+                    hit_files,
+                    state.search_result.duration
+                )
+            );
+            if state.min_match_len > 0 {
+                header.push_str(&format!(" (min match length: {})", state.min_match_len));
+            }
+            header
+        } else if state.min_match_len == 0 {
             format!(
                 "[RESULT]: {} hits, {} lines, {} files",
                 state.search_result.hit_texts(),
                 state.search_result.hit_lines(),
                 state.search_result.hit_files()
-            ),
-            style,
-        ));
+            )
+        } else {
+            let (hit_texts, hit_lines, hit_files) = state.shown_hit_counts();
+            format!(
+                "[RESULT]: {}/{} hits, {}/{} lines, {}/{} files (min match length: {})",
+                hit_texts,
+                state.search_result.hit_texts(),
+                hit_lines,
+                state.search_result.hit_lines(),
+                hit_files,
+                state.search_result.hit_files(),
+                state.min_match_len
+            )
+        };
+        let header = if state.grep.added_lines_only && !state.grep.filename_search {
+            format!("{header} (added lines only)")
+        } else {
+            header
+        };
+        let header = if state.dense_mode {
+            format!("{header} (dense)")
+        } else {
+            header
+        };
+        let header = if let Some((revision_hits, worktree_hits)) = state.revision_comparison {
+            format!(
+                "{header} ({}: {revision_hits}, worktree: {worktree_hits})",
+                state.grep.revision.text
+            )
+        } else {
+            header
+        };
+        let header = if state.directory_summary.is_some() {
+            format!("{header} (by directory)")
+        } else {
+            header
+        };
+        let header = if self.highlight_mismatches > 0 {
+            format!(
+                "{header} ({} lines with mismatched highlights)",
+                self.highlight_mismatches
+            )
+        } else {
+            header
+        };
+        let header = if state.stale {
+            format!("{header} (stale: refresh to update)")
+        } else {
+            header
+        };
+        let header = if state.scroll_offset.is_some() {
+            format!("{header} (scrolled)")
+        } else {
+            header
+        };
+        let header = Token::with_style(header, style);
+        let status = Token::new(flag_status(state));
+
+        // The status is dropped rather than pushing the header off-screen.
+        let header_cols = header.cols();
+        canvas.draw(header);
+        if !status.text().is_empty() && header_cols + 1 + status.cols() <= canvas.frame_size().cols
+        {
+            canvas.draw(Token::new(" "));
+            canvas.draw(status);
+        }
+        canvas.newline();
     }
 
-    fn render_files(&self, state: &AppState, canvas: &mut Canvas) {
-        for (file_index, (file, lines)) in state.search_result.files.iter().enumerate() {
+    fn render_files(&mut self, state: &AppState, canvas: &mut Canvas) {
+        self.highlight_mismatches = 0;
+        self.heading_rows.clear();
+        self.minimap_rows.clear();
+        if let Some(selected) = state.directory_summary {
+            self.render_directory_summary(state, canvas, selected);
+            return;
+        }
+        let window = state.file_window();
+        if window.start > 0 {
+            canvas.drawln(Token::new(format!(
+                "   (+{} more files not shown)",
+                window.start
+            )));
+        }
+
+        let short_names = state
+            .settings
+            .basename_only
+            .then(|| short_names(state.search_result.files.keys()));
+        let mut rendered_files = window.start;
+        for (file_index, (file, lines)) in state
+            .shown_files()
+            .enumerate()
+            .skip(window.start)
+            .take(window.len())
+        {
             if canvas.is_frame_exceeded() {
                 break;
             }
+            if state.settings.file_breaks && !state.dense_mode && file_index > window.start {
+                // Like `git grep --break`.
+                canvas.newline();
+            }
+            rendered_files = file_index + 1;
 
-            if state.cursor.render_for_file(canvas, file) {
-                self.recenter(canvas);
+            let name = short_names
+                .as_ref()
+                .and_then(|names| names.get(file).cloned())
+                .unwrap_or_else(|| file.display().to_string());
+            if state.inline_format {
+                self.render_inline_file(state, canvas, file, &name, lines);
+                continue;
+            }
+
+            if state
+                .cursor
+                .render_for_file(canvas, file, &state.settings.cursor_markers)
+            {
+                self.recenter(state, canvas);
+            }
+            self.heading_rows.push(HeadingRow {
+                row: canvas.cursor().row,
+                file_index,
+                file: file.clone(),
+                name: name.clone(),
+            });
+            for token in file_heading_tokens(state, file_index, file, &name) {
+                canvas.draw(token);
             }
-            canvas.draw(Token::new(format!("{}# ", file_index + 1)));
-            canvas.draw(Token::with_style(
-                format!("{}", file.display()),
-                TerminalStyle::new().underline(),
-            ));
-            canvas.draw(Token::new(format!(
-                " ({} hits, {} lines)",
-                state.search_result.hit_texts_in_file(file),
-                state.search_result.hit_lines_in_file(file)
-            )));
 
             if state.collapsed.contains(file) {
-                canvas.drawln(Token::new("…"));
+                canvas.drawln(Token::new(&state.settings.collapsed_indicator));
             } else {
                 canvas.newline();
+                let first_row = canvas.cursor().row;
                 self.render_lines(state, canvas, file, lines);
+                if state.show_minimap {
+                    self.add_minimap(state, file, lines, first_row..canvas.cursor().row);
+                }
+            }
+        }
+
+        if rendered_files == window.end {
+            let rest = state.shown_files().count().saturating_sub(window.end);
+            if rest > 0 {
+                canvas.drawln(Token::new(format!("   (+{rest} more files not shown)")));
             }
         }
     }
 
-    fn render_lines(&self, state: &AppState, canvas: &mut Canvas, file: &PathBuf, lines: &[Line]) {
-        for line in lines.iter().filter(|l| l.hit) {
+    fn render_directory_summary(&mut self, state: &AppState, canvas: &mut Canvas, selected: usize) {
+        let dirs = state.search_result.directory_hits();
+        let max_hits = dirs.first().map_or(0, |(_, n)| *n);
+        let name_cols = dirs
+            .iter()
+            .map(|(dir, _)| mame::terminal::str_cols(dir))
+            .max()
+            .unwrap_or_default();
+        let count_cols = max_hits.to_string().len();
+        let markers = &state.settings.cursor_markers;
+        for (i, (dir, hits)) in dirs.iter().enumerate() {
+            if i == selected {
+                canvas.draw(Token::new(markers.file()));
+                self.recenter(state, canvas);
+            } else {
+                canvas.draw(Token::new(" ".repeat(markers.file_cols())));
+            }
+            let padding = name_cols - mame::terminal::str_cols(dir);
+            canvas.drawln(Token::new(format!(
+                "{dir}/{} {hits:>count_cols$} {}",
+                " ".repeat(padding),
+                bar(*hits, max_hits, DIRECTORY_BAR_COLS)
+            )));
+        }
+    }
+
+    // Like `git grep` without `--heading`, each line is prefixed with the path.
+    // The file-level cursor is drawn on the first line of the file.
+    fn render_inline_file(
+        &mut self,
+        state: &AppState,
+        canvas: &mut Canvas,
+        file: &PathBuf,
+        name: &str,
+        lines: &[Line],
+    ) {
+        let markers = &state.settings.cursor_markers;
+        let file_focused = state.cursor.is_file_level() && state.cursor.file.as_ref() == Some(file);
+        let file_marker = || {
+            let padding = markers.line_cols().saturating_sub(markers.file_cols());
+            Token::new(format!("{}{}", markers.file(), " ".repeat(padding)))
+        };
+
+        let hit_lines = lines
+            .iter()
+            .filter(|l| state.is_hit_shown(file, l))
+            .collect::<Vec<_>>();
+        if state.collapsed.contains(file) || hit_lines.is_empty() {
+            if file_focused {
+                canvas.draw(file_marker());
+                self.recenter(state, canvas);
+            } else {
+                canvas.draw(Token::new(" ".repeat(markers.line_cols())));
+            }
+            let suffix = if hit_lines.is_empty() { "" } else { " …" };
+            canvas.drawln(Token::new(format!("{name}{suffix}")));
+            return;
+        }
+
+        for (i, line) in hit_lines.into_iter().enumerate() {
             if canvas.is_frame_exceeded() {
                 break;
             }
 
-            let focused = state.cursor.is_line_focused(file, line.number);
+            let focused = !state.dense_mode && state.cursor.is_line_focused(file, line.number);
+            let (before, after) = if focused {
+                let j = lines
+                    .binary_search_by_key(&line.number, |l| l.number)
+                    .expect("infallible");
+                let n = state.grep.context_lines.0;
+                (
+                    &lines[j.saturating_sub(n)..j],
+                    &lines[j + 1..(j + 1 + n).min(lines.len())],
+                )
+            } else {
+                (&lines[..0], &lines[..0])
+            };
+            self.render_inline_context(state, canvas, file, name, before);
+            if i == 0 && file_focused {
+                canvas.draw(file_marker());
+                self.recenter(state, canvas);
+            } else if state
+                .cursor
+                .render_for_line(canvas, file, line.number, markers)
+            {
+                self.recenter(state, canvas);
+            }
+            canvas.draw(Token::new(format!("{name}:{}: ", line.number)));
+            self.render_line_text(state, canvas, file, line);
+            self.render_inline_context(state, canvas, file, name, after);
+        }
+    }
+
+    fn render_inline_context(
+        &self,
+        state: &AppState,
+        canvas: &mut Canvas,
+        file: &PathBuf,
+        name: &str,
+        lines: &[Line],
+    ) {
+        for line in lines {
+            let text = visible_text(
+                &line.text,
+                0..line.text.len(),
+                state.settings.show_whitespace,
+                state.settings.control_chars,
+            );
+            canvas.drawln(Token::with_style(
+                format!(
+                    "{:>width$}{name}-{}- {text}",
+                    "",
+                    line.number,
+                    width = state.settings.cursor_markers.line_cols()
+                ),
+                line_style(state, file, line),
+            ));
+        }
+    }
+
+    fn render_lines(
+        &mut self,
+        state: &AppState,
+        canvas: &mut Canvas,
+        file: &PathBuf,
+        lines: &[Line],
+    ) {
+        if let Some((expanded, content)) = &state.expanded_file
+            && expanded == file
+        {
+            self.render_full_file(state, canvas, file, lines, content);
+            return;
+        }
+
+        if !lines.iter().any(|l| l.hit) {
+            self.render_context_lines(state, canvas, file, lines);
+            return;
+        }
+
+        if state.settings.merge_context
+            && !state.dense_mode
+            && state.grep.context_lines != ContextLines::MIN
+            && let Some(focused) = lines.iter().position(|l| {
+                state.is_hit_shown(file, l) && state.cursor.is_line_focused(file, l.number)
+            })
+        {
+            self.render_merged_lines(state, canvas, file, lines, focused);
+            return;
+        }
+
+        for line in lines.iter().filter(|l| state.is_hit_shown(file, l)) {
+            if canvas.is_frame_exceeded() {
+                break;
+            }
+
+            let focused = !state.dense_mode && state.cursor.is_line_focused(file, line.number);
             if focused {
-                self.render_before_lines(state, canvas, lines, line);
+                self.render_before_lines(state, canvas, file, lines, line);
             }
             self.render_line(state, canvas, file, line);
             if focused {
-                self.render_after_lines(state, canvas, lines, line);
+                self.render_after_lines(state, canvas, file, lines, line);
             }
         }
     }
 
-    fn render_line(&self, state: &AppState, canvas: &mut Canvas, file: &PathBuf, line: &Line) {
-        if state.cursor.render_for_line(canvas, file, line.number) {
-            self.recenter(canvas);
+    // Renders the context window of the focused line as a single block,
+    // in which the other hit lines are shown as hits rather than duplicated as context.
+    fn render_merged_lines(
+        &mut self,
+        state: &AppState,
+        canvas: &mut Canvas,
+        file: &PathBuf,
+        lines: &[Line],
+        focused: usize,
+    ) {
+        let n = state.grep.context_lines.0;
+        let window = focused.saturating_sub(n)..(focused + 1 + n).min(lines.len());
+        let mut segments = context_segments(lines, window.clone(), |l| state.is_hit_shown(file, l))
+            .into_iter()
+            .peekable();
+        let mut i = 0;
+        while i < lines.len() && !canvas.is_frame_exceeded() {
+            if i == window.start {
+                canvas.newline();
+            }
+            if let Some(segment) = segments.next_if(|s| s.start == i) {
+                self.render_context_lines(state, canvas, file, &lines[segment.clone()]);
+                i = segment.end;
+            } else {
+                if state.is_hit_shown(file, &lines[i]) {
+                    self.render_line(state, canvas, file, &lines[i]);
+                }
+                i += 1;
+            }
+            if i == window.end {
+                canvas.newline();
+            }
+        }
+    }
+
+    // The hit lines are taken from the result (to keep their highlights) and the others from the content.
+    fn render_full_file(
+        &mut self,
+        state: &AppState,
+        canvas: &mut Canvas,
+        file: &PathBuf,
+        lines: &[Line],
+        content: &[String],
+    ) {
+        let mut hits = lines.iter().filter(|l| l.hit).peekable();
+        for (i, text) in content.iter().enumerate() {
+            if canvas.is_frame_exceeded() {
+                break;
+            }
+            let number = NonZeroUsize::MIN.saturating_add(i);
+            while hits.next_if(|l| l.number < number).is_some() {}
+            if let Some(line) = hits.next_if(|l| l.number == number) {
+                self.render_line(state, canvas, file, line);
+            } else {
+                let line = Line {
+                    number,
+                    text: text.clone(),
+                    hit: false,
+                };
+                self.render_line(state, canvas, file, &line);
+            }
+        }
+    }
+
+    fn render_line(&mut self, state: &AppState, canvas: &mut Canvas, file: &PathBuf, line: &Line) {
+        let focused = state.cursor.is_line_focused(file, line.number);
+        let key = (line.number, line.hit, line_style(state, file, line));
+        if !focused
+            && let Some((row, mismatched)) = self.row_cache.rows.get(file).and_then(|r| r.get(&key))
+        {
+            if *mismatched {
+                self.highlight_mismatches += 1;
+            }
+            canvas.drawln_frame_line(row.clone());
+            return;
+        }
+
+        let row = canvas.cursor().row;
+        let mismatches = self.highlight_mismatches;
+        if state
+            .cursor
+            .render_for_line(canvas, file, line.number, &state.settings.cursor_markers)
+        {
+            self.recenter(state, canvas);
         }
         canvas.draw(Token::new(format!(
             "[{:>width$}] ",
             line.number,
-            width = state.search_result.max_line_width
+            width = state
+                .search_result
+                .line_number_width(state.settings.line_number_width)
         )));
+        self.render_line_text(state, canvas, file, line);
+
+        // Rows out of the frame are not drawn, so they are not cached either.
+        if !focused && let Some(frame_line) = canvas.frame_line(row) {
+            let mismatched = self.highlight_mismatches > mismatches;
+            self.row_cache
+                .rows
+                .entry(file.clone())
+                .or_default()
+                .insert(key, (frame_line.clone(), mismatched));
+        }
+    }
+
+    fn render_line_text(
+        &mut self,
+        state: &AppState,
+        canvas: &mut Canvas,
+        file: &PathBuf,
+        line: &Line,
+    ) {
         let col_offset = canvas.cursor().col;
-        canvas.draw(Token::new(&line.text));
-        self.highlight_line(state, canvas, file, line, col_offset);
+        canvas.draw(Token::with_style(
+            visible_text(
+                &line.text,
+                0..line.text.len(),
+                state.settings.show_whitespace,
+                state.settings.control_chars,
+            ),
+            line_style(state, file, line),
+        ));
+        if self.highlight_line(state, canvas, file, line, col_offset) {
+            self.highlight_mismatches += 1;
+            let position = TerminalPosition::row_col(canvas.cursor().row, col_offset - 1);
+            canvas.draw_at(position, Token::new("~"));
+        }
         canvas.newline();
     }
 
-    fn recenter(&self, canvas: &mut Canvas) {
+    fn recenter(&mut self, state: &AppState, canvas: &mut Canvas) {
+        if state.scroll_offset.is_some() {
+            return;
+        }
         canvas.set_auto_scroll(false);
 
         let current_row = canvas.cursor().row;
         let frame_rows = canvas.frame_size().rows;
-        let frame_half_rows = frame_rows / 2;
-        canvas.scroll(
-            current_row
-                .saturating_sub(frame_half_rows)
-                .min(frame_half_rows),
+        if state.settings.scrolloff == 0 {
+            let frame_half_rows = frame_rows / 2;
+            canvas.scroll(
+                current_row
+                    .saturating_sub(frame_half_rows)
+                    .min(frame_half_rows),
+            );
+            return;
+        }
+
+        // The auto-scrolled canvas still holds the last `frame_rows` rows up to the cursor,
+        // which always covers the new offset, so scrolling forward is enough here.
+        self.row_offset = scroll_offset(
+            self.row_offset,
+            current_row,
+            frame_rows,
+            state.settings.scrolloff,
         );
+        canvas.scroll(self.row_offset.saturating_sub(canvas.row_offset()));
     }
 
+    // Returns whether some of the hit texts are missing in the line.
     fn highlight_line(
         &self,
         state: &AppState,
         canvas: &mut Canvas,
         file: &PathBuf,
         line: &Line,
-        mut col_offset: usize,
-    ) {
-        let hit_texts = state.search_result.hit_texts_in_line(file, line.number);
-        let mut line_text = &line.text[..];
-        for hit_text in hit_texts {
-            let Some(i) = line_text.find(hit_text) else {
-                // Normally, this branch should not be executed.
-                // (Possibly, the file was edited during the git grep call.)
-                continue;
+        col_offset: usize,
+    ) -> bool {
+        let active_match = state
+            .cursor
+            .match_index
+            .filter(|_| state.cursor.is_line_focused(file, line.number));
+        let ranges = state.search_result.hit_ranges_in_line(file, line);
+        let mismatched = ranges.len()
+            < state
+                .search_result
+                .hit_texts_in_line(file, line.number)
+                .len();
+        for (i, range) in ranges.into_iter().enumerate() {
+            let style = if active_match == Some(i) {
+                TerminalStyle::new().reverse().underline()
+            } else {
+                TerminalStyle::new().reverse()
             };
+            let show_whitespace = state.settings.show_whitespace;
+            let control_chars = state.settings.control_chars;
+            let prefix = visible_text(&line.text, 0..range.start, show_whitespace, control_chars);
+            canvas.set_cursor_col(col_offset + mame::terminal::str_cols(&prefix));
+            canvas.draw(Token::with_style(
+                visible_text(&line.text, range, show_whitespace, control_chars),
+                style,
+            ));
+        }
 
-            col_offset += mame::terminal::str_cols(&line_text[..i]);
-            canvas.set_cursor_col(col_offset);
-            canvas.draw(Token::with_style(hit_text, TerminalStyle::new().reverse()));
-
-            col_offset += mame::terminal::str_cols(hit_text);
-            line_text = &line_text[i + hit_text.len()..];
+        // Per-term colors are drawn over the uniform highlighting above.
+        let active_range = active_match.and_then(|i| {
+            state
+                .search_result
+                .hit_ranges_in_line(file, line)
+                .get(i)
+                .cloned()
+        });
+        let colors = &state.settings.term_colors;
+        for term_index in 0..state.search_result.highlight.terms.len() {
+            let Some(color) = colors.get(term_index % colors.len().max(1)) else {
+                break;
+            };
+            for range in state
+                .search_result
+                .term_hit_ranges_in_line(term_index, file, line)
+            {
+                let mut style = TerminalStyle::new().reverse().fg_color(*color);
+                if active_range
+                    .as_ref()
+                    .is_some_and(|r| r.start <= range.start && range.end <= r.end)
+                {
+                    style = style.underline();
+                }
+                let show_whitespace = state.settings.show_whitespace;
+                let control_chars = state.settings.control_chars;
+                let prefix =
+                    visible_text(&line.text, 0..range.start, show_whitespace, control_chars);
+                canvas.set_cursor_col(col_offset + mame::terminal::str_cols(&prefix));
+                canvas.draw(Token::with_style(
+                    visible_text(&line.text, range, show_whitespace, control_chars),
+                    style,
+                ));
+            }
         }
+        mismatched
     }
 
     fn render_before_lines(
         &self,
         state: &AppState,
         canvas: &mut Canvas,
+        file: &PathBuf,
         lines: &[Line],
         current_line: &Line,
     ) {
@@ -178,20 +796,14 @@ impl SearchResultWidget {
             .binary_search_by_key(&current_line.number, |l| l.number)
             .expect("infallible");
         let start = end.saturating_sub(state.grep.context_lines.0);
-        for line in &lines[start..end] {
-            canvas.drawln(Token::new(format!(
-                "      {:>width$}| {}",
-                "",
-                line.text,
-                width = state.search_result.max_line_width,
-            )));
-        }
+        self.render_context_lines(state, canvas, file, &lines[start..end]);
     }
 
     fn render_after_lines(
         &self,
         state: &AppState,
         canvas: &mut Canvas,
+        file: &PathBuf,
         lines: &[Line],
         current_line: &Line,
     ) {
@@ -204,30 +816,133 @@ impl SearchResultWidget {
             .expect("infallible")
             + 1;
         let end = (start + state.grep.context_lines.0).min(lines.len());
-        for line in &lines[start..end] {
-            canvas.drawln(Token::new(format!(
-                "      {:>width$}| {}",
-                "",
-                line.text,
-                width = state.search_result.max_line_width,
-            )));
-        }
+        self.render_context_lines(state, canvas, file, &lines[start..end]);
         canvas.newline();
     }
 
+    fn render_context_lines(
+        &self,
+        state: &AppState,
+        canvas: &mut Canvas,
+        file: &PathBuf,
+        lines: &[Line],
+    ) {
+        let is_blank = |line: &Line| !line.hit && line.text.trim().is_empty();
+        for (i, line) in lines.iter().enumerate() {
+            let mut text = visible_text(
+                &line.text,
+                0..line.text.len(),
+                state.settings.show_whitespace,
+                state.settings.control_chars,
+            );
+            if state.settings.fold_blank_context && is_blank(line) {
+                let prev_blank = i > 0 && is_blank(&lines[i - 1]);
+                let next_blank = lines.get(i + 1).is_some_and(is_blank);
+                if prev_blank {
+                    continue;
+                } else if next_blank {
+                    text = Cow::Borrowed("⋮");
+                }
+            }
+            canvas.drawln(Token::with_style(
+                format!(
+                    "{:>width$}| {}",
+                    "",
+                    text,
+                    // Aligned with the `[` and the number of the hit lines.
+                    width = state.settings.cursor_markers.line_cols()
+                        + 1
+                        + state
+                            .search_result
+                            .line_number_width(state.settings.line_number_width),
+                ),
+                line_style(state, file, line),
+            ));
+        }
+    }
+
     pub fn handle_action(&mut self, state: &mut AppState, action: Action) -> orfail::Result<()> {
         match action {
-            Action::CursorUp => state.cursor_up(),
-            Action::CursorDown => state.cursor_down(),
-            Action::CursorRight => state.cursor_right(),
-            Action::CursorLeft => state.cursor_left(),
+            Action::CursorUp => {
+                state.scroll_offset = None;
+                state.cursor_up();
+            }
+            Action::CursorDown => {
+                state.scroll_offset = None;
+                state.cursor_down();
+            }
+            Action::CursorUpBy(n) => {
+                state.scroll_offset = None;
+                state.cursor_up_by(n);
+            }
+            Action::CursorDownBy(n) => {
+                state.scroll_offset = None;
+                state.cursor_down_by(n);
+            }
+            Action::CursorRight => {
+                state.scroll_offset = None;
+                state.cursor_right();
+            }
+            Action::CursorLeft => {
+                state.scroll_offset = None;
+                state.cursor_left();
+            }
+            Action::ScrollLineUp => self.scroll(state, -1),
+            Action::ScrollLineDown => self.scroll(state, 1),
+            Action::ScrollPageUp => self.scroll(state, -self.page_rows()),
+            Action::ScrollPageDown => self.scroll(state, self.page_rows()),
+            Action::Recenter => state.scroll_offset = None,
             Action::SetFocus(focus) => state.set_focus(focus),
             Action::ToggleExpansion => state.toggle_expansion(),
             Action::ToggleAllExpansion => state.toggle_all_expansion(),
+            Action::StartSelection => state.start_selection(),
+            Action::ToggleArgQuoting => {
+                state.settings.arg_quoting = state.settings.arg_quoting.next();
+            }
+            Action::ToggleFoldBlankContext => {
+                state.settings.fold_blank_context = !state.settings.fold_blank_context;
+            }
+            Action::ToggleMergeContext => {
+                state.settings.merge_context = !state.settings.merge_context;
+            }
+            Action::ToggleStickyHeading => {
+                state.settings.sticky_file_heading = !state.settings.sticky_file_heading;
+            }
+            Action::Refresh => state.refresh().or_fail()?,
+            Action::ToggleBasenameOnly => {
+                state.settings.basename_only = !state.settings.basename_only;
+            }
+            Action::ToggleContextOnlyFiles => state.toggle_context_only_files(),
+            Action::ToggleDirectorySummary => {
+                state.scroll_offset = None;
+                state.toggle_directory_summary();
+            }
+            Action::JumpToHottestFile => {
+                state.scroll_offset = None;
+                state.jump_to_hottest_file();
+            }
+            Action::DenseMode => {
+                state.dense_mode = !state.dense_mode;
+            }
+            Action::ToggleMinimap => {
+                state.show_minimap = !state.show_minimap;
+            }
+            Action::ToggleHeadingFormat => {
+                state.inline_format = !state.inline_format;
+            }
+            Action::ToggleFileBreaks => {
+                state.settings.file_breaks = !state.settings.file_breaks;
+            }
+            Action::ToggleShowWhitespace => {
+                state.settings.show_whitespace = !state.settings.show_whitespace;
+            }
+            Action::NextMatch => state.cursor_next_match(),
+            Action::PrevMatch => state.cursor_prev_match(),
             Action::FlipIgnoreCase => state.flip_grep_flag(|f| &mut f.ignore_case).or_fail()?,
             Action::FlipUntracked => state.flip_grep_flag(|f| &mut f.untracked).or_fail()?,
             Action::FlipNoIndex => state.flip_grep_flag(|f| &mut f.no_index).or_fail()?,
             Action::FlipNoRecursive => state.flip_grep_flag(|f| &mut f.no_recursive).or_fail()?,
+            Action::FlipTextconv => state.flip_grep_flag(|f| &mut f.textconv).or_fail()?,
             Action::FlipExtendedRegexp if !(state.grep.fixed_strings || state.grep.perl_regexp) => {
                 state.flip_grep_flag(|f| &mut f.extended_regexp).or_fail()?;
             }
@@ -237,17 +952,58 @@ impl SearchResultWidget {
             Action::FlipPerlRegexp if !(state.grep.fixed_strings || state.grep.extended_regexp) => {
                 state.flip_grep_flag(|f| &mut f.perl_regexp).or_fail()?;
             }
-            Action::IncreaseContext if state.cursor.is_line_level() => {
-                if state.grep.context_lines < ContextLines::MAX {
-                    state.grep.context_lines.0 += 1;
-                    state.regrep().or_fail()?;
-                }
+            Action::IncreaseContext if state.can_increase_context() => {
+                state.grep.context_lines.0 += 1;
+                state.regrep().or_fail()?;
             }
-            Action::DecreaseContext if state.cursor.is_line_level() => {
-                if state.grep.context_lines > ContextLines::MIN {
-                    state.grep.context_lines.0 -= 1;
-                    state.regrep().or_fail()?;
-                }
+            Action::DecreaseContext if state.can_decrease_context() => {
+                state.grep.context_lines.0 -= 1;
+                state.regrep().or_fail()?;
+            }
+            Action::IncreaseMinMatchLen => {
+                state.set_min_match_len(state.min_match_len + 1);
+            }
+            Action::DecreaseMinMatchLen if state.min_match_len > 0 => {
+                state.set_min_match_len(state.min_match_len - 1);
+            }
+            Action::ResetFlags => state.reset_flags().or_fail()?,
+            Action::ToggleSortOrder => {
+                state.settings.sort_order = state.settings.sort_order.next();
+                state.apply_sort_order();
+            }
+            Action::SwapPrevious => {
+                state.swap_previous().or_fail()?;
+            }
+            Action::ToggleFlagStatus => {
+                state.settings.show_flag_status = !state.settings.show_flag_status;
+            }
+            Action::ToggleExcludeTests => {
+                state
+                    .grep
+                    .toggle_exclusions(&state.settings.test_exclude_globs);
+                state.regrep().or_fail()?;
+            }
+            Action::ClearExtensionFilter => {
+                state.grep.set_extensions("");
+                state.regrep().or_fail()?;
+            }
+            Action::ClearVisited => {
+                state.visited.clear();
+            }
+            Action::UseEngine(engine) => {
+                state.grep.set_engine(engine);
+                state.regrep().or_fail()?;
+            }
+            Action::ToggleAddedLinesOnly => {
+                state
+                    .flip_grep_flag(|f| &mut f.added_lines_only)
+                    .or_fail()?;
+            }
+            Action::ToggleFilenameSearch => {
+                state.flip_grep_flag(|f| &mut f.filename_search).or_fail()?;
+            }
+            Action::ToggleTermHighlight => {
+                state.flip_grep_flag(|f| &mut f.highlight_terms).or_fail()?;
             }
             Action::FlipWholeWord => {
                 state.flip_grep_flag(|f| &mut f.word_regexp).or_fail()?;
@@ -258,10 +1014,204 @@ impl SearchResultWidget {
     }
 }
 
-#[derive(Debug, Default, Clone)]
+fn line_style(state: &AppState, file: &PathBuf, line: &Line) -> TerminalStyle {
+    if state.is_line_selected(file, line.number) {
+        TerminalStyle::new().bold()
+    } else if state.settings.dim_visited && state.visited.contains(&(file.clone(), line.number)) {
+        TerminalStyle::new().dim()
+    } else {
+        TerminalStyle::new()
+    }
+}
+
+// Compact indicators of the enabled flags (e.g., `[i][w][E]`).
+fn flag_status(state: &AppState) -> String {
+    if !state.settings.show_flag_status {
+        return String::new();
+    }
+    state
+        .grep
+        .enabled_flags()
+        .filter_map(|name| state.settings.flag_status_letters.get(name))
+        .map(|letter| format!("[{letter}]"))
+        .collect()
+}
+
+fn file_heading_tokens(
+    state: &AppState,
+    file_index: usize,
+    file: &PathBuf,
+    name: &str,
+) -> [Token; 4] {
+    let index = if state.settings.show_file_indices {
+        format!("{}# ", file_index + 1)
+    } else {
+        String::new()
+    };
+    let status = state
+        .file_status(file)
+        .map(|status| format!(" {}", status.marker()))
+        .unwrap_or_default();
+    [
+        Token::new(index),
+        Token::with_style(name, TerminalStyle::new().underline())
+            .with_link(state.file_hyperlink(file)),
+        Token::new(format!(
+            " ({} hits, {} lines)",
+            state.search_result.hit_texts_in_file(file),
+            state.search_result.hit_lines_in_file(file)
+        )),
+        Token::new(status),
+    ]
+}
+
+const DIRECTORY_BAR_COLS: usize = 30;
+
+// A bar of `cols` columns at most, drawn in eighths of a column (a non-zero value is at least visible).
+fn bar(value: usize, max: usize, cols: usize) -> String {
+    const PARTIALS: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+    if max == 0 {
+        return String::new();
+    }
+    let eighths = (value * cols * 8 / max).max(usize::from(value > 0));
+    let mut bar = "█".repeat(eighths / 8);
+    if eighths % 8 > 0 {
+        bar.push(PARTIALS[eighths % 8]);
+    }
+    bar
+}
+
+// Counts the hits in each of the `rows` equal regions of the line number span.
+fn minimap_buckets(
+    hits: impl Iterator<Item = usize>,
+    span: RangeInclusive<usize>,
+    rows: usize,
+) -> Vec<usize> {
+    let mut buckets = vec![0; rows];
+    let len = span.end().saturating_sub(*span.start()) + 1;
+    for hit in hits.filter(|hit| span.contains(hit)) {
+        if let Some(bucket) = buckets.get_mut((hit - span.start()) * rows / len) {
+            *bucket += 1;
+        }
+    }
+    buckets
+}
+
+// A shade whose intensity is relative to the densest region (an empty region is blank).
+fn minimap_glyph(count: usize, max: usize) -> char {
+    const SHADES: [char; 4] = ['░', '▒', '▓', '█'];
+    if count == 0 || max == 0 {
+        return ' ';
+    }
+    SHADES[(count * SHADES.len()).div_ceil(max) - 1]
+}
+
+// Splits the context window into the runs of lines that are not rendered as hits.
+// A run is also split where the line numbers are not contiguous (i.e., at git's `--` separators).
+fn context_segments(
+    lines: &[Line],
+    window: Range<usize>,
+    is_shown: impl Fn(&Line) -> bool,
+) -> Vec<Range<usize>> {
+    let mut segments: Vec<Range<usize>> = Vec::new();
+    for i in window {
+        if is_shown(&lines[i]) {
+            continue;
+        }
+        match segments.last_mut() {
+            Some(last)
+                if last.end == i && lines[i - 1].number.get() + 1 == lines[i].number.get() =>
+            {
+                last.end += 1;
+            }
+            _ => segments.push(i..i + 1),
+        }
+    }
+    segments
+}
+
+// Returns `text[range]`, replacing the trailing whitespace of `text` with visible glyphs if requested.
+//
+// The same substitution is applied to every sub-range of a line,
+// so the widths of highlighted segments stay consistent with the rendered line.
+// The control chars are replaced here (rather than escaped by `Token`) so that the highlight columns match.
+fn visible_text(
+    text: &str,
+    range: Range<usize>,
+    show_whitespace: bool,
+    control_chars: ControlChars,
+) -> Cow<'_, str> {
+    match visible_whitespace(text, range, show_whitespace) {
+        Cow::Borrowed(text) => control_chars.apply(text),
+        Cow::Owned(text) => Cow::Owned(control_chars.apply(&text).into_owned()),
+    }
+}
+
+fn visible_whitespace(text: &str, range: Range<usize>, show_whitespace: bool) -> Cow<'_, str> {
+    let trailing_start = text.trim_end_matches([' ', '\t']).len();
+    if !show_whitespace || range.end <= trailing_start {
+        return Cow::Borrowed(&text[range]);
+    }
+    text[range.clone()]
+        .char_indices()
+        .map(|(i, c)| match c {
+            ' ' if range.start + i >= trailing_start => '·',
+            '\t' if range.start + i >= trailing_start => '→',
+            _ => c,
+        })
+        .collect()
+}
+
+// The shortest trailing components of each path that no other path ends with (e.g., the basename if unique).
+fn short_names<'a>(files: impl Iterator<Item = &'a PathBuf>) -> BTreeMap<&'a PathBuf, String> {
+    let files = files.collect::<Vec<_>>();
+    let suffix = |file: &Path, depth: usize| {
+        let components = file.components().collect::<Vec<_>>();
+        let start = components.len().saturating_sub(depth);
+        let suffix = components[start..].iter().collect::<PathBuf>();
+        (suffix.display().to_string(), start == 0)
+    };
+
+    let mut names = BTreeMap::new();
+    let mut pending = files.clone();
+    for depth in 1.. {
+        if pending.is_empty() {
+            break;
+        }
+        let mut counts = BTreeMap::<_, usize>::new();
+        for file in &files {
+            *counts.entry(suffix(file, depth).0).or_default() += 1;
+        }
+        pending.retain(|file| {
+            let (name, whole) = suffix(file, depth);
+            if whole || counts[&name] == 1 {
+                names.insert(*file, name);
+                false
+            } else {
+                true
+            }
+        });
+    }
+    names
+}
+
+fn scroll_offset(
+    old_offset: usize,
+    cursor_row: usize,
+    frame_rows: usize,
+    scrolloff: usize,
+) -> usize {
+    let scrolloff = scrolloff.min(frame_rows.saturating_sub(1) / 2);
+    let min_offset = (cursor_row + scrolloff + 1).saturating_sub(frame_rows);
+    let max_offset = cursor_row.saturating_sub(scrolloff);
+    old_offset.max(min_offset).min(max_offset)
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Cursor {
     pub file: Option<PathBuf>,
     pub line_number: Option<NonZeroUsize>,
+    pub match_index: Option<usize>,
 }
 
 impl Cursor {
@@ -273,12 +1223,17 @@ impl Cursor {
         self.line_number.is_some()
     }
 
-    pub fn render_for_file(&self, canvas: &mut Canvas, file: &PathBuf) -> bool {
+    pub fn render_for_file(
+        &self,
+        canvas: &mut Canvas,
+        file: &PathBuf,
+        markers: &CursorMarkers,
+    ) -> bool {
         if self.is_file_level() && self.file.as_ref() == Some(file) {
-            canvas.draw(Token::new("-> "));
+            canvas.draw(Token::new(markers.file()));
             true
         } else {
-            canvas.draw(Token::new("   "));
+            canvas.draw(Token::new(" ".repeat(markers.file_cols())));
             false
         }
     }
@@ -288,12 +1243,13 @@ impl Cursor {
         canvas: &mut Canvas,
         file: &PathBuf,
         line_number: NonZeroUsize,
+        markers: &CursorMarkers,
     ) -> bool {
         if self.is_line_focused(file, line_number) {
-            canvas.draw(Token::new("---> "));
+            canvas.draw(Token::new(markers.line()));
             true
         } else {
-            canvas.draw(Token::new("     "));
+            canvas.draw(Token::new(" ".repeat(markers.line_cols())));
             false
         }
     }
@@ -302,3 +1258,878 @@ impl Cursor {
         self.file.as_ref() == Some(file) && self.line_number == Some(line_number)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::{Highlight, SearchResult};
+
+    #[test]
+    fn two_term_highlighting() -> orfail::Result<()> {
+        use tuinix::TerminalColor;
+
+        let mut highlight = Highlight::parse("a.rs\n1:foo\n1:bar\n").or_fail()?;
+        highlight.terms = vec![
+            (
+                "foo".to_owned(),
+                Highlight::parse("a.rs\n1:foo\n").or_fail()?.lines,
+            ),
+            (
+                "bar".to_owned(),
+                Highlight::parse("a.rs\n1:bar\n").or_fail()?.lines,
+            ),
+        ];
+        let mut state = AppState {
+            search_result: SearchResult::parse("a.rs\n1:foo bar\n", highlight).or_fail()?,
+            ..Default::default()
+        };
+        state.grep.context_lines = ContextLines::MIN;
+        state.cursor.file = Some(PathBuf::from("a.rs"));
+        state.settings.term_colors = vec![TerminalColor::RED, TerminalColor::BLUE];
+
+        let mut canvas = Canvas::new(TerminalSize::rows_cols(10, 80));
+        SearchResultWidget::default().render(&state, &mut canvas);
+        let line = canvas.into_frame().into_lines().nth(2).or_fail()?;
+        let style_of = |text: &str| {
+            line.tokens()
+                .iter()
+                .find(|t| t.text() == text)
+                .map(|t| t.style())
+        };
+        assert_eq!(line.text(), "     [1] foo bar");
+        assert_eq!(
+            style_of("foo"),
+            Some(TerminalStyle::new().reverse().fg_color(TerminalColor::RED))
+        );
+        assert_eq!(
+            style_of("bar"),
+            Some(TerminalStyle::new().reverse().fg_color(TerminalColor::BLUE))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn control_chars_keep_highlight_columns() -> orfail::Result<()> {
+        let mut state = AppState {
+            search_result: SearchResult::parse(
+                "a.rs\n1:\tfoo\n",
+                Highlight::parse("a.rs\n1:foo\n").or_fail()?,
+            )
+            .or_fail()?,
+            ..Default::default()
+        };
+        state.grep.context_lines = ContextLines::MIN;
+        state.cursor.file = Some(PathBuf::from("a.rs"));
+
+        for (policy, expected) in [
+            (ControlChars::Escape, "     [1] \\tfoo"),
+            (ControlChars::Caret, "     [1] ^Ifoo"),
+            (ControlChars::Placeholder, "     [1] \u{FFFD}foo"),
+        ] {
+            state.settings.control_chars = policy;
+            let mut canvas = Canvas::new(TerminalSize::rows_cols(10, 80));
+            SearchResultWidget::default().render(&state, &mut canvas);
+            let line = canvas.into_frame().into_lines().nth(2).or_fail()?;
+            assert_eq!(line.text(), expected);
+            let highlighted = line
+                .tokens()
+                .iter()
+                .find(|t| t.style() == TerminalStyle::new().reverse())
+                .map(|t| t.text());
+            assert_eq!(highlighted, Some("foo"), "{policy:?}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn short_names_are_disambiguated() {
+        let files = [
+            "src/a/mod.rs",
+            "src/b/mod.rs",
+            "x/src/b/mod.rs",
+            "src/main.rs",
+            "mod.rs",
+        ]
+        .map(PathBuf::from);
+        let names = short_names(files.iter());
+        let names = files.iter().map(|f| names[f].as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "a/mod.rs",
+                "src/b/mod.rs",
+                "x/src/b/mod.rs",
+                "main.rs",
+                "mod.rs"
+            ]
+        );
+    }
+
+    #[test]
+    fn highlight_mismatch_marker() -> orfail::Result<()> {
+        // The line was edited between the two `git grep` calls.
+        let state = AppState {
+            search_result: SearchResult::parse(
+                "a.rs\n1:foo\n2:foo bar\n",
+                Highlight::parse("a.rs\n1:foo\n2:foo\n2:baz\n").or_fail()?,
+            )
+            .or_fail()?,
+            ..Default::default()
+        };
+
+        let mut canvas = Canvas::new(TerminalSize::rows_cols(10, 80));
+        SearchResultWidget::default().render(&state, &mut canvas);
+        let lines = canvas
+            .into_frame()
+            .into_lines()
+            .map(|l| l.text())
+            .collect::<Vec<_>>();
+        assert!(lines[0].ends_with("(1 lines with mismatched highlights)"));
+        assert_eq!(lines[2], "     [1] foo");
+        assert_eq!(lines[3], "     [2]~foo bar");
+
+        Ok(())
+    }
+
+    #[test]
+    fn stale_indicator() {
+        let header = |state: &AppState| {
+            let mut canvas = Canvas::new(TerminalSize::rows_cols(1, 100));
+            SearchResultWidget::default().render_header_line(state, &mut canvas);
+            canvas.into_frame().into_lines().next().map(|l| l.text())
+        };
+        let mut state = AppState::default();
+        state.settings.show_flag_status = false;
+        assert!(header(&state).is_some_and(|h| !h.contains("stale")));
+
+        state.stale = true;
+        assert!(header(&state).is_some_and(|h| h.ends_with("(stale: refresh to update)")));
+
+        state.regrep().expect("empty query");
+        assert!(!state.stale);
+    }
+
+    #[test]
+    fn dense_mode() -> orfail::Result<()> {
+        let mut state = AppState {
+            search_result: SearchResult::parse(
+                "a.rs\n1-a\n2:hit\n3-b\n4:hit\n5-c\n",
+                Highlight::default(),
+            )
+            .or_fail()?,
+            ..Default::default()
+        };
+        state.grep.context_lines = ContextLines(1);
+        state.cursor.file = Some(PathBuf::from("a.rs"));
+        state.cursor.line_number = NonZeroUsize::new(2);
+
+        let render = |state: &AppState| {
+            let mut canvas = Canvas::new(TerminalSize::rows_cols(10, 80));
+            SearchResultWidget::default().render(state, &mut canvas);
+            canvas
+                .into_frame()
+                .into_lines()
+                .map(|l| l.text())
+                .filter(|l| !l.is_empty())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(render(&state).len(), 6);
+
+        state.dense_mode = true;
+        let lines = render(&state);
+        assert_eq!(
+            lines,
+            [
+                "[RESULT]: 0 hits, 0 lines, 1 files (dense)",
+                "   1# a.rs (0 hits, 2 lines)",
+                "---> [2] hit",
+                "     [4] hit",
+            ]
+        );
+        assert_eq!(state.grep.context_lines, ContextLines(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn inline_format() -> orfail::Result<()> {
+        let mut state = AppState {
+            search_result: SearchResult::parse(
+                "a.rs\n1:foo\n2-x\n3:foo\nb.rs\n5:foo\n",
+                Highlight::parse("a.rs\n1:foo\n3:foo\nb.rs\n5:foo\n").or_fail()?,
+            )
+            .or_fail()?,
+            inline_format: true,
+            ..Default::default()
+        };
+        state.grep.context_lines = ContextLines(1);
+        state.cursor.file = Some(PathBuf::from("a.rs"));
+
+        let render = |state: &AppState| {
+            let mut canvas = Canvas::new(TerminalSize::rows_cols(10, 80));
+            SearchResultWidget::default().render(state, &mut canvas);
+            canvas
+                .into_frame()
+                .into_lines()
+                .map(|l| l.text())
+                .filter(|l| !l.is_empty())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            render(&state),
+            [
+                "[RESULT]: 3 hits, 3 lines, 2 files",
+                "->   a.rs:1: foo",
+                "     a.rs:3: foo",
+                "     b.rs:5: foo",
+            ]
+        );
+
+        // The context lines of the focused line are also prefixed with the path.
+        state.cursor.line_number = NonZeroUsize::new(3);
+        state.collapsed.insert(PathBuf::from("b.rs"));
+        assert_eq!(
+            render(&state),
+            [
+                "[RESULT]: 3 hits, 3 lines, 2 files",
+                "     a.rs:1: foo",
+                "     a.rs-2- x",
+                "---> a.rs:3: foo",
+                "     b.rs …",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn full_file_expansion() -> orfail::Result<()> {
+        let mut state = AppState {
+            search_result: SearchResult::parse(
+                "a.rs
+2:hit
+4:hit
+",
+                Highlight::default(),
+            )
+            .or_fail()?,
+            ..Default::default()
+        };
+        state.grep.context_lines = ContextLines::MIN;
+        state.cursor.file = Some(PathBuf::from("a.rs"));
+        state.cursor.line_number = NonZeroUsize::new(4);
+        assert!(state.expand_file(PathBuf::from("a.rs"), "a\nhit\nb\nhit\nc\n"));
+
+        let mut canvas = Canvas::new(TerminalSize::rows_cols(10, 80));
+        SearchResultWidget::default().render(&state, &mut canvas);
+        let lines = canvas
+            .into_frame()
+            .into_lines()
+            .map(|l| l.text())
+            .filter(|l| !l.is_empty())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "[RESULT]: 0 hits, 0 lines, 1 files",
+                "   1# a.rs (0 hits, 2 lines)",
+                "     [1] a",
+                "     [2] hit",
+                "     [3] b",
+                "---> [4] hit",
+                "     [5] c",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn manual_scroll() -> orfail::Result<()> {
+        let output = (1..=20).map(|i| format!("{i}:x\n")).collect::<String>();
+        let mut state = AppState {
+            search_result: SearchResult::parse(&format!("a.rs\n{output}"), Highlight::default())
+                .or_fail()?,
+            ..Default::default()
+        };
+        state.grep.context_lines = ContextLines::MIN;
+        state.cursor.file = Some(PathBuf::from("a.rs"));
+        state.cursor.line_number = NonZeroUsize::new(1);
+
+        let mut widget = SearchResultWidget::default();
+        let mut render = |state: &AppState| {
+            let mut canvas = Canvas::new(TerminalSize::rows_cols(6, 80));
+            widget.render(state, &mut canvas);
+            let lines = canvas
+                .into_frame()
+                .into_lines()
+                .map(|l| l.text())
+                .collect::<Vec<_>>();
+            (lines[0].clone(), lines[1].trim().to_owned())
+        };
+        assert_eq!(render(&state).1, "1# a.rs (0 hits, 20 lines)");
+
+        // The view stays at the offset while the cursor is out of it.
+        state.scroll_offset = Some(10);
+        let (header, first) = render(&state);
+        assert!(header.ends_with("(scrolled)"));
+        assert_eq!(first, "[10] x");
+
+        // Beyond the end, the last page is shown.
+        state.scroll_offset = Some(100);
+        assert_eq!(render(&state).1, "[16] x");
+
+        Ok(())
+    }
+
+    #[test]
+    fn flag_status_in_header() {
+        let mut state = AppState::default();
+        state.grep.ignore_case = true;
+        state.grep.word_regexp = true;
+        state.grep.extended_regexp = true;
+        state
+            .settings
+            .flag_status_letters
+            .insert("whole-word".to_owned(), "W".to_owned());
+
+        let header = |state: &AppState, cols| {
+            let mut canvas = Canvas::new(TerminalSize::rows_cols(1, cols));
+            SearchResultWidget::default().render_header_line(state, &mut canvas);
+            canvas.into_frame().into_lines().next().map(|l| l.text())
+        };
+        assert_eq!(
+            header(&state, 80).as_deref(),
+            Some("[RESULT]: 0 hits, 0 lines, 0 files [i][W][E]")
+        );
+
+        // The status is omitted if it does not fit.
+        assert_eq!(
+            header(&state, 40).as_deref(),
+            Some("[RESULT]: 0 hits, 0 lines, 0 files")
+        );
+
+        state.settings.show_flag_status = false;
+        assert_eq!(
+            header(&state, 80).as_deref(),
+            Some("[RESULT]: 0 hits, 0 lines, 0 files")
+        );
+    }
+
+    #[test]
+    fn min_match_len_in_header() -> orfail::Result<()> {
+        let output = "a.rs\n1:x\n2:xyz\nb.rs\n1:z\n";
+        let mut state = AppState {
+            search_result: SearchResult::parse(output, Highlight::parse(output).or_fail()?)
+                .or_fail()?,
+            ..Default::default()
+        };
+        state.settings.show_flag_status = false;
+
+        let header = |state: &AppState| {
+            let mut canvas = Canvas::new(TerminalSize::rows_cols(1, 100));
+            SearchResultWidget::default().render_header_line(state, &mut canvas);
+            canvas.into_frame().into_lines().next().map(|l| l.text())
+        };
+        assert_eq!(
+            header(&state).as_deref(),
+            Some("[RESULT]: 3 hits, 3 lines, 2 files")
+        );
+
+        state.set_min_match_len(2);
+        assert_eq!(
+            header(&state).as_deref(),
+            Some("[RESULT]: 1/3 hits, 1/3 lines, 1/2 files (min match length: 2)")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn fold_blank_context_lines() -> orfail::Result<()> {
+        let output = "a.rs\n1-\n2-x\n3-\n4-  \n5-\n6:hit\n7-\n8-y\n";
+        let mut state = AppState {
+            search_result: SearchResult::parse(output, Highlight::default()).or_fail()?,
+            ..Default::default()
+        };
+        state.grep.context_lines = ContextLines(5);
+        state.cursor.file = Some(PathBuf::from("a.rs"));
+        state.cursor.line_number = Some(NonZeroUsize::MIN.saturating_add(5));
+
+        let render = |state: &AppState| -> Vec<String> {
+            let mut canvas = Canvas::new(TerminalSize::rows_cols(20, 80));
+            SearchResultWidget::default().render(state, &mut canvas);
+            canvas
+                .into_frame()
+                .into_lines()
+                .map(|l| l.text())
+                .skip(3)
+                .take_while(|l| !l.is_empty())
+                .collect()
+        };
+
+        assert_eq!(render(&state).len(), 8);
+
+        // A run of blank lines is folded into a single marker while a lone blank line is kept.
+        state.settings.fold_blank_context = true;
+        assert_eq!(
+            render(&state),
+            [
+                "       | ",
+                "       | x",
+                "       | ⋮",
+                "---> [6] hit",
+                "       | ",
+                "       | y",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn visible_text_keeps_highlight_columns() {
+        let text = "foo \t ";
+        assert_eq!(visible_whitespace(text, 0..text.len(), false), text);
+        assert_eq!(visible_whitespace(text, 0..text.len(), true), "foo·→·");
+
+        // Inner whitespace is left as is.
+        assert_eq!(visible_whitespace("a b ", 0..4, true), "a b·");
+
+        // A highlighted segment covering the trailing whitespace starts right after
+        // the rendered prefix and has the same width as in the rendered line.
+        let line_cols = mame::terminal::str_cols(&visible_whitespace(text, 0..text.len(), true));
+        let prefix_cols = mame::terminal::str_cols(&visible_whitespace(text, 0..2, true));
+        let hit_cols = mame::terminal::str_cols(&visible_whitespace(text, 2..text.len(), true));
+        assert_eq!(visible_whitespace(text, 2..text.len(), true), "o·→·");
+        assert_eq!(prefix_cols + hit_cols, line_cols);
+        assert_eq!(line_cols, 6);
+    }
+
+    #[test]
+    fn scroll_offset_keeps_cursor_away_from_edges() {
+        // The view does not move while the cursor stays inside the margins.
+        assert_eq!(scroll_offset(10, 20, 20, 3), 10);
+        assert_eq!(scroll_offset(10, 13, 20, 3), 10);
+        assert_eq!(scroll_offset(10, 26, 20, 3), 10);
+
+        // Approaching the top or bottom edge scrolls the view.
+        assert_eq!(scroll_offset(10, 12, 20, 3), 9);
+        assert_eq!(scroll_offset(10, 27, 20, 3), 11);
+    }
+
+    #[test]
+    fn scroll_offset_at_result_boundaries() {
+        // Top of the result set.
+        assert_eq!(scroll_offset(5, 0, 20, 3), 0);
+        assert_eq!(scroll_offset(5, 2, 20, 3), 0);
+
+        // Bottom of the result set (the last row is 99).
+        assert_eq!(scroll_offset(70, 99, 20, 3), 83);
+
+        // Too large scrolloff values are limited to half of the frame.
+        assert_eq!(scroll_offset(0, 50, 20, 100), 40);
+
+        // Empty frame.
+        assert_eq!(scroll_offset(3, 5, 0, 3), 5);
+    }
+
+    #[test]
+    fn merged_context_segments() {
+        let lines = [
+            (1, false),
+            (2, true),
+            (3, false),
+            (4, false),
+            (5, true),
+            (9, false),
+        ]
+        .map(|(n, hit)| Line {
+            number: NonZeroUsize::new(n).expect("non zero"),
+            text: String::new(),
+            hit,
+        });
+        let is_shown = |l: &Line| l.hit;
+
+        assert_eq!(context_segments(&lines, 0..6, is_shown), [0..1, 2..4, 5..6]);
+        assert_eq!(context_segments(&lines, 1..4, is_shown), vec![2..4]);
+        assert_eq!(context_segments(&lines, 1..2, is_shown), []);
+
+        // Hidden hits are rendered as context.
+        assert_eq!(context_segments(&lines, 2..5, |_| false), vec![2..5]);
+
+        // The non-contiguous lines (`--` in git's output) are kept in separate segments.
+        assert_eq!(context_segments(&lines, 3..6, |_| false), [3..5, 5..6]);
+    }
+
+    #[test]
+    fn merged_context_rendering() -> orfail::Result<()> {
+        let mut state = AppState {
+            search_result: SearchResult::parse(
+                "a.rs\n1-x\n2:hit\n3:hit\n4-y\n5-z\n",
+                Highlight::default(),
+            )
+            .or_fail()?,
+            ..Default::default()
+        };
+        state.grep.context_lines = ContextLines(2);
+        state.cursor.file = Some(PathBuf::from("a.rs"));
+        state.cursor.line_number = NonZeroUsize::new(3);
+
+        let render = |state: &AppState| {
+            let mut canvas = Canvas::new(TerminalSize::rows_cols(20, 80));
+            SearchResultWidget::default().render(state, &mut canvas);
+            canvas
+                .into_frame()
+                .into_lines()
+                .map(|l| l.text())
+                .skip(2)
+                .collect::<Vec<_>>()
+        };
+
+        // The adjacent hit within the context is not duplicated.
+        assert_eq!(
+            &render(&state)[..7],
+            [
+                "",
+                "       | x",
+                "     [2] hit",
+                "---> [3] hit",
+                "       | y",
+                "       | z",
+                "",
+            ]
+        );
+
+        state.settings.merge_context = false;
+        assert_eq!(
+            &render(&state)[..8],
+            [
+                "     [2] hit",
+                "",
+                "       | x",
+                "       | hit",
+                "---> [3] hit",
+                "       | y",
+                "       | z",
+                "",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn directory_summary() -> orfail::Result<()> {
+        let mut state = AppState {
+            search_result: SearchResult::parse(
+                "a.rs\n1:x\nsrc/b.rs\n1:x\n2:x\nsrc/c.rs\n1:x\n",
+                Highlight::parse("a.rs\n1:x\nsrc/b.rs\n1:x\n2:x\nsrc/c.rs\n1:x\n").or_fail()?,
+            )
+            .or_fail()?,
+            ..Default::default()
+        };
+        state.cursor.file = Some(PathBuf::from("src/c.rs"));
+        state.toggle_directory_summary();
+        assert_eq!(state.directory_summary, Some(0));
+
+        let mut canvas = Canvas::new(TerminalSize::rows_cols(10, 80));
+        SearchResultWidget::default().render(&state, &mut canvas);
+        let lines = canvas
+            .into_frame()
+            .into_lines()
+            .map(|l| l.text())
+            .filter(|l| !l.is_empty())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "[RESULT]: 4 hits, 4 lines, 3 files (by directory)",
+                "-> src/ 3 ██████████████████████████████",
+                "   ./   1 ██████████",
+            ]
+        );
+
+        // Drilling into a directory focuses its first file.
+        state.cursor_down();
+        assert!(!state.can_cursor_down());
+        state.cursor_right();
+        assert_eq!(state.directory_summary, None);
+        assert_eq!(state.cursor.file, Some(PathBuf::from("a.rs")));
+
+        assert_eq!(bar(1, 3, 1), "▎");
+        assert_eq!(bar(1, 1000, 1), "▏");
+        assert_eq!(bar(0, 3, 1), "");
+        Ok(())
+    }
+
+    #[test]
+    fn sticky_file_heading() -> orfail::Result<()> {
+        let mut state = AppState {
+            search_result: SearchResult::parse(
+                "a.rs\n1:a\n2:a\n3:a\n4:a\n5:a\nb.rs\n6:b\n7:b\n",
+                Highlight::default(),
+            )
+            .or_fail()?,
+            ..Default::default()
+        };
+        state.grep.context_lines = ContextLines::MIN;
+        state.settings.sticky_file_heading = true;
+
+        let render = |state: &AppState| {
+            let mut canvas = Canvas::new(TerminalSize::rows_cols(4, 40));
+            SearchResultWidget::default().render(state, &mut canvas);
+            canvas
+                .into_frame()
+                .into_lines()
+                .skip(1)
+                .map(|l| l.text().trim_end().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        state.scroll_offset = Some(2);
+        assert_eq!(
+            render(&state),
+            ["   1# a.rs (0 hits, 5 lines)", "     [3] a", "     [4] a"]
+        );
+
+        // The heading stays while the last line of the file is at the top.
+        state.scroll_offset = Some(5);
+        assert_eq!(
+            render(&state),
+            [
+                "   1# a.rs (0 hits, 5 lines)",
+                "   2# b.rs (0 hits, 2 lines)",
+                "     [6] b"
+            ]
+        );
+
+        // No sticky heading when the heading itself is at the top.
+        state.scroll_offset = Some(6);
+        assert_eq!(render(&state)[0], "   2# b.rs (0 hits, 2 lines)");
+
+        state.settings.sticky_file_heading = false;
+        state.scroll_offset = Some(2);
+        assert_eq!(render(&state)[0], "     [2] a");
+        Ok(())
+    }
+
+    #[test]
+    fn file_breaks_keep_cursor_visible() -> orfail::Result<()> {
+        let mut state = AppState {
+            search_result: SearchResult::parse(
+                "a.rs\n1:a\n2:a\nb.rs\n3:b\n4:b\nc.rs\n5:c\n6:c\nd.rs\n7:d\n8:d\n",
+                Highlight::default(),
+            )
+            .or_fail()?,
+            ..Default::default()
+        };
+        state.grep.context_lines = ContextLines::MIN;
+        state.settings.file_breaks = true;
+        state.cursor.file = Some(PathBuf::from("d.rs"));
+        state.cursor.line_number = NonZeroUsize::new(8);
+
+        let render = |rows| {
+            let mut canvas = Canvas::new(TerminalSize::rows_cols(rows, 40));
+            SearchResultWidget::default().render(&state, &mut canvas);
+            let mut lines = canvas
+                .into_frame()
+                .into_lines()
+                .skip(1)
+                .map(|l| l.text().trim_end().to_owned())
+                .collect::<Vec<_>>();
+            while lines.last().is_some_and(|l| l.is_empty()) {
+                lines.pop();
+            }
+            lines
+        };
+        assert_eq!(
+            render(40),
+            [
+                "   1# a.rs (0 hits, 2 lines)",
+                "     [1] a",
+                "     [2] a",
+                "",
+                "   2# b.rs (0 hits, 2 lines)",
+                "     [3] b",
+                "     [4] b",
+                "",
+                "   3# c.rs (0 hits, 2 lines)",
+                "     [5] c",
+                "     [6] c",
+                "",
+                "   4# d.rs (0 hits, 2 lines)",
+                "     [7] d",
+                "---> [8] d",
+            ]
+        );
+
+        // In short frames, the focused line stays visible with the blank rows counted.
+        assert_eq!(
+            render(6),
+            ["   4# d.rs (0 hits, 2 lines)", "     [7] d", "---> [8] d"]
+        );
+        assert_eq!(
+            render(9),
+            [
+                "",
+                "   4# d.rs (0 hits, 2 lines)",
+                "     [7] d",
+                "---> [8] d"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn heading_format_settings() -> orfail::Result<()> {
+        let mut state = AppState {
+            search_result: SearchResult::parse("a.rs\n1:a\nb.rs\n2:b\n", Highlight::default())
+                .or_fail()?,
+            ..Default::default()
+        };
+        state.grep.context_lines = ContextLines::MIN;
+        state.collapsed.insert(PathBuf::from("a.rs"));
+
+        let render = |state: &AppState| {
+            let mut canvas = Canvas::new(TerminalSize::rows_cols(4, 40));
+            SearchResultWidget::default().render(state, &mut canvas);
+            canvas
+                .into_frame()
+                .into_lines()
+                .skip(1)
+                .map(|l| l.text().trim_end().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            render(&state),
+            [
+                "   1# a.rs (0 hits, 1 lines)…",
+                "   2# b.rs (0 hits, 1 lines)",
+                "     [2] b"
+            ]
+        );
+
+        // The lines stay indented under the headings without the indices.
+        state.settings.collapsed_indicator = " [+]".to_owned();
+        state.settings.show_file_indices = false;
+        assert_eq!(
+            render(&state),
+            [
+                "   a.rs (0 hits, 1 lines) [+]",
+                "   b.rs (0 hits, 1 lines)",
+                "     [2] b"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cached_rendering() -> orfail::Result<()> {
+        let mut state = AppState {
+            search_result: SearchResult::parse("a.rs\n1:a\n2:a\nb.rs\n3:b\n", Highlight::default())
+                .or_fail()?,
+            ..Default::default()
+        };
+        state.grep.context_lines = ContextLines::MIN;
+        state.cursor.file = Some(PathBuf::from("a.rs"));
+
+        let render = |widget: &mut SearchResultWidget, state: &AppState| {
+            let mut canvas = Canvas::new(TerminalSize::rows_cols(6, 40));
+            widget.render(state, &mut canvas);
+            canvas
+                .into_frame()
+                .into_lines()
+                .map(|l| l.text())
+                .collect::<Vec<_>>()
+        };
+
+        let mut widget = SearchResultWidget::default();
+        let fresh = render(&mut widget, &state);
+        assert_eq!(render(&mut widget, &state), fresh);
+
+        // Cursor moves, scrolling and collapsing are reflected without a new view generation
+        // (and match fresh renderings).
+        state.cursor_down();
+        let moved = render(&mut widget, &state);
+        assert_ne!(moved, fresh);
+        assert_eq!(moved, render(&mut SearchResultWidget::default(), &state));
+        state.cursor_down();
+        state.cursor_down();
+        state.scroll_offset = Some(1);
+        assert_eq!(
+            render(&mut widget, &state),
+            render(&mut SearchResultWidget::default(), &state)
+        );
+        state.scroll_offset = None;
+        state.collapsed.insert(PathBuf::from("a.rs"));
+        assert_eq!(
+            render(&mut widget, &state),
+            render(&mut SearchResultWidget::default(), &state)
+        );
+        state.collapsed.clear();
+
+        // The rows of the unfocused lines are reused until the view generation changes.
+        state.settings.line_number_width = Some(3);
+        let cached = render(&mut widget, &state);
+        assert!(cached.contains(&"     [1] a".to_owned()));
+        state.view_generation += 1;
+        let updated = render(&mut widget, &state);
+        assert!(updated.contains(&"     [  1] a".to_owned()));
+        assert_eq!(updated, render(&mut SearchResultWidget::default(), &state));
+        Ok(())
+    }
+
+    #[test]
+    fn minimap_density() {
+        // Lines 1..=100 in 4 rows (i.e., 25 lines per row).
+        let hits = [1, 2, 3, 30, 99, 100, 100];
+        assert_eq!(minimap_buckets(hits.into_iter(), 1..=100, 4), [3, 1, 0, 3]);
+        // Hits out of the span are ignored.
+        assert_eq!(minimap_buckets([5, 20].into_iter(), 10..=19, 2), [0, 0]);
+        assert_eq!(minimap_buckets([7].into_iter(), 7..=7, 3), [1, 0, 0]);
+        assert!(minimap_buckets(hits.into_iter(), 1..=100, 0).is_empty());
+
+        let glyphs = [0, 1, 2, 3, 4, 8].map(|count| minimap_glyph(count, 8));
+        assert_eq!(glyphs, [' ', '░', '░', '▒', '▒', '█']);
+        assert_eq!(minimap_glyph(0, 0), ' ');
+    }
+
+    #[test]
+    fn dim_visited_lines() -> orfail::Result<()> {
+        let mut state = AppState {
+            search_result: SearchResult::parse("a.rs\n1:foo\n2:bar foo\n", Highlight::default())
+                .or_fail()?,
+            ..Default::default()
+        };
+        state.grep.context_lines = ContextLines::MIN;
+        state.cursor.file = Some(PathBuf::from("a.rs"));
+        state.cursor.line_number = NonZeroUsize::new(1);
+        state.mark_visited();
+        state.cursor.line_number = None;
+        assert_eq!(state.visited.len(), 1);
+
+        let style_of = |state: &AppState, text: &str| -> orfail::Result<TerminalStyle> {
+            let mut canvas = Canvas::new(TerminalSize::rows_cols(10, 80));
+            SearchResultWidget::default().render(state, &mut canvas);
+            let frame = canvas.into_frame();
+            let line = frame
+                .into_lines()
+                .find(|l| l.text().ends_with(text))
+                .or_fail()?;
+            let token = line.tokens().iter().find(|t| t.text() == text).or_fail()?;
+            Ok(token.style())
+        };
+        assert_eq!(style_of(&state, "foo")?, TerminalStyle::new());
+
+        state.settings.dim_visited = true;
+        assert_eq!(style_of(&state, "foo")?, TerminalStyle::new().dim());
+        assert_eq!(style_of(&state, "bar foo")?, TerminalStyle::new());
+
+        state.visited.clear();
+        assert_eq!(style_of(&state, "foo")?, TerminalStyle::new());
+        Ok(())
+    }
+}